@@ -2,8 +2,11 @@ use std::{thread::available_parallelism, time::Duration};
 
 use neatworks::{
     codec::{Decode, Encode},
-    model::search::{breadth_first, random_depth_first, Settings},
-    unreplicated::model::{ClientContextState, State},
+    model::search::{
+        breadth_first, depth_first, nested_depth_first, random_depth_first, Identity, Settings,
+        Uniform,
+    },
+    unreplicated::model::{Addr, ClientContextState, State},
     workload::{
         app::kvstore::{
             self, InfinitePutGet,
@@ -14,7 +17,7 @@ use neatworks::{
         Workload,
     },
 };
-use rand::thread_rng;
+use rand::{thread_rng, Rng as _};
 
 #[global_allocator]
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
@@ -46,16 +49,85 @@ fn main() -> anyhow::Result<()> {
                 .all(|(_, context): &(_, C<Iter<_, _>>)| context.upcall.workload.done)
         },
         prune: |_: &_| false,
+        canonicalize: Identity,
+        weight: Uniform,
         max_depth: None,
+        max_states: None,
+        progress: None,
     };
     let result = breadth_first(state.clone(), settings.clone(), 1.try_into().unwrap(), None)?;
     println!("{result:?}");
+    let result = depth_first(state.clone(), settings.clone(), None)?;
+    println!("{result:?}");
 
     let settings = Settings {
         invariant: settings.invariant,
         goal: |_: &_| false,
         prune: settings.goal,
+        canonicalize: settings.canonicalize,
+        weight: settings.weight,
+        max_depth: None,
+        max_states: None,
+        progress: None,
+    };
+    let result = breadth_first(state.clone(), settings.clone(), 1.try_into().unwrap(), None)?;
+    println!("{result:?}");
+    let progress = settings.prune;
+    let result = nested_depth_first(state, settings, progress, None)?;
+    println!("{result:?}");
+
+    println!("* Single client; replies delayed past resend timeout");
+    let mut state = State::with_network_delays([(Addr::Client(0), 2)]);
+    state.push_client(Iter::new([
+        (Put(String::from("foo"), String::from("bar")), PutOk),
+        (Get(String::from("foo")), GetResult(String::from("bar"))),
+    ]));
+    state.init()?;
+
+    let settings = Settings {
+        invariant: |_: &_| Ok(()),
+        goal: |state: &State<_>| {
+            state
+                .clients
+                .iter()
+                .all(|(_, context): &(_, C<Iter<_, _>>)| context.upcall.workload.done)
+        },
+        prune: |_: &_| false,
+        canonicalize: Identity,
+        weight: Uniform,
+        max_depth: None,
+        max_states: None,
+        progress: None,
+    };
+    let result = breadth_first(state, settings, 1.try_into().unwrap(), None)?;
+    println!("{result:?}");
+
+    println!("* Multi-client different keys; server batching");
+    let mut state = State::with_batching(Duration::from_millis(1));
+    for i in 0..2 {
+        state.push_client(Iter::new((0..2).map(move |x| {
+            (
+                Append(format!("KEY-{i}"), x.to_string()),
+                AppendResult((0..=x).map(|x| x.to_string()).collect::<Vec<_>>().concat()),
+            )
+        })))
+    }
+    state.init()?;
+
+    let settings = Settings {
+        invariant: |_: &_| Ok(()),
+        goal: |state: &State<_>| {
+            state
+                .clients
+                .iter()
+                .all(|(_, context): &(_, C<Iter<_, _>>)| context.upcall.workload.done)
+        },
+        prune: |_: &_| false,
+        canonicalize: Identity,
+        weight: Uniform,
         max_depth: None,
+        max_states: None,
+        progress: None,
     };
     let result = breadth_first(state, settings, 1.try_into().unwrap(), None)?;
     println!("{result:?}");
@@ -81,7 +153,11 @@ fn main() -> anyhow::Result<()> {
                 .all(|(_, context): &(_, C<Iter<_, _>>)| context.upcall.workload.done)
         },
         prune: |_: &_| false,
+        canonicalize: Identity,
+        weight: Uniform,
         max_depth: None,
+        max_states: None,
+        progress: None,
     };
     let result = breadth_first(state.clone(), settings.clone(), 1.try_into().unwrap(), None)?;
     println!("{result:?}");
@@ -90,7 +166,11 @@ fn main() -> anyhow::Result<()> {
         invariant: settings.invariant,
         goal: |_: &_| false,
         prune: settings.goal,
+        canonicalize: settings.canonicalize,
+        weight: settings.weight,
         max_depth: None,
+        max_states: None,
+        progress: None,
     };
     let result = breadth_first(state, settings, 1.try_into().unwrap(), None)?;
     println!("{result:?}");
@@ -144,7 +224,11 @@ fn main() -> anyhow::Result<()> {
                 })
         },
         prune: |_: &_| false,
+        canonicalize: Identity,
+        weight: Uniform,
         max_depth: None,
+        max_states: None,
+        progress: None,
     };
     let result = breadth_first(state.clone(), settings.clone(), 1.try_into().unwrap(), None)?;
     println!("{result:?}");
@@ -153,7 +237,11 @@ fn main() -> anyhow::Result<()> {
         invariant: settings.invariant,
         goal: |_: &_| false,
         prune: settings.goal,
+        canonicalize: settings.canonicalize,
+        weight: settings.weight,
         max_depth: None,
+        max_states: None,
+        progress: None,
     };
     let result = breadth_first(state, settings, 1.try_into().unwrap(), None)?;
     println!("{result:?}");
@@ -167,7 +255,11 @@ fn main() -> anyhow::Result<()> {
         invariant: |_: &_| Ok(()),
         goal: |_: &_| false,
         prune: |_: &_| false,
+        canonicalize: Identity,
+        weight: Uniform,
         max_depth: None,
+        max_states: None,
+        progress: None,
     };
     let result = breadth_first(
         state.clone(),
@@ -178,11 +270,12 @@ fn main() -> anyhow::Result<()> {
     )?;
     println!("{result:?}");
     settings.max_depth = Some(1000.try_into().unwrap());
-    let result = random_depth_first(
+    let (result, _) = random_depth_first(
         state,
         settings,
         available_parallelism()?,
         // 1.try_into().unwrap(),
+        thread_rng().gen(),
         Duration::from_secs(15),
     )?;
     println!("{result:?}");
@@ -1,19 +1,31 @@
+#[cfg(feature = "metrics")]
+use std::sync::{Arc, Mutex};
 use std::{
     env::args,
     time::{Duration, Instant},
 };
 
-use neatworks::{pbft::PublicParameters, workload::events::Invoke};
+use neatworks::{
+    pbft::PublicParameters,
+    workload::{events::Invoke, random_payloads},
+};
 use tokio::{select, time::sleep};
 use workload::util::run_until;
 
 pub mod workload {
     pub mod clients;
+    #[cfg(feature = "metrics")]
+    pub mod metrics;
     pub mod servers;
     pub mod util;
 }
 
-struct InvokeTask;
+// issue ops of `payload_size` random bytes, which the servers' `NoopApp` echoes
+struct InvokeTask {
+    payload_size: usize,
+    #[cfg(feature = "metrics")]
+    snapshot: Arc<Mutex<workload::metrics::Snapshot>>,
+}
 
 impl workload::clients::InvokeTask for InvokeTask {
     async fn run(
@@ -24,12 +36,22 @@ impl workload::clients::InvokeTask for InvokeTask {
         >,
     ) -> anyhow::Result<()> {
         sleep(Duration::from_millis(100)).await;
-        for _ in 0..10 {
+        let payloads = random_payloads(self.payload_size, &mut rand::thread_rng())?;
+        for op in payloads.take(10) {
             let start = Instant::now();
-            sender.send(Invoke(Default::default()))?;
+            sender.send(Invoke(op))?;
             let recv = receiver.recv().await;
             anyhow::ensure!(recv.is_some());
-            println!("{:?}", start.elapsed())
+            let latency = start.elapsed();
+            #[cfg(feature = "metrics")]
+            self.snapshot
+                .lock()
+                .unwrap()
+                .latencies
+                .entry("echo".into())
+                .or_default()
+                .push(latency);
+            println!("{latency:?}")
         }
         anyhow::Ok(())
     }
@@ -38,24 +60,45 @@ impl workload::clients::InvokeTask for InvokeTask {
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     let mode = args().nth(1);
+    let invoke_task = InvokeTask {
+        payload_size: args()
+            .nth(2)
+            .map(|size| size.parse())
+            .transpose()?
+            .unwrap_or(0),
+        #[cfg(feature = "metrics")]
+        snapshot: Default::default(),
+    };
+    // scraped on the address given as the third argument, if any
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = args().nth(3) {
+        let addr = addr.parse()?;
+        let snapshot = invoke_task.snapshot.clone();
+        let start = Instant::now();
+        tokio::spawn(async move {
+            let snapshot = move || workload::metrics::Snapshot {
+                elapsed: start.elapsed(),
+                ..snapshot.lock().unwrap().clone()
+            };
+            if let Err(err) = workload::metrics::serve(addr, snapshot).await {
+                eprintln!("metrics: {err}")
+            }
+        });
+    }
     match mode.as_deref().unwrap_or("unreplicated") {
         "unreplicated" => {
             let server_task = workload::servers::unreplicated();
-            let client_task = workload::clients::unreplicated(InvokeTask);
+            let client_task = workload::clients::unreplicated(invoke_task);
             run_until(client_task, server_task).await
         }
         "pbft" => {
-            let config = PublicParameters {
-                num_replica: 4,
-                num_faulty: 1,
-                num_concurrent: 1,
-                max_batch_size: 1,
-                ..PublicParameters::durations(if cfg!(debug_assertions) {
+            let config = PublicParameters::builder(4, 1)
+                .with_durations(if cfg!(debug_assertions) {
                     Duration::from_millis(300)
                 } else {
                     Duration::from_millis(100)
                 })
-            };
+                .build()?;
             let addrs = (0..4)
                 .map(|index| ([127, 0, 0, 1 + index], 3000).into())
                 .collect::<Vec<_>>();
@@ -63,7 +106,7 @@ async fn main() -> anyhow::Result<()> {
             let server_task1 = workload::servers::pbft(config.clone(), 1, addrs.clone());
             let server_task2 = workload::servers::pbft(config.clone(), 2, addrs.clone());
             let server_task3 = workload::servers::pbft(config.clone(), 3, addrs.clone());
-            let client_task = workload::clients::pbft(InvokeTask, config, addrs);
+            let client_task = workload::clients::pbft(invoke_task, config, addrs);
             run_until(client_task, async {
                 select! {
                     result = server_task0 => result,
@@ -1,5 +1,7 @@
 pub mod workload {
     pub mod clients;
+    #[cfg(feature = "metrics")]
+    pub mod metrics;
     pub mod servers;
     pub mod util;
 }
@@ -139,7 +139,7 @@ pub async fn pbft(
         schedule: Erase::new(ScheduleState::new(schedule_sender)),
     };
     let client_task = run_with_schedule(
-        Untyped::new(pbft::client::State::new(random(), addr, config)),
+        Untyped::new(pbft::client::State::new(random(), addr, config)?),
         &mut context,
         &mut receiver,
         &mut schedule_receiver,
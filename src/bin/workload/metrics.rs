@@ -0,0 +1,103 @@
+use std::{collections::BTreeMap, fmt::Write as _, net::SocketAddr, time::Duration};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{header::CONTENT_TYPE, server::conn::http1, service::service_fn, Response};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+// upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 13] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.,
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub elapsed: Duration,
+    // op type => latencies of the completed ops of the type
+    pub latencies: BTreeMap<String, Vec<Duration>>,
+}
+
+// Prometheus text exposition format
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut text = String::new();
+    writeln!(&mut text, "# TYPE neatworks_op_latency_seconds histogram").unwrap();
+    for (op, latencies) in &snapshot.latencies {
+        for bucket in BUCKETS {
+            let count = latencies
+                .iter()
+                .filter(|latency| latency.as_secs_f64() <= bucket)
+                .count();
+            writeln!(
+                &mut text,
+                "neatworks_op_latency_seconds_bucket{{op=\"{op}\",le=\"{bucket}\"}} {count}"
+            )
+            .unwrap()
+        }
+        let count = latencies.len();
+        let sum = latencies.iter().sum::<Duration>().as_secs_f64();
+        writeln!(
+            &mut text,
+            "neatworks_op_latency_seconds_bucket{{op=\"{op}\",le=\"+Inf\"}} {count}"
+        )
+        .unwrap();
+        writeln!(
+            &mut text,
+            "neatworks_op_latency_seconds_sum{{op=\"{op}\"}} {sum}"
+        )
+        .unwrap();
+        writeln!(
+            &mut text,
+            "neatworks_op_latency_seconds_count{{op=\"{op}\"}} {count}"
+        )
+        .unwrap()
+    }
+    let count = snapshot.latencies.values().map(Vec::len).sum::<usize>();
+    writeln!(
+        &mut text,
+        "# TYPE neatworks_throughput_ops_per_second gauge"
+    )
+    .unwrap();
+    writeln!(
+        &mut text,
+        "neatworks_throughput_ops_per_second {}",
+        count as f64 / snapshot.elapsed.as_secs_f64().max(f64::EPSILON)
+    )
+    .unwrap();
+    text
+}
+
+// a scraper that connects but does not send the request in time is disconnected
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// answer every HTTP request on `addr` with the rendered snapshot, regardless of the method and path
+pub async fn serve(
+    addr: SocketAddr,
+    snapshot: impl Fn() -> Snapshot + Clone + Send + 'static,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, remote) = listener.accept().await?;
+        let snapshot = snapshot.clone();
+        let service = service_fn(move |_| {
+            let body = render(&snapshot());
+            async move {
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(Full::new(Bytes::from(body)))
+            }
+        });
+        // a task per connection, so a slow scraper does not hold back the others
+        tokio::spawn(async move {
+            let result = http1::Builder::new()
+                .timer(TokioTimer::new())
+                .header_read_timeout(READ_TIMEOUT)
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+            if let Err(err) = result {
+                warn!("metrics connection from {remote}: {err}")
+            }
+        });
+    }
+}
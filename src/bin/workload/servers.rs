@@ -4,32 +4,50 @@ use neatworks::{
     codec::Encode,
     crypto::{Crypto, CryptoFlavor},
     event::{
-        task::{self, run, run_with_schedule, run_worker, ScheduleState},
+        task::{self, run_with_schedule, run_worker, ScheduleState},
         Erase, Untyped,
     },
-    net::{combinators::IndexNet, task::udp},
+    net::{
+        combinators::{demux_shards, IndexNet, ShardedNet},
+        task::udp,
+    },
     pbft, unreplicated,
-    workload::Null,
+    workload::NoopApp,
 };
-use tokio::{net::UdpSocket, select, sync::mpsc::unbounded_channel};
+use tokio::{net::UdpSocket, select, sync::mpsc::unbounded_channel, task::JoinSet};
 
 pub async fn unreplicated() -> anyhow::Result<()> {
     let socket = Arc::new(UdpSocket::bind("localhost:3000").await?);
+    let (schedule_sender, mut schedule_receiver) = unbounded_channel();
     let (sender, mut receiver) = unbounded_channel();
 
+    type S = unreplicated::ServerState<NoopApp, SocketAddr>;
     type Net = Encode<unreplicated::Reply, Arc<UdpSocket>>;
-    struct Context(Net);
+    type Schedule = task::erase::ScheduleState<S, Context>;
+    struct Context {
+        net: Net,
+        schedule: Schedule,
+    }
     impl unreplicated::ServerContext<SocketAddr> for Context {
         type Net = Net;
+        type Schedule = Schedule;
         fn net(&mut self) -> &mut Self::Net {
-            &mut self.0
+            &mut self.net
+        }
+        fn schedule(&mut self) -> &mut Self::Schedule {
+            &mut self.schedule
         }
     }
-    let mut context = Context(unreplicated::codec::server_encode(socket.clone()));
-    let server_task = run(
-        Untyped::new(unreplicated::ServerState::new(Null)),
+    let mut context = Context {
+        net: unreplicated::codec::server_encode(socket.clone()),
+        schedule: Erase::new(ScheduleState::new(schedule_sender)),
+    };
+    let server_task = run_with_schedule(
+        Untyped::new(unreplicated::ServerState::new(NoopApp::default(), None)),
         &mut context,
         &mut receiver,
+        &mut schedule_receiver,
+        |context| &mut context.schedule,
     );
     let net_task = udp::run(
         &socket,
@@ -54,7 +72,7 @@ pub async fn pbft(
     let (schedule_sender, mut schedule_receiver) = unbounded_channel();
     let (sender, mut receiver) = unbounded_channel();
 
-    type S = pbft::replica::State<Null, SocketAddr>;
+    type S = pbft::replica::State<NoopApp, SocketAddr>;
     type PeerNet =
         Encode<pbft::messages::codec::ToReplica<SocketAddr>, IndexNet<SocketAddr, Arc<UdpSocket>>>;
     type DownlinkNet = Encode<pbft::messages::codec::ToClient, Arc<UdpSocket>>;
@@ -97,7 +115,11 @@ pub async fn pbft(
         schedule: Erase::new(ScheduleState::new(schedule_sender)),
     };
     let server_task = run_with_schedule(
-        Untyped::new(pbft::replica::State::new(index as _, Null, config.clone())),
+        Untyped::new(pbft::replica::State::new(
+            index as _,
+            NoopApp::default(),
+            config.clone(),
+        )?),
         &mut context,
         &mut receiver,
         &mut schedule_receiver,
@@ -120,3 +142,100 @@ pub async fn pbft(
     }
     anyhow::bail!("unexpected termination of infinite task")
 }
+
+// `num_shard` independent PBFT groups in one process, where the replicas of the same index share
+// the socket at `addrs[index]`, and each group has its own crypto worker. the replicas address each
+// other through `ShardedNet`, so the clients of a group should send through `ShardedNet` with the
+// same shard id as well
+pub async fn pbft_sharded(
+    config: pbft::PublicParameters,
+    index: usize,
+    addrs: Vec<SocketAddr>,
+    num_shard: u8,
+) -> anyhow::Result<()> {
+    let socket = Arc::new(UdpSocket::bind(addrs[index]).await?);
+
+    type S = pbft::replica::State<NoopApp, SocketAddr>;
+    type PeerNet = Encode<
+        pbft::messages::codec::ToReplica<SocketAddr>,
+        IndexNet<SocketAddr, ShardedNet<Arc<UdpSocket>>>,
+    >;
+    type DownlinkNet = Encode<pbft::messages::codec::ToClient, Arc<UdpSocket>>;
+    type CryptoWorker = task::work::Sender<Crypto, CryptoContext>;
+    type CryptoContext = task::erase::Sender<S, Context>;
+    type Schedule = task::erase::ScheduleState<S, Context>;
+    struct Context {
+        peer_net: PeerNet,
+        downlink_net: DownlinkNet,
+        crypto_worker: CryptoWorker,
+        schedule: Schedule,
+    }
+    impl pbft::replica::Context<S, SocketAddr> for Context {
+        type PeerNet = PeerNet;
+        type DownlinkNet = DownlinkNet;
+        type CryptoWorker = CryptoWorker;
+        type CryptoContext = CryptoContext;
+        type Schedule = Schedule;
+        fn peer_net(&mut self) -> &mut Self::PeerNet {
+            &mut self.peer_net
+        }
+        fn downlink_net(&mut self) -> &mut Self::DownlinkNet {
+            &mut self.downlink_net
+        }
+        fn crypto_worker(&mut self) -> &mut Self::CryptoWorker {
+            &mut self.crypto_worker
+        }
+        fn schedule(&mut self) -> &mut Self::Schedule {
+            &mut self.schedule
+        }
+    }
+
+    let mut shard_tasks = JoinSet::new();
+    let mut decoders = Vec::new();
+    for shard in 0..num_shard {
+        let (crypto_sender, mut crypto_receiver) = unbounded_channel();
+        let (schedule_sender, mut schedule_receiver) = unbounded_channel();
+        let (sender, mut receiver) = unbounded_channel();
+        decoders.push(pbft::messages::codec::to_replica_decode(Erase::new(
+            sender.clone(),
+        )));
+        let mut context = Context {
+            peer_net: pbft::messages::codec::to_replica_encode(IndexNet::new(
+                addrs.clone(),
+                index,
+                ShardedNet::new(socket.clone(), shard),
+            )),
+            downlink_net: pbft::messages::codec::to_client_encode(socket.clone()),
+            crypto_worker: crypto_sender,
+            schedule: Erase::new(ScheduleState::new(schedule_sender)),
+        };
+        let state = Untyped::new(pbft::replica::State::new(
+            index as _,
+            NoopApp::default(),
+            config.clone(),
+        )?);
+        let crypto = Crypto::new_hardcoded(config.num_replica, index, CryptoFlavor::Schnorrkel)?;
+        shard_tasks.spawn(async move {
+            let server_task = run_with_schedule(
+                state,
+                &mut context,
+                &mut receiver,
+                &mut schedule_receiver,
+                |context| &mut context.schedule,
+            );
+            let crypto_task = run_worker(crypto, Erase::new(sender), &mut crypto_receiver);
+            select! {
+                result = server_task => result?,
+                result = crypto_task => result?,
+            }
+            anyhow::bail!("unexpected termination of infinite task")
+        });
+    }
+    let net_task = udp::run(&socket, demux_shards(decoders));
+
+    select! {
+        result = net_task => result?,
+        Some(result) = shard_tasks.join_next() => result??,
+    }
+    anyhow::bail!("unexpected termination of infinite task")
+}
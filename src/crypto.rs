@@ -1,4 +1,7 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    collections::BTreeSet,
+    hash::{Hash, Hasher},
+};
 
 use blake2::Blake2b;
 use derive_more::Deref;
@@ -20,7 +23,27 @@ use sha2::{Digest, Sha256};
 // guarantees the desired reproducibility, and the main problem is the lack of
 // cross-platform compatibility, which is hardly concerned in this codebase
 // since it is written for benchmarks performed on unified systems and machines.
-// nevertheless, I manually addressed the endianness problem below
+// nevertheless, I manually addressed the endianness problem below, and the
+// pointer-sized integers are always written as 64-bit, so the digests are the
+// same on 32-bit platforms
+//
+// about ambiguity: the `Hash` implementations of the standard types are prefix
+// free, i.e. `Vec` and slices are prefixed with their length (through
+// `write_usize`), `str` is terminated with 0xff that never appears in UTF-8,
+// and `Option` and other enums write the discriminant (through `write_isize`)
+// before the payload. so `(vec![1], vec![2, 3])` and `(vec![1, 2], vec![3])`,
+// or `Some(0u32)` and `None` followed by `0u32`, do not collide. the derived
+// `Hash` of structs simply concatenates the fields, which is fine as long as
+// every field is prefix free by itself
+// prefix freedom is part of the documented contract of `Hash` (see "Prefix
+// collisions" in its docs), so there's no need to override the traversal to
+// prefix lengths again. a manual `Hash` impl should stick to the contract, e.g.
+// hash a `&[u8]` instead of writing the bytes directly
+// the type is not digested though, so values of different types may collide,
+// e.g. an empty `Vec` and a `None`, or two message types with the same fields.
+// a message type should tag itself if its layout is shared with another one
+// that is signed by the same keys
+// the golden digests in the `pbft::messages` tests guard the layout
 
 pub trait DigestHasher {
     fn write(&mut self, bytes: &[u8]);
@@ -63,10 +86,14 @@ impl<T: DigestHasher> Hasher for ImplHasher<'_, T> {
         self.0.write(&i.to_le_bytes())
     }
 
-    fn write_usize(&mut self, i: usize) {
+    fn write_u128(&mut self, i: u128) {
         self.0.write(&i.to_le_bytes())
     }
 
+    fn write_usize(&mut self, i: usize) {
+        self.0.write(&(i as u64).to_le_bytes())
+    }
+
     fn write_i16(&mut self, i: i16) {
         self.0.write(&i.to_le_bytes())
     }
@@ -79,10 +106,14 @@ impl<T: DigestHasher> Hasher for ImplHasher<'_, T> {
         self.0.write(&i.to_le_bytes())
     }
 
-    fn write_isize(&mut self, i: isize) {
+    fn write_i128(&mut self, i: i128) {
         self.0.write(&i.to_le_bytes())
     }
 
+    fn write_isize(&mut self, i: isize) {
+        self.0.write(&(i as i64).to_le_bytes())
+    }
+
     fn finish(&self) -> u64 {
         unimplemented!()
     }
@@ -122,24 +153,61 @@ impl<M, S> Verifiable<M, S> {
     }
 }
 
+// rewrite the message while keeping the signature, which is only valid for the plain signatures that
+// do not depend on the message, i.e. in model checking
+#[cfg(test)]
+impl<M, S> Verifiable<M, S> {
+    pub fn map<N>(self, f: impl FnOnce(M) -> N) -> Verifiable<N, S> {
+        Verifiable {
+            inner: f(self.inner),
+            signature: self.signature,
+        }
+    }
+}
+
 pub mod events {
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
     pub struct Signed<M, S = super::Signature>(pub super::Verifiable<M, S>);
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
     pub struct Verified<M, S = super::Signature>(pub super::Verifiable<M, S>);
 }
 
 // the cryptographic library must support seedable RNG based key pair generation
 // to be used in this codebase
-// it would be better if the library supports prehashed message as well, but a
-// fallback `impl DigestHasher for Vec<u8>` is provided above anyway
+// it would be better if the library supports prehashed message as well, so the
+// `*_prehashed` interfaces can skip the `DigestHash` step. a fallback
+// `impl DigestHasher for Vec<u8>` is provided above for the ones that don't
+
+// a hasher that has been finalized in advance, for feeding a prehashed digest into the interfaces
+// that expect a hasher e.g. `SigningContext::hash256`
+struct Prehashed(H256);
+
+impl sha2::digest::OutputSizeUser for Prehashed {
+    type OutputSize = sha2::digest::consts::U32;
+}
+
+// required by `FixedOutput`, while `SigningContext::hash256` only finalizes the hasher, and the type
+// is not exposed to be fed by anyone else
+impl sha2::digest::Update for Prehashed {
+    fn update(&mut self, _: &[u8]) {
+        unreachable!("Prehashed is only finalized")
+    }
+}
+
+impl sha2::digest::FixedOutput for Prehashed {
+    fn finalize_into(self, out: &mut sha2::digest::Output<Self>) {
+        out.copy_from_slice(self.0.as_bytes())
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Signature {
-    Plain(String), // for testing
+    Plain(String),      // for testing
+    Model(usize, H256), // (signer index, digest)
     Secp256k1(secp256k1::ecdsa::Signature),
     Schnorrkel(SchnorrkelSignature),
+    Bls(BlsSignature),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -163,6 +231,38 @@ impl Hash for SchnorrkelSignature {
     }
 }
 
+// boxed as the point is kept uncompressed, which would otherwise grow every `Signature` to 192 bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlsSignature(pub Box<blst::min_pk::Signature>);
+
+impl Ord for BlsSignature {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.to_bytes().cmp(&other.0.to_bytes())
+    }
+}
+
+impl PartialOrd for BlsSignature {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for BlsSignature {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Hash::hash(&self.0.to_bytes(), state)
+    }
+}
+
+// the signature part of a quorum certificate that is compacted into one
+// `Verifiable<M, AggregateSignature>`, instead of a map of `Verifiable<M>`s
+// the signers set is carried along to assemble the group public key on
+// verification side
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct AggregateSignature {
+    pub signers: BTreeSet<usize>,
+    pub signature: BlsSignature,
+}
+
 #[derive(Debug, Clone)]
 pub struct Crypto {
     provider: CryptoProvider,
@@ -172,8 +272,10 @@ pub struct Crypto {
 #[derive(Debug, Clone)]
 enum CryptoProvider {
     Insecure(String), // the "signature"
+    Model(usize),     // the index
     Secp256k1(Secp256k1Crypto),
     Schnorrkel(Box<SchnorrkelCrypto>),
+    Bls(Box<blst::min_pk::SecretKey>),
 }
 
 #[derive(Debug, Clone)]
@@ -190,20 +292,96 @@ pub struct SchnorrkelCrypto {
     pub context: schnorrkel::context::SigningContext,
 }
 
+// `UnknownSigner`, `FlavorMismatch` and `UnsupportedFlavor` are on the local setup, i.e. the public
+// key set or the configured flavor, `LengthMismatch` is on the caller of a batch verification,
+// while `BadSignature` is on the signed message, which is forged or corrupted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    UnknownSigner(usize),
+    FlavorMismatch,
+    UnsupportedFlavor, // the operation is not available for the flavor, e.g. aggregating without `Bls`
+    LengthMismatch(usize, usize), // (indexes, signed messages)
+    BadSignature,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownSigner(index) => write!(f, "missing identifier for index {index}"),
+            Self::FlavorMismatch => write!(f, "mismatched crypto flavor"),
+            Self::UnsupportedFlavor => write!(f, "unsupported crypto flavor"),
+            Self::LengthMismatch(indexes, signed) => {
+                write!(f, "{indexes} indexes for {signed} signed messages")
+            }
+            Self::BadSignature => write!(f, "bad signature"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
 #[derive(Debug, Clone)]
 enum PublicKey {
     Plain(String),
+    Model(usize),
     Secp256k1(secp256k1::PublicKey),
     Schnorrkel(peer::PublicKey),
+    Bls(blst::min_pk::PublicKey),
+}
+
+impl PublicKey {
+    // compressed forms for the curve points
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Plain(signature) => signature.as_bytes().to_vec(),
+            Self::Model(index) => (*index as u64).to_le_bytes().to_vec(),
+            Self::Secp256k1(public_key) => public_key.serialize().to_vec(),
+            Self::Schnorrkel(public_key) => public_key.to_bytes().to_vec(),
+            Self::Bls(public_key) => public_key.compress().to_vec(),
+        }
+    }
+
+    fn from_bytes(flavor: CryptoFlavor, bytes: &[u8]) -> anyhow::Result<Self> {
+        let public_key = match flavor {
+            CryptoFlavor::Plain => Self::Plain(String::from_utf8(bytes.to_vec())?),
+            CryptoFlavor::ModelDeterministic => {
+                Self::Model(u64::from_le_bytes(bytes.try_into()?) as _)
+            }
+            CryptoFlavor::Secp256k1 => Self::Secp256k1(secp256k1::PublicKey::from_slice(bytes)?),
+            CryptoFlavor::Schnorrkel => Self::Schnorrkel(
+                schnorrkel::PublicKey::from_bytes(bytes).map_err(anyhow::Error::msg)?,
+            ),
+            CryptoFlavor::Bls => {
+                // the keys come from the outside now, so do the group checks that the hardcoded
+                // keys skip
+                let public_key = blst::min_pk::PublicKey::key_validate(bytes)
+                    .map_err(|err| anyhow::format_err!("{err:?}"))?;
+                Self::Bls(public_key)
+            }
+        };
+        Ok(public_key)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum CryptoFlavor {
     Plain,
+    // for model checking as well, but the "signature" is the signer's index along with the message
+    // digest, so unlike `Plain` it is bound to the message, and a signed message cannot be
+    // rewritten (e.g. with `Verifiable::map`) or claimed by another signer without failing the
+    // verification. no secret is involved, so the protection is only against the replicas that
+    // are modeled to sign through their own `Crypto`
+    ModelDeterministic,
     Secp256k1,
     Schnorrkel,
+    Bls,
 }
 
+// the ciphersuite of the (minimal-pubkey-size) proof of possession scheme
+// the public keys are hardcoded/trusted for now so rogue key attack is not concerned, but keep the
+// standard one to not surprise anyone
+const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
 impl Crypto {
     pub fn new_hardcoded(
         n: usize,
@@ -213,7 +391,7 @@ impl Crypto {
         let secret_keys = (0..n).map(|id| {
             let mut k = [0; 32];
             let k1 = format!("replica-{id}");
-            k[..k1.as_bytes().len()].copy_from_slice(k1.as_bytes());
+            k[..k1.len()].copy_from_slice(k1.as_bytes());
             k
         });
         let crypto = match flavor {
@@ -223,6 +401,10 @@ impl Crypto {
                     .collect(),
                 provider: CryptoProvider::Insecure(format!("replica-{:03}", index.into())),
             },
+            CryptoFlavor::ModelDeterministic => Self {
+                public_keys: (0..n).map(PublicKey::Model).collect(),
+                provider: CryptoProvider::Model(index.into()),
+            },
             CryptoFlavor::Secp256k1 => {
                 let secret_keys = secret_keys
                     .map(|k| secp256k1::SecretKey::from_slice(&k))
@@ -258,29 +440,140 @@ impl Crypto {
                     })),
                 }
             }
+            CryptoFlavor::Bls => {
+                let mut secret_keys = secret_keys
+                    .map(|k| blst::min_pk::SecretKey::key_gen(&k, &[]))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| anyhow::format_err!("{err:?}"))?;
+                Self {
+                    public_keys: secret_keys
+                        .iter()
+                        .map(|secret_key| PublicKey::Bls(secret_key.sk_to_pk()))
+                        .collect(),
+                    provider: CryptoProvider::Bls(Box::new(secret_keys.remove(index.into()))),
+                }
+            }
         };
         Ok(crypto)
     }
 
+    // the stable encoding of the configured public keys, in index order, for transmitting them to
+    // the nodes that learn the peer keys at runtime
+    pub fn public_keys_encoded(&self) -> Vec<Vec<u8>> {
+        self.public_keys.iter().map(PublicKey::to_bytes).collect()
+    }
+
+    // the counterpart of `public_keys_encoded` on the receiving side
+    // `secret` is the key material `new_hardcoded` works with, i.e. the "signature" string for
+    // `Plain`, the secret key for `Secp256k1`, the mini secret key for `Schnorrkel` and the key
+    // generation input for `Bls`. it must match the public key of `index` in `public_set`
+    pub fn from_encoded(
+        index: impl Into<usize>,
+        secret: &[u8],
+        public_set: &[Vec<u8>],
+        flavor: CryptoFlavor,
+    ) -> anyhow::Result<Self> {
+        let public_keys = public_set
+            .iter()
+            .map(|bytes| PublicKey::from_bytes(flavor, bytes))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (provider, public_key) = match flavor {
+            CryptoFlavor::Plain => {
+                let signature = String::from_utf8(secret.to_vec())?;
+                (
+                    CryptoProvider::Insecure(signature.clone()),
+                    PublicKey::Plain(signature),
+                )
+            }
+            CryptoFlavor::ModelDeterministic => {
+                let PublicKey::Model(index) = PublicKey::from_bytes(flavor, secret)? else {
+                    unreachable!()
+                };
+                (CryptoProvider::Model(index), PublicKey::Model(index))
+            }
+            CryptoFlavor::Secp256k1 => {
+                let secret_key = secp256k1::SecretKey::from_slice(secret)?;
+                let secp = secp256k1::Secp256k1::new();
+                let public_key = PublicKey::Secp256k1(secret_key.public_key(&secp));
+                (
+                    CryptoProvider::Secp256k1(Secp256k1Crypto { secret_key, secp }),
+                    public_key,
+                )
+            }
+            CryptoFlavor::Schnorrkel => {
+                let keypair = schnorrkel::MiniSecretKey::from_bytes(secret)
+                    .map_err(anyhow::Error::msg)?
+                    .expand_to_keypair(schnorrkel::ExpansionMode::Uniform);
+                let public_key = PublicKey::Schnorrkel(keypair.public);
+                (
+                    CryptoProvider::Schnorrkel(Box::new(SchnorrkelCrypto {
+                        keypair,
+                        context: schnorrkel::signing_context(b"default"),
+                    })),
+                    public_key,
+                )
+            }
+            CryptoFlavor::Bls => {
+                let secret_key = blst::min_pk::SecretKey::key_gen(secret, &[])
+                    .map_err(|err| anyhow::format_err!("{err:?}"))?;
+                let public_key = PublicKey::Bls(secret_key.sk_to_pk());
+                (CryptoProvider::Bls(Box::new(secret_key)), public_key)
+            }
+        };
+        let index = index.into();
+        let Some(expected) = public_keys.get(index) else {
+            anyhow::bail!("missing identifier for index {index}")
+        };
+        anyhow::ensure!(
+            public_key.to_bytes() == expected.to_bytes(),
+            "secret does not match the public key of index {index}"
+        );
+        Ok(Self {
+            provider,
+            public_keys,
+        })
+    }
+
     pub fn sign<M: DigestHash>(&self, message: M) -> Verifiable<M> {
+        let signature = match &self.provider {
+            // skip digesting for the insecure one, which is mostly used in model checking where
+            // the hashing cost is significant
+            CryptoProvider::Insecure(signature) => Signature::Plain(signature.clone()),
+            _ => self.sign_prehashed(message.sha256()),
+        };
+        Verifiable {
+            inner: message,
+            signature,
+        }
+    }
+
+    // sign multiple messages in one call, e.g. the Commits of the ops that are prepared together
+    // none of the current flavors has a signing algorithm that is cheaper in batch, so this only
+    // saves the per-call dispatching for now
+    pub fn sign_batch<M: DigestHash>(&self, messages: Vec<M>) -> Vec<Verifiable<M>> {
+        messages
+            .into_iter()
+            .map(|message| self.sign(message))
+            .collect()
+    }
+
+    // for the callers that already have the digest of the message at hand
+    // `sign_prehashed(message.sha256())` produces the same signature as `sign(message)`, and
+    // the two `verify`s are interchangeable in the same way
+    pub fn sign_prehashed(&self, digest: H256) -> Signature {
         match &self.provider {
-            CryptoProvider::Insecure(signature) => Verifiable {
-                inner: message,
-                signature: Signature::Plain(signature.clone()),
-            },
+            CryptoProvider::Insecure(signature) => Signature::Plain(signature.clone()),
+            CryptoProvider::Model(index) => Signature::Model(*index, digest),
             CryptoProvider::Secp256k1(crypto) => {
-                let digest = secp256k1::Message::from_digest(message.sha256().into());
-                Verifiable {
-                    inner: message,
-                    signature: Signature::Secp256k1(
-                        crypto.secp.sign_ecdsa(&digest, &crypto.secret_key),
-                    ),
-                }
+                let digest = secp256k1::Message::from_digest(digest.into());
+                Signature::Secp256k1(crypto.secp.sign_ecdsa(&digest, &crypto.secret_key))
             }
-            CryptoProvider::Schnorrkel(crypto) => Verifiable {
-                signature: Signature::Schnorrkel(crypto.sign(&message)),
-                inner: message,
-            },
+            CryptoProvider::Schnorrkel(crypto) => {
+                Signature::Schnorrkel(crypto.sign_prehashed(digest))
+            }
+            CryptoProvider::Bls(secret_key) => Signature::Bls(BlsSignature(Box::new(
+                secret_key.sign(digest.as_bytes(), BLS_DST, &[]),
+            ))),
         }
     }
 
@@ -288,56 +581,207 @@ impl Crypto {
         &self,
         index: impl Into<usize>,
         signed: &Verifiable<M>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), CryptoError> {
+        let digest = match &self.provider {
+            // same as above, the insecure "signature" does not depend on the digest
+            CryptoProvider::Insecure(_) => Default::default(),
+            _ => signed.inner.sha256(),
+        };
+        self.verify_prehashed(index, digest, &signed.signature)
+    }
+
+    pub fn verify_prehashed(
+        &self,
+        index: impl Into<usize>,
+        digest: H256,
+        signature: &Signature,
+    ) -> Result<(), CryptoError> {
         let index = index.into();
         let Some(public_key) = self.public_keys.get(index) else {
-            anyhow::bail!("missing identifier for index {}", index)
+            return Err(CryptoError::UnknownSigner(index));
         };
-        match (&self.provider, public_key, &signed.signature) {
+        let valid = match (&self.provider, public_key, signature) {
             (
                 CryptoProvider::Insecure(_),
                 PublicKey::Plain(expected_signature),
                 Signature::Plain(signature),
-            ) => anyhow::ensure!(signature == expected_signature),
+            ) => signature == expected_signature,
+            (
+                CryptoProvider::Model(_),
+                PublicKey::Model(expected_index),
+                Signature::Model(signer, signed_digest),
+            ) => signer == expected_index && *signed_digest == digest,
 
             (
                 CryptoProvider::Secp256k1(crypto),
                 PublicKey::Secp256k1(public_key),
                 Signature::Secp256k1(signature),
             ) => {
-                let digest = secp256k1::Message::from_digest(signed.inner.sha256().into());
-                crypto.secp.verify_ecdsa(&digest, signature, public_key)?
+                let digest = secp256k1::Message::from_digest(digest.into());
+                crypto
+                    .secp
+                    .verify_ecdsa(&digest, signature, public_key)
+                    .is_ok()
+            }
+            (
+                CryptoProvider::Schnorrkel(crypto),
+                PublicKey::Schnorrkel(public_key),
+                Signature::Schnorrkel(signature),
+            ) => crypto
+                .verify_prehashed(public_key, digest, signature)
+                .is_ok(),
+            (
+                CryptoProvider::Bls(_),
+                PublicKey::Bls(public_key),
+                Signature::Bls(BlsSignature(signature)),
+            ) => {
+                signature.verify(true, digest.as_bytes(), BLS_DST, &[], public_key, false)
+                    == blst::BLST_ERROR::BLST_SUCCESS
             }
-            (CryptoProvider::Schnorrkel(crypto), PublicKey::Schnorrkel(public_key), _) => crypto
-                .verify(public_key, signed, |signature| match signature {
-                    Signature::Schnorrkel(signature) => Ok(signature),
-                    _ => anyhow::bail!("unimplemented"),
-                })?,
-            _ => anyhow::bail!("unimplemented"),
+            _ => return Err(CryptoError::FlavorMismatch),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(CryptoError::BadSignature)
         }
-        Ok(())
     }
 
+    // a failed batch does not tell which signatures are bad, see `verify_batch_or_each`
+    // the flavors other than `Schnorrkel` have no batch verification and fall back to verify one by
+    // one
     pub fn verify_batch<I: Clone + Into<usize>, M: DigestHash>(
         &self,
         indexes: &[I],
         signed: &[Verifiable<M>],
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), CryptoError> {
+        if indexes.len() != signed.len() {
+            return Err(CryptoError::LengthMismatch(indexes.len(), signed.len()));
+        }
         let CryptoProvider::Schnorrkel(crypto) = &self.provider else {
-            anyhow::bail!("unimplemented") // TODO fallback to verify one by one?
+            for (index, signed) in indexes.iter().zip(signed) {
+                self.verify(index.clone(), signed)?
+            }
+            return Ok(());
         };
         let public_keys = indexes
             .iter()
-            .map(|i| match &self.public_keys[i.clone().into()] {
-                PublicKey::Schnorrkel(key) => Ok(*key),
-                _ => anyhow::bail!("unimplemented"),
+            .map(|i| {
+                let i = i.clone().into();
+                match self.public_keys.get(i) {
+                    Some(PublicKey::Schnorrkel(key)) => Ok(*key),
+                    Some(_) => Err(CryptoError::FlavorMismatch),
+                    None => Err(CryptoError::UnknownSigner(i)),
+                }
             })
-            .collect::<anyhow::Result<Vec<_>>>()?;
-        crypto.verify_batch(&public_keys, signed, |signature| match signature {
-            Signature::Schnorrkel(signature) => Ok(signature),
-            _ => anyhow::bail!("unimplemented"),
+            .collect::<Result<Vec<_>, _>>()?;
+        if signed
+            .iter()
+            .any(|signed| !matches!(signed.signature, Signature::Schnorrkel(_)))
+        {
+            return Err(CryptoError::FlavorMismatch);
+        }
+        crypto
+            .verify_batch(&public_keys, signed, |signature| match signature {
+                Signature::Schnorrkel(signature) => Ok(signature),
+                _ => unreachable!(),
+            })
+            .map_err(|_| CryptoError::BadSignature)
+    }
+
+    // verify in one go if the flavor supports it, i.e. `Schnorrkel`, and one by one otherwise or if
+    // the batch fails, so the invalid ones can be told apart. return the valid ones
+    pub fn verify_batch_or_each<I: Clone + Into<usize>, M: DigestHash>(
+        &self,
+        indexes: &[I],
+        signed: Vec<Verifiable<M>>,
+    ) -> Result<Vec<Verifiable<M>>, CryptoError> {
+        match self.verify_batch(indexes, &signed) {
+            Ok(()) => return Ok(signed),
+            Err(err @ CryptoError::LengthMismatch(..)) => return Err(err),
+            Err(_) => {}
+        }
+        Ok(indexes
+            .iter()
+            .zip(signed)
+            .filter(|(index, signed)| self.verify((*index).clone(), signed).is_ok())
+            .map(|(_, signed)| signed)
+            .collect())
+    }
+
+    // all `signed` must be signed over the identical message, which is the case for e.g. a
+    // quorum of `Commit`s that matches on view number, op number and digest
+    // the shares are not verified here; verify them one by one before aggregating, or verify the
+    // aggregated result as a whole and throw it away if it fails
+    pub fn aggregate<I: Clone + Into<usize>, M: DigestHash + Clone>(
+        &self,
+        indexes: &[I],
+        signed: &[Verifiable<M>],
+    ) -> anyhow::Result<Verifiable<M, AggregateSignature>> {
+        anyhow::ensure!(indexes.len() == signed.len());
+        let Some(first) = signed.first() else {
+            anyhow::bail!("empty aggregation")
+        };
+        let digest = first.inner.sha256();
+        let mut signers = BTreeSet::new();
+        let mut signatures = Vec::new();
+        for (index, verifiable) in indexes.iter().zip(signed) {
+            anyhow::ensure!(
+                verifiable.inner.sha256() == digest,
+                "aggregating different messages"
+            );
+            let Signature::Bls(BlsSignature(signature)) = &verifiable.signature else {
+                return Err(CryptoError::UnsupportedFlavor.into());
+            };
+            let inserted = signers.insert(index.clone().into());
+            anyhow::ensure!(inserted, "duplicated signer {}", index.clone().into());
+            signatures.push(&**signature)
+        }
+        let signature = blst::min_pk::AggregateSignature::aggregate(&signatures, true)
+            .map_err(|err| anyhow::format_err!("{err:?}"))?
+            .to_signature();
+        Ok(Verifiable {
+            inner: first.inner.clone(),
+            signature: AggregateSignature {
+                signers,
+                signature: BlsSignature(Box::new(signature)),
+            },
         })
     }
+
+    // verify against the group public key aggregated from the public keys of `signers`, and ensure
+    // there are at least `threshold` signers
+    pub fn verify_aggregate<M: DigestHash>(
+        &self,
+        threshold: usize,
+        aggregated: &Verifiable<M, AggregateSignature>,
+    ) -> anyhow::Result<()> {
+        let AggregateSignature {
+            signers,
+            signature: BlsSignature(signature),
+        } = &aggregated.signature;
+        anyhow::ensure!(
+            signers.len() >= threshold,
+            "insufficient signers: {} < {threshold}",
+            signers.len()
+        );
+        let public_keys = signers
+            .iter()
+            .map(|index| match self.public_keys.get(*index) {
+                Some(PublicKey::Bls(public_key)) => Ok(public_key),
+                Some(_) => Err(CryptoError::UnsupportedFlavor),
+                None => Err(CryptoError::UnknownSigner(*index)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = signature.fast_aggregate_verify(
+            true,
+            aggregated.inner.sha256().as_bytes(),
+            BLS_DST,
+            &public_keys,
+        );
+        anyhow::ensure!(result == blst::BLST_ERROR::BLST_SUCCESS, "{result:?}");
+        Ok(())
+    }
 }
 
 pub mod peer {
@@ -350,10 +794,10 @@ pub mod peer {
     pub type PublicKey = schnorrkel::PublicKey;
 
     pub mod events {
-        #[derive(Debug, Clone)]
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
         pub struct Signed<M>(pub super::Verifiable<M>);
 
-        #[derive(Debug, Clone)]
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
         pub struct Verified<M>(pub super::Verifiable<M>);
     }
 
@@ -408,9 +852,11 @@ impl SchnorrkelCrypto {
     }
 
     fn sign<M: DigestHash>(&self, message: &M) -> SchnorrkelSignature {
-        let mut state = Sha256::new();
-        DigestHash::hash(message, &mut state);
-        SchnorrkelSignature(self.keypair.sign(self.context.hash256(state)))
+        self.sign_prehashed(message.sha256())
+    }
+
+    fn sign_prehashed(&self, digest: H256) -> SchnorrkelSignature {
+        SchnorrkelSignature(self.keypair.sign(self.context.hash256(Prehashed(digest))))
     }
 
     fn verify<M: DigestHash, S>(
@@ -419,11 +865,18 @@ impl SchnorrkelCrypto {
         signed: &Verifiable<M, S>,
         as_signature: impl FnOnce(&S) -> anyhow::Result<&SchnorrkelSignature>,
     ) -> anyhow::Result<()> {
-        let SchnorrkelSignature(signature) = as_signature(&signed.signature)?;
-        let mut state = Sha256::new();
-        DigestHash::hash(&signed.inner, &mut state);
+        let signature = as_signature(&signed.signature)?;
+        self.verify_prehashed(public_key, signed.inner.sha256(), signature)
+    }
+
+    fn verify_prehashed(
+        &self,
+        public_key: &schnorrkel::PublicKey,
+        digest: H256,
+        SchnorrkelSignature(signature): &SchnorrkelSignature,
+    ) -> anyhow::Result<()> {
         public_key
-            .verify(self.context.hash256(state), signature)
+            .verify(self.context.hash256(Prehashed(digest)), signature)
             .map_err(anyhow::Error::msg)
     }
 
@@ -465,6 +918,19 @@ mod tests {
         assert_ne!(foo.sha256(), Default::default());
     }
 
+    #[test]
+    fn prefix_free_digest() {
+        assert_ne!(
+            (vec![1u8], vec![2u8, 3]).sha256(),
+            (vec![1u8, 2], vec![3u8]).sha256()
+        );
+        assert_ne!(("a", "bc").sha256(), ("ab", "c").sha256());
+        assert_ne!(
+            (Some(0u32), None::<u32>).sha256(),
+            (None::<u32>, Some(0u32)).sha256()
+        );
+    }
+
     #[test]
     fn verify_batched() -> anyhow::Result<()> {
         let message = "hello";
@@ -475,6 +941,134 @@ mod tests {
             .iter()
             .map(|crypto| crypto.sign(message))
             .collect::<Vec<_>>();
-        crypto[0].verify_batch(&[0usize, 1, 2, 3], &verifiable)
+        crypto[0].verify_batch(&[0usize, 1, 2, 3], &verifiable)?;
+        // an extra signed message is not left unverified
+        for crypto in [
+            &crypto[0],
+            &Crypto::new_hardcoded(4, 0usize, CryptoFlavor::Plain)?,
+        ] {
+            anyhow::ensure!(
+                crypto.verify_batch(&[0usize, 1, 2], &verifiable)
+                    == Err(CryptoError::LengthMismatch(3, 4))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prehashed() -> anyhow::Result<()> {
+        let message = "hello";
+        for flavor in [
+            CryptoFlavor::Plain,
+            CryptoFlavor::ModelDeterministic,
+            CryptoFlavor::Secp256k1,
+            CryptoFlavor::Schnorrkel,
+            CryptoFlavor::Bls,
+        ] {
+            let crypto = (0..2usize)
+                .map(|i| Crypto::new_hardcoded(2, i, flavor))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let signature = crypto[0].sign_prehashed(message.sha256());
+            crypto[1].verify_prehashed(0usize, message.sha256(), &signature)?;
+            crypto[1].verify(
+                0usize,
+                &Verifiable {
+                    inner: message,
+                    signature: signature.clone(),
+                },
+            )?;
+            let verifiable = crypto[0].sign(message);
+            crypto[1].verify_prehashed(0usize, message.sha256(), &verifiable.signature)?;
+            for verifiable in crypto[0].sign_batch(vec![message, "world"]) {
+                crypto[1].verify(0usize, &verifiable)?
+            }
+            anyhow::ensure!(crypto[1]
+                .verify_prehashed(1usize, message.sha256(), &signature)
+                .is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn encoded_public_keys() -> anyhow::Result<()> {
+        let message = "hello";
+        // the key material `new_hardcoded` uses for replica 1
+        let mut secret = [0; 32];
+        secret[..9].copy_from_slice(b"replica-1");
+        for (flavor, secret) in [
+            (CryptoFlavor::Plain, &b"replica-001"[..]),
+            (CryptoFlavor::ModelDeterministic, &1u64.to_le_bytes()),
+            (CryptoFlavor::Secp256k1, &secret),
+            (CryptoFlavor::Schnorrkel, &secret),
+            (CryptoFlavor::Bls, &secret),
+        ] {
+            let crypto = Crypto::new_hardcoded(2, 0usize, flavor)?;
+            let public_set = crypto.public_keys_encoded();
+            let joined = Crypto::from_encoded(1usize, secret, &public_set, flavor)?;
+            anyhow::ensure!(joined.public_keys_encoded() == public_set);
+            crypto.verify(1usize, &joined.sign(message))?;
+            joined.verify(0usize, &crypto.sign(message))?;
+            anyhow::ensure!(Crypto::from_encoded(0usize, secret, &public_set, flavor).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn model_deterministic() -> anyhow::Result<()> {
+        let crypto = (0..2usize)
+            .map(|i| Crypto::new_hardcoded(2, i, CryptoFlavor::ModelDeterministic))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let signed = crypto[0].sign("hello");
+        anyhow::ensure!(signed == crypto[0].sign("hello"));
+        crypto[1].verify(0usize, &signed)?;
+        // claimed by another signer
+        anyhow::ensure!(crypto[1].verify(1usize, &signed) == Err(CryptoError::BadSignature));
+        // rewritten message
+        anyhow::ensure!(
+            crypto[1].verify(0usize, &signed.clone().map(|_| "world"))
+                == Err(CryptoError::BadSignature)
+        );
+        anyhow::ensure!(crypto[1].verify(2usize, &signed) == Err(CryptoError::UnknownSigner(2)));
+        // signed with another flavor
+        let plain = Crypto::new_hardcoded(2, 0usize, CryptoFlavor::Plain)?;
+        anyhow::ensure!(
+            crypto[1].verify(0usize, &plain.sign("hello")) == Err(CryptoError::FlavorMismatch)
+        );
+        // the plain "signature" does not catch the rewritten message
+        plain.verify(0usize, &plain.sign("hello").map(|_| "world"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn verify_aggregated() -> anyhow::Result<()> {
+        let message = "hello";
+        let crypto = (0..4usize)
+            .map(|i| Crypto::new_hardcoded(4, i, CryptoFlavor::Bls))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let verifiable = crypto
+            .iter()
+            .map(|crypto| crypto.sign(message))
+            .collect::<Vec<_>>();
+        for (i, verifiable) in verifiable.iter().enumerate() {
+            crypto[0].verify(i, verifiable)?
+        }
+        let aggregated = crypto[0].aggregate(&[1usize, 2, 3], &verifiable[1..])?;
+        crypto[0].verify_aggregate(3, &aggregated)?;
+        anyhow::ensure!(crypto[0].verify_aggregate(4, &aggregated).is_err());
+
+        let mut forged = aggregated.clone();
+        forged.signature.signers = [0, 1, 2].into();
+        anyhow::ensure!(crypto[0].verify_aggregate(3, &forged).is_err());
+
+        let plain = Crypto::new_hardcoded(4, 0usize, CryptoFlavor::Plain)?;
+        let unsupported =
+            |err: anyhow::Error| err.downcast_ref() == Some(&CryptoError::UnsupportedFlavor);
+        anyhow::ensure!(plain
+            .aggregate(&[0usize], &[plain.sign(message)])
+            .is_err_and(unsupported));
+        anyhow::ensure!(plain
+            .verify_aggregate(3, &aggregated)
+            .is_err_and(unsupported));
+        Ok(())
     }
 }
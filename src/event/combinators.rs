@@ -1,7 +1,12 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hash,
+};
+
 use derive_more::{Deref, DerefMut};
 use derive_where::derive_where;
 
-use super::{OnEvent, SendEvent, Submit};
+use super::{OnErasedEvent, OnEvent, SendEvent, Submit};
 
 pub mod erase {
     use crate::event::{Erase, UntypedEvent};
@@ -70,6 +75,50 @@ impl<S, C> Submit<S, C> for Transient<super::Work<S, C>> {
     }
 }
 
+// buffer the events and drop the ones identical to a pending one, e.g. the follow-up work that is
+// queued repeatedly before getting processed
+// `flush` delivers the pending events in the order of their first sending
+#[derive(Debug)]
+#[derive_where(Default)]
+pub struct Coalesce<M> {
+    pending: VecDeque<M>,
+    seen: HashSet<M>,
+}
+
+impl<M> Coalesce<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<M: Eq + Hash> Coalesce<M> {
+    // on error the events that are not delivered yet are kept pending
+    pub fn flush(&mut self, mut sender: impl SendEvent<M>) -> anyhow::Result<()> {
+        while let Some(event) = self.pending.pop_front() {
+            self.seen.remove(&event);
+            sender.send(event)?
+        }
+        Ok(())
+    }
+}
+
+impl<M: Eq + Hash + Clone> SendEvent<M> for Coalesce<M> {
+    fn send(&mut self, event: M) -> anyhow::Result<()> {
+        if self.seen.insert(event.clone()) {
+            self.pending.push_back(event)
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Map<F, E>(pub F, pub E);
 
@@ -79,6 +128,83 @@ impl<F: FnMut(M) -> N, M, N, E: SendEvent<N>> SendEvent<M> for Map<F, E> {
     }
 }
 
+// capture the events that fail to be sent along with the errors, instead of propagating the errors
+// e.g. to inspect what is lost in a shutdown race. only the latest `capacity` failures are kept
+// wrap only the senders whose failures are expected; a plain sender still propagates the errors
+#[derive(Debug)]
+pub struct DeadLetter<E, M> {
+    inner: E,
+    capacity: usize,
+    captured: VecDeque<(M, anyhow::Error)>,
+}
+
+impl<E, M> DeadLetter<E, M> {
+    pub fn new(inner: E, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            captured: Default::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.captured.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captured.is_empty()
+    }
+
+    // the captured events in the order of failing
+    pub fn drain(&mut self) -> impl Iterator<Item = (M, anyhow::Error)> + '_ {
+        self.captured.drain(..)
+    }
+}
+
+impl<E: SendEvent<M>, M: Clone> SendEvent<M> for DeadLetter<E, M> {
+    fn send(&mut self, event: M) -> anyhow::Result<()> {
+        if let Err(err) = self.inner.send(event.clone()) {
+            if self.captured.len() == self.capacity {
+                self.captured.pop_front();
+            }
+            if self.capacity > 0 {
+                self.captured.push_back((event, err))
+            }
+        }
+        Ok(())
+    }
+}
+
+// feed every event to both the primary state and an observer (e.g. for metrics, logging or fault
+// injection), without the primary knowing about the observer
+// the primary handles the event first and its result is authoritative: if it fails the error is
+// returned as is and the observer does not see the event. otherwise an error from the observer is
+// returned (so e.g. an injected fault still shuts down the event loop), while the effect of the
+// primary's handling is already in place
+#[derive(Debug, Clone, Default)]
+pub struct Tee<S, O>(pub S, pub O);
+
+impl<S: OnEvent<C>, O: OnEvent<C, Event = S::Event>, C> OnEvent<C> for Tee<S, O>
+where
+    S::Event: Clone,
+{
+    type Event = S::Event;
+
+    fn on_event(&mut self, event: Self::Event, context: &mut C) -> anyhow::Result<()> {
+        self.0.on_event(event.clone(), context)?;
+        self.1.on_event(event, context)
+    }
+}
+
+impl<S: OnErasedEvent<M, C>, O: OnErasedEvent<M, C>, M: Clone, C: ?Sized> OnErasedEvent<M, C>
+    for Tee<S, O>
+{
+    fn on_event(&mut self, event: M, context: &mut C) -> anyhow::Result<()> {
+        self.0.on_event(event.clone(), context)?;
+        self.1.on_event(event, context)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::event::Submit as _;
@@ -101,4 +227,46 @@ mod tests {
         anyhow::ensure!(context == 55);
         Ok(())
     }
+
+    #[test]
+    fn tee() -> anyhow::Result<()> {
+        // sums the events into the context, and fails on the events above the limit
+        struct Sum(u32);
+        impl OnEvent<u32> for Sum {
+            type Event = u32;
+            fn on_event(&mut self, event: u32, context: &mut u32) -> anyhow::Result<()> {
+                anyhow::ensure!(event <= self.0);
+                *context += event;
+                Ok(())
+            }
+        }
+
+        let mut context = 0;
+        let mut tee = Tee(Sum(10), Sum(5));
+        for event in [1, 2, 3] {
+            tee.on_event(event, &mut context)?
+        }
+        anyhow::ensure!(context == 12);
+        // the primary has handled the event before the observer fails
+        anyhow::ensure!(tee.on_event(6, &mut context).is_err());
+        anyhow::ensure!(context == 18);
+        // the observer is skipped when the primary fails
+        anyhow::ensure!(Tee(Sum(5), Sum(10)).on_event(6, &mut context).is_err());
+        anyhow::ensure!(context == 18);
+        Ok(())
+    }
+
+    #[test]
+    fn dead_letter() -> anyhow::Result<()> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel::<u32>();
+        let mut sender = DeadLetter::<_, u32>::new(sender, 2);
+        sender.send(1)?;
+        drop(receiver);
+        for event in [2, 3, 4] {
+            sender.send(event)?
+        }
+        anyhow::ensure!(sender.drain().map(|(event, _)| event).eq([3, 4]));
+        anyhow::ensure!(sender.is_empty());
+        Ok(())
+    }
 }
@@ -4,6 +4,8 @@ pub mod event;
 pub mod model;
 pub mod net;
 pub mod pbft;
+pub mod quorum;
+pub mod raft;
 pub mod timer;
 pub mod unreplicated;
 pub mod workload; // better name that clearly shows unrelated to `worker`?
@@ -2,10 +2,13 @@ use std::{
     any::Any,
     convert::identity,
     fmt::{Debug, Display},
+    fs::File,
     hash::{BuildHasherDefault, Hash},
-    iter::repeat,
+    io::{BufReader, BufWriter, Write as _},
+    iter::{once, repeat_n},
     num::NonZeroUsize,
     panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering::SeqCst},
         Arc, Barrier, Condvar, Mutex,
@@ -15,11 +18,18 @@ use std::{
 
 use crossbeam_queue::SegQueue;
 use derive_where::derive_where;
-use rand::{seq::IteratorRandom as _, thread_rng};
+use rand::{
+    distributions::{Distribution as _, WeightedIndex},
+    rngs::StdRng,
+    seq::IteratorRandom as _,
+    Rng as _, SeedableRng as _,
+};
 use rustc_hash::FxHasher;
 use scc::HashMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
 
-use crate::event::SendEvent;
+use crate::{crypto::DigestHash as _, event::SendEvent};
 
 // use scc::HashIndex as HashMap;
 
@@ -53,19 +63,339 @@ fn step<S: State>(state: &mut S, event: S::Event) -> anyhow::Result<()> {
         .and_then(identity)
 }
 
+// bounded fairness: an event that `is_fair` (typically a timer) must be taken within `bound` steps
+// of staying enabled. once one has been enabled for `bound` consecutive steps without being taken,
+// only the starving events are offered, so the unfair schedules that postpone e.g. a view change
+// timer forever are not explored
+// this under-approximates fairness: the schedules that take a timer after more than `bound` steps
+// are fair as well but are not explored, and it only applies to the events that are enabled
+// continuously, not the ones that are enabled infinitely often
+// the counters are part of the state, so the same underlying state may be explored once for each
+// distinct set of counters
+#[derive_where(Debug, Clone, PartialEq, Eq, Hash; S, S::Event)]
+pub struct Fair<S: State> {
+    pub state: S,
+    // the enabled fair events, along with the number of steps since they are enabled
+    enabled: Vec<(S::Event, usize)>,
+    bound: usize,
+    #[derive_where(skip)]
+    is_fair: fn(&S::Event) -> bool,
+}
+
+impl<S: State> Fair<S>
+where
+    S::Event: PartialEq,
+{
+    pub fn new(state: S, is_fair: fn(&S::Event) -> bool, bound: usize) -> Self {
+        let enabled = state
+            .events()
+            .filter(is_fair)
+            .map(|event| (event, 0))
+            .collect();
+        Self {
+            state,
+            enabled,
+            bound,
+            is_fair,
+        }
+    }
+}
+
+impl<S: State> SendEvent<S::Event> for Fair<S>
+where
+    S::Event: PartialEq,
+{
+    fn send(&mut self, event: S::Event) -> anyhow::Result<()> {
+        // taking an event resets its counter, e.g. re-arming the same timer
+        self.enabled
+            .retain(|(enabled_event, _)| *enabled_event != event);
+        self.state.send(event)?;
+        let enabled = self
+            .state
+            .events()
+            .filter(self.is_fair)
+            .map(|event| {
+                let count = self
+                    .enabled
+                    .iter()
+                    .find(|(enabled_event, _)| *enabled_event == event)
+                    .map(|(_, count)| count + 1)
+                    .unwrap_or(0);
+                (event, count)
+            })
+            .collect();
+        self.enabled = enabled;
+        Ok(())
+    }
+}
+
+impl<S: State> State for Fair<S>
+where
+    S::Event: Clone + PartialEq,
+{
+    type Event = S::Event;
+
+    fn events(&self) -> impl Iterator<Item = Self::Event> + '_ {
+        let starving = self
+            .enabled
+            .iter()
+            .filter(|(_, count)| *count >= self.bound)
+            .map(|(event, _)| event)
+            .collect::<Vec<_>>();
+        self.state
+            .events()
+            .filter(move |event| starving.is_empty() || starving.contains(&event))
+    }
+}
+
+// the violated property is reported by its name along with the error. a plain
+// `Fn(&S) -> anyhow::Result<()>` invariant is named after its type, which is the path of a function
+// e.g. `State::agreement`, and less informative for a closure
+pub trait Invariant<S> {
+    fn check(&self, state: &S) -> Result<(), (String, anyhow::Error)>;
+}
+
+impl<S, F: Fn(&S) -> anyhow::Result<()>> Invariant<S> for F {
+    fn check(&self, state: &S) -> Result<(), (String, anyhow::Error)> {
+        self(state).map_err(|err| (std::any::type_name::<F>().into(), err))
+    }
+}
+
+pub type NamedInvariant<S> = (String, Arc<dyn Fn(&S) -> anyhow::Result<()> + Send + Sync>);
+
+// a set of properties that are checked in order, and the first violated one is reported
+// `Invariants::new().with("agreement", ...).with("validity", ...)`
+#[derive_where(Clone, Default)]
+pub struct Invariants<S>(pub Vec<NamedInvariant<S>>);
+
+impl<S> Invariants<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(
+        mut self,
+        name: impl Into<String>,
+        invariant: impl Fn(&S) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.0.push((name.into(), Arc::new(invariant)));
+        self
+    }
+}
+
+impl<S> Debug for Invariants<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl<S> Invariant<S> for Invariants<S> {
+    fn check(&self, state: &S) -> Result<(), (String, anyhow::Error)> {
+        for (name, invariant) in &self.0 {
+            invariant(state).map_err(|err| (name.clone(), err))?
+        }
+        Ok(())
+    }
+}
+
+// what a `Prune` may consult besides the state, e.g. to bound the exploration of each depth for a
+// beam search
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PruneContext {
+    // the number of steps from the initial state to the state
+    pub depth: usize,
+    // the number of states explored so far, which counts the inner search states for the inner
+    // search of `nested_depth_first`
+    pub explored: usize,
+    // the number of states discovered at `depth` so far, including the state. only available to
+    // the breadth first searches
+    pub explored_at_depth: Option<usize>,
+}
+
+pub trait Prune<S> {
+    fn prune(&self, state: &S, context: &PruneContext) -> bool;
+}
+
+impl<S, F: Fn(&S) -> bool> Prune<S> for F {
+    fn prune(&self, state: &S, _: &PruneContext) -> bool {
+        self(state)
+    }
+}
+
+// `prune: WithContext(|state: &_, context: &PruneContext| ...)` for the prunes that consult the
+// context, while a plain `Fn(&S) -> bool` prune is taken as is
 #[derive(Debug, Clone)]
-pub struct Settings<I, G, P> {
+pub struct WithContext<F>(pub F);
+
+impl<S, F: Fn(&S, &PruneContext) -> bool> Prune<S> for WithContext<F> {
+    fn prune(&self, state: &S, context: &PruneContext) -> bool {
+        (self.0)(state, context)
+    }
+}
+
+// how `random_depth_first` chooses among the events of a state, to steer the probes towards the
+// rare but interesting events e.g. timeouts. a `Fn(&E) -> f32` gives the relative chance of each
+// event, and an event of zero weight is never chosen
+pub trait Weight<E> {
+    // the index of the chosen one among `events`, None if there's nothing to choose
+    fn choose(&self, events: &[E], rng: &mut StdRng) -> Option<usize>;
+}
+
+// every event is equally likely to be chosen, the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Uniform;
+
+impl<E> Weight<E> for Uniform {
+    fn choose(&self, events: &[E], rng: &mut StdRng) -> Option<usize> {
+        (0..events.len()).choose(rng)
+    }
+}
+
+impl<E, F: Fn(&E) -> f32> Weight<E> for F {
+    fn choose(&self, events: &[E], rng: &mut StdRng) -> Option<usize> {
+        let index = WeightedIndex::new(events.iter().map(self)).ok()?;
+        Some(index.sample(rng))
+    }
+}
+
+// map a state into the representative of its symmetry class, e.g. relabeling interchangeable
+// nodes into a canonical order, so the symmetric states are only explored once. a `Fn(&S) -> S`
+// is taken as the mapping
+pub trait Canonicalize<S> {
+    fn canonicalize(&self, state: S) -> S;
+}
+
+// every state is its own representative i.e. no reduction, the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl<S> Canonicalize<S> for Identity {
+    fn canonicalize(&self, state: S) -> S {
+        state
+    }
+}
+
+impl<S, F: Fn(&S) -> S> Canonicalize<S> for F {
+    fn canonicalize(&self, state: S) -> S {
+        self(&state)
+    }
+}
+
+#[derive(Clone)]
+#[derive_where(Debug; I, G, P)]
+pub struct Settings<I, G, P, C = Identity, W = Uniform> {
     pub invariant: I,
     pub goal: G,
     pub prune: P,
+    // see `Canonicalize`. `Identity` for no reduction. only the breadth first searches keep the
+    // discovered states and make use of it, and the invariant and goal are checked on the canonical
+    // states, including the initial one. the depth first searches explore the states as is
+    // the canonical states may be reached by events of other labels, so a trace with relabeled
+    // states does not necessarily replay as is
+    #[derive_where(skip)]
+    pub canonicalize: C,
+    // see `Weight`. `Uniform` for not steering the probes
+    #[derive_where(skip)]
+    pub weight: W,
     pub max_depth: Option<NonZeroUsize>,
+    // stop the breadth first searches with `StateLimitReached` when this many states are
+    // discovered, instead of running out of memory
+    pub max_states: Option<usize>,
+    // called periodically with the search progress. the progress is printed to stdout if not set
+    #[derive_where(skip)]
+    pub progress: Option<ProgressCallback>,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(SearchProgress) + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub struct SearchProgress {
+    pub explored: usize,
+    pub elapsed: Duration,
+    // explored states per second
+    pub rate: f32,
+    // the current depth of `breadth_first`, or the current depth bound of `depth_first`
+    pub depth: Option<usize>,
+    // the number of probes that have been started by `random_depth_first`
+    pub num_probe: Option<usize>,
+    // the number of states explored by the inner searches of `nested_depth_first`
+    pub inner_explored: Option<usize>,
+    pub checkpoint: Option<PathBuf>,
+    // the resident memory of the process divided by the discovered states of `breadth_first`, for
+    // estimating the `max_states` that fits in memory
+    pub bytes_per_state: Option<f32>,
+}
+
+impl SearchProgress {
+    fn new(explored: usize, elapsed: Duration) -> Self {
+        Self {
+            explored,
+            elapsed,
+            rate: explored as f32 / elapsed.as_secs_f32(),
+            depth: None,
+            num_probe: None,
+            inner_explored: None,
+            checkpoint: None,
+            bytes_per_state: None,
+        }
+    }
+}
+
+// the resident set size, only available on Linux
+fn resident_bytes() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<usize>()
+        .ok()?;
+    Some(kilobytes << 10)
+}
+
+impl Display for SearchProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Explored: {}", self.explored)?;
+        if let Some(depth) = self.depth {
+            write!(f, ", Depth {depth}")?
+        }
+        if let Some(num_probe) = self.num_probe {
+            write!(f, ", Num Probes: {num_probe}")?
+        }
+        if let Some(inner_explored) = self.inner_explored {
+            write!(f, ", Inner explored: {inner_explored}")?
+        }
+        if let Some(bytes_per_state) = self.bytes_per_state {
+            write!(f, ", {bytes_per_state:.0} B/state")?
+        }
+        write!(
+            f,
+            " ({:.2}s, {:.2}K states/s)",
+            self.elapsed.as_secs_f32(),
+            self.rate / 1000.
+        )?;
+        if let Some(checkpoint) = &self.checkpoint {
+            write!(f, ", Checkpoint {}", checkpoint.display())?
+        }
+        Ok(())
+    }
 }
 
 pub enum SearchResult<S, E> {
     Err(Vec<(E, S)>, E, anyhow::Error),
-    InvariantViolation(Vec<(E, S)>, anyhow::Error),
+    // the name of the violated property, see `Invariant`
+    InvariantViolation(Vec<(E, S)>, String, anyhow::Error),
+    // a reachable cycle along which no state makes progress. the prefix leads from the initial
+    // state to the first state of the cycle, and the cycle trace ends at that same state
+    LivenessViolation(Vec<(E, S)>, Vec<(E, S)>),
     GoalFound(S),
     SpaceExhausted,
+    // the number of discovered states when `max_states` is reached
+    StateLimitReached(usize),
     Timeout,
 }
 
@@ -73,9 +403,18 @@ impl<S, E> Debug for SearchResult<S, E> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Err(_, _, err) => write!(f, "Err({err})"),
-            Self::InvariantViolation(_, err) => write!(f, "InvariantViolation({err:?})"),
+            Self::InvariantViolation(_, name, err) => {
+                write!(f, "InvariantViolation({name}, {err:?})")
+            }
+            Self::LivenessViolation(prefix, cycle) => write!(
+                f,
+                "LivenessViolation(prefix {}, cycle {})",
+                prefix.len(),
+                cycle.len()
+            ),
             Self::GoalFound(_) => write!(f, "GoalFound"),
             Self::SpaceExhausted => write!(f, "SpaceExhausted"),
+            Self::StateLimitReached(count) => write!(f, "StateLimitReached({count})"),
             Self::Timeout => write!(f, "Timeout"),
         }
     }
@@ -92,40 +431,241 @@ impl<S: Debug, E: Debug> Display for SearchResult<S, E> {
                 writeln!(f, "-> {event:?}")?;
                 write!(f, "{err}")
             }
-            Self::InvariantViolation(trace, err) => {
+            Self::InvariantViolation(trace, name, err) => {
                 for (event, state) in trace {
                     writeln!(f, "-> {event:?}")?;
                     writeln!(f, "{state:?}")?
                 }
-                write!(f, "{err}")
+                write!(f, "{name}: {err}")
+            }
+            Self::LivenessViolation(prefix, cycle) => {
+                for (event, state) in prefix {
+                    writeln!(f, "-> {event:?}")?;
+                    writeln!(f, "{state:?}")?
+                }
+                writeln!(f, "(cycle start)")?;
+                for (event, state) in cycle {
+                    writeln!(f, "-> {event:?}")?;
+                    writeln!(f, "{state:?}")?
+                }
+                write!(f, "(cycle end)")
             }
             result => write!(f, "{result:?}"),
         }
     }
 }
 
-pub fn breadth_first<S, I, G, P>(
+// one JSON object per line for each step of the trace, for post-processing (e.g. visualizing) by
+// external tools. the failing event of `Err` comes last without a state, and the steps in the cycle
+// of `LivenessViolation` are marked with `"cycle": true`
+// results without a trace write nothing
+impl<S: Serialize, E: Serialize> SearchResult<S, E> {
+    pub fn write_trace_json(&self, mut write: impl std::io::Write) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct Step<'a, S, E> {
+            event: &'a E,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            state: Option<&'a S>,
+            #[serde(skip_serializing_if = "std::ops::Not::not")]
+            cycle: bool,
+        }
+        let mut write_steps = |trace: &[(E, S)], cycle| {
+            for (event, state) in trace {
+                let step = Step {
+                    event,
+                    state: Some(state),
+                    cycle,
+                };
+                serde_json::to_writer(&mut write, &step)?;
+                writeln!(write)?
+            }
+            anyhow::Ok(())
+        };
+        match self {
+            Self::Err(trace, event, _) => {
+                write_steps(trace, false)?;
+                let step = Step::<S, _> {
+                    event,
+                    state: None,
+                    cycle: false,
+                };
+                serde_json::to_writer(&mut write, &step)?;
+                writeln!(write)?
+            }
+            Self::InvariantViolation(trace, ..) => write_steps(trace, false)?,
+            Self::LivenessViolation(prefix, cycle) => {
+                write_steps(prefix, false)?;
+                write_steps(cycle, true)?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+pub fn breadth_first<S, I, G, P, C, W>(
     initial_state: S,
-    settings: Settings<I, G, P>,
+    settings: Settings<I, G, P, C, W>,
     num_worker: NonZeroUsize,
     max_duration: impl Into<Option<Duration>>,
 ) -> anyhow::Result<SearchResult<S, S::Event>>
 where
     S: State + Clone + Eq + Hash + Send + Sync + 'static,
     S::Event: Clone + Send + Sync,
-    I: Fn(&S) -> anyhow::Result<()> + Clone + Send + 'static,
+    I: Invariant<S> + Clone + Send + 'static,
     G: Fn(&S) -> bool + Clone + Send + 'static,
-    P: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Canonicalize<S> + Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
 {
-    let discovered = Arc::new(HashMap::with_hasher(
-        BuildHasherDefault::<FxHasher>::default(),
-    ));
-    let queue = Arc::new(SegQueue::new());
-    let pushing_queue = Arc::new(SegQueue::new());
-    let depth = Arc::new(AtomicUsize::new(0));
-    let depth_barrier = Arc::new(Barrier::new(num_worker.get()));
-    let search_finished = Arc::new((Mutex::new(None), Condvar::new(), AtomicBool::new(false)));
+    let initial_state = settings.canonicalize.canonicalize(initial_state);
+    if let Some(result) = check_initial(&initial_state, &settings.invariant, &settings.goal) {
+        return Ok(result);
+    }
+    let (discovered, queue) = initial_discovered(initial_state);
+    breadth_first_internal(
+        discovered,
+        queue,
+        0,
+        settings,
+        num_worker,
+        max_duration,
+        None,
+        trace,
+    )
+}
+
+// same as `breadth_first`, but only keeps a 128-bit fingerprint of each discovered state instead of
+// the state itself and how it is reached, which takes much less memory so larger bounds can be
+// checked
+// a fingerprint collision may cause part of the state space to be silently skipped. the trace of a
+// violation is not available from the search, and is rebuilt by searching from the initial state
+// again for the violating state
+pub fn breadth_first_fingerprint<S, I, G, P, C, W>(
+    initial_state: S,
+    settings: Settings<I, G, P, C, W>,
+    num_worker: NonZeroUsize,
+    max_duration: impl Into<Option<Duration>>,
+) -> anyhow::Result<SearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash + Send + Sync + 'static,
+    S::Event: Clone + Send + Sync,
+    I: Invariant<S> + Clone + Send + 'static,
+    G: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Canonicalize<S> + Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
+{
+    let canonical_state = settings.canonicalize.canonicalize(initial_state.clone());
+    if let Some(result) = check_initial(&canonical_state, &settings.invariant, &settings.goal) {
+        return Ok(result);
+    }
+    let discovered = Fingerprints::default();
+    let _ = discovered.insert(fingerprint(&canonical_state));
+    let queue = SegQueue::new();
+    queue.push(Arc::new(canonical_state));
+    let canonicalize = settings.canonicalize.clone();
+    breadth_first_internal(
+        discovered,
+        queue,
+        0,
+        settings,
+        num_worker,
+        max_duration,
+        None,
+        move |_, target| rebuild_trace(&initial_state, &target, &canonicalize),
+    )
+}
+
+// same as `breadth_first`, but saves the discovered states and the frontier into `checkpoint` file
+// every `checkpoint_interval` (at the end of the depth that exceeds the interval), so the search
+// can be continued with `resume_breadth_first` after being interrupted
+pub fn breadth_first_resumable<S, I, G, P, C, W>(
+    initial_state: S,
+    settings: Settings<I, G, P, C, W>,
+    num_worker: NonZeroUsize,
+    max_duration: impl Into<Option<Duration>>,
+    checkpoint: impl Into<PathBuf>,
+    checkpoint_interval: Duration,
+) -> anyhow::Result<SearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash + Serialize + Send + Sync + 'static,
+    S::Event: Clone + Serialize + Send + Sync,
+    I: Invariant<S> + Clone + Send + 'static,
+    G: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Canonicalize<S> + Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
+{
+    let initial_state = settings.canonicalize.canonicalize(initial_state);
+    if let Some(result) = check_initial(&initial_state, &settings.invariant, &settings.goal) {
+        return Ok(result);
+    }
+    let (discovered, queue) = initial_discovered(initial_state);
+    breadth_first_internal(
+        discovered,
+        queue,
+        0,
+        settings,
+        num_worker,
+        max_duration,
+        Some(Checkpoint::new(checkpoint.into(), checkpoint_interval)),
+        trace,
+    )
+}
+
+// continue the search from a checkpoint saved by `breadth_first_resumable`, and keep updating the
+// same checkpoint file
+// the settings are not saved in the checkpoint, and it is up to the caller to pass in the same
+// settings that the checkpoint was created with
+pub fn resume_breadth_first<S, I, G, P, C, W>(
+    checkpoint: impl Into<PathBuf>,
+    settings: Settings<I, G, P, C, W>,
+    num_worker: NonZeroUsize,
+    max_duration: impl Into<Option<Duration>>,
+    checkpoint_interval: Duration,
+) -> anyhow::Result<SearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash + Serialize + DeserializeOwned + Send + Sync + 'static,
+    S::Event: Clone + Serialize + DeserializeOwned + Send + Sync,
+    I: Invariant<S> + Clone + Send + 'static,
+    G: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Canonicalize<S> + Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
+{
+    let checkpoint = Checkpoint::new(checkpoint.into(), checkpoint_interval);
+    let (discovered, queue, depth) = checkpoint.load()?;
+    breadth_first_internal(
+        discovered,
+        queue,
+        depth,
+        settings,
+        num_worker,
+        max_duration,
+        Some(checkpoint),
+        trace,
+    )
+}
 
+// the workers only check the states that they step into, so the initial state is checked upfront
+fn check_initial<S: Clone, E>(
+    initial_state: &S,
+    invariant: &impl Invariant<S>,
+    goal: impl Fn(&S) -> bool,
+) -> Option<SearchResult<S, E>> {
+    if let Err((name, err)) = invariant.check(initial_state) {
+        return Some(SearchResult::InvariantViolation(Vec::new(), name, err));
+    }
+    if goal(initial_state) {
+        return Some(SearchResult::GoalFound(initial_state.clone()));
+    }
+    None
+}
+
+fn initial_discovered<S: Eq + Hash, E>(initial_state: S) -> (Discovered<S, E>, SegQueue<Arc<S>>) {
+    let discovered = HashMap::with_hasher(BuildHasherDefault::<FxHasher>::default());
+    let queue = SegQueue::new();
     let initial_state = Arc::new(initial_state);
     queue.push(initial_state.clone());
     discovered
@@ -138,38 +678,84 @@ where
         )
         .map_err(|_| "empty discovered map at initial")
         .unwrap();
+    (discovered, queue)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn breadth_first_internal<S, I, G, P, C, W, D>(
+    discovered: D,
+    queue: SegQueue<Arc<S>>,
+    initial_depth: usize,
+    settings: Settings<I, G, P, C, W>,
+    num_worker: NonZeroUsize,
+    max_duration: impl Into<Option<Duration>>,
+    checkpoint: Option<Checkpoint<D, S>>,
+    trace: impl FnOnce(&D, S) -> Vec<(S::Event, S)>,
+) -> anyhow::Result<SearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash + Send + Sync + 'static,
+    S::Event: Clone + Send + Sync,
+    D: Visited<S, S::Event> + Send + Sync + 'static,
+    I: Invariant<S> + Clone + Send + 'static,
+    G: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Canonicalize<S> + Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
+{
+    // `len` of the discovered map takes linear time, so keep counting for checking `max_states`
+    let num_state = Arc::new(AtomicUsize::new(discovered.len()));
+    // `num_state` when the current depth starts, for `PruneContext::explored_at_depth`
+    let depth_start = Arc::new(AtomicUsize::new(discovered.len()));
+    let discovered = Arc::new(discovered);
+    let queue = Arc::new(queue);
+    let pushing_queue = Arc::new(SegQueue::new());
+    let depth = Arc::new(AtomicUsize::new(initial_depth));
+    let depth_barrier = Arc::new(Barrier::new(num_worker.get()));
+    let search_finished = Arc::new((Mutex::new(None), Condvar::new(), AtomicBool::new(false)));
+    let checkpoint = checkpoint.map(Arc::new);
 
+    let report_progress = settings.progress.clone();
     let result = search_internal(
         max_duration,
-        repeat({
-            let discovered = discovered.clone();
-            let depth = depth.clone();
-            let search_finished = search_finished.clone();
-            move || {
-                breath_first_worker(
-                    settings,
-                    discovered,
-                    queue,
-                    pushing_queue,
-                    depth,
-                    depth_barrier,
-                    search_finished,
-                )
-            }
-        })
-        .take(num_worker.get()),
+        repeat_n(
+            {
+                let discovered = discovered.clone();
+                let num_state = num_state.clone();
+                let depth = depth.clone();
+                let search_finished = search_finished.clone();
+                let checkpoint = checkpoint.clone();
+                move || {
+                    breath_first_worker::<_, _, _, _, _, _, D>(
+                        settings,
+                        discovered,
+                        num_state,
+                        depth_start,
+                        queue,
+                        pushing_queue,
+                        depth,
+                        depth_barrier,
+                        search_finished,
+                        checkpoint,
+                    )
+                }
+            },
+            num_worker.get(),
+        ),
         {
             let discovered = discovered.clone();
+            let checkpoint_path = checkpoint.map(|checkpoint| checkpoint.path.clone());
             move |elapsed| {
-                format!(
-                    "Explored: {}, Depth {} ({:.2}s, {:.2}K states/s)",
-                    discovered.len(),
-                    depth.load(SeqCst),
-                    elapsed.as_secs_f32(),
-                    discovered.len() as f32 / elapsed.as_secs_f32() / 1000.
-                )
+                let explored = discovered.len();
+                SearchProgress {
+                    depth: Some(depth.load(SeqCst)),
+                    checkpoint: checkpoint_path.clone(),
+                    bytes_per_state: resident_bytes()
+                        .map(|bytes| bytes as f32 / explored.max(1) as f32),
+                    ..SearchProgress::new(explored, elapsed)
+                }
             }
         },
+        report_progress,
         search_finished,
     )?;
     // println!("search internal done");
@@ -177,71 +763,485 @@ where
     let Some(result) = result else {
         return Ok(SearchResult::Timeout);
     };
+    // the workers are joined, so this is the only reference
+    let discovered = Arc::into_inner(discovered).unwrap();
     let result = match result {
         SearchWorkerResult::Error(state, event, err) => {
             SearchResult::Err(trace(&discovered, state), event, err)
         }
-        SearchWorkerResult::InvariantViolation(state, err) => {
-            SearchResult::InvariantViolation(trace(&discovered, state), err)
+        SearchWorkerResult::InvariantViolation(state, name, err) => {
+            SearchResult::InvariantViolation(trace(&discovered, state), name, err)
+        }
+        SearchWorkerResult::GoalFound(state) => SearchResult::GoalFound(state),
+        SearchWorkerResult::SpaceExhausted => SearchResult::SpaceExhausted,
+        SearchWorkerResult::StateLimitReached(count) => SearchResult::StateLimitReached(count),
+    };
+    // println!("search exit");
+    Ok(result)
+}
+
+// the discussion above on `S` and `T` also applies here
+// every probe is driven by its own rng seeded from `seed`, and when a probe ends the search, the
+// seed of the probe and the indexes of the chosen events are returned along with the result, so the
+// probe can be reproduced with `replay_random`
+pub fn random_depth_first<S, I, G, P, C, W>(
+    initial_state: S,
+    settings: Settings<I, G, P, C, W>,
+    num_worker: NonZeroUsize,
+    seed: u64,
+    max_duration: impl Into<Option<Duration>>,
+) -> anyhow::Result<RandomSearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash + Send + Sync + 'static,
+    S::Event: Clone + Send + Sync,
+    I: Invariant<S> + Clone + Send + 'static,
+    G: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
+{
+    random_depth_first_internal(
+        initial_state,
+        settings,
+        num_worker,
+        seed,
+        max_duration,
+        false,
+    )
+}
+
+// same as `random_depth_first`, but the probes only record the chosen events instead of cloning
+// every state they step into, and the trace is rebuilt with `replay_random` only when a probe ends
+// the search. much cheaper for large states, while the states along the walk are not available for
+// inspecting until then
+// if the replay diverges (i.e. `events()` is not deterministic) the trace is left empty, and the
+// returned probe tells what was chosen
+pub fn random_depth_first_lazy<S, I, G, P, C, W>(
+    initial_state: S,
+    settings: Settings<I, G, P, C, W>,
+    num_worker: NonZeroUsize,
+    seed: u64,
+    max_duration: impl Into<Option<Duration>>,
+) -> anyhow::Result<RandomSearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash + Send + Sync + 'static,
+    S::Event: Clone + Send + Sync,
+    I: Invariant<S> + Clone + Send + 'static,
+    G: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
+{
+    random_depth_first_internal(
+        initial_state,
+        settings,
+        num_worker,
+        seed,
+        max_duration,
+        true,
+    )
+}
+
+fn random_depth_first_internal<S, I, G, P, C, W>(
+    initial_state: S,
+    settings: Settings<I, G, P, C, W>,
+    num_worker: NonZeroUsize,
+    seed: u64,
+    max_duration: impl Into<Option<Duration>>,
+    lazy_trace: bool,
+) -> anyhow::Result<RandomSearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash + Send + Sync + 'static,
+    S::Event: Clone + Send + Sync,
+    I: Invariant<S> + Clone + Send + 'static,
+    G: Fn(&S) -> bool + Clone + Send + 'static,
+    P: Prune<S> + Clone + Send + 'static,
+    C: Clone + Send + 'static,
+    W: Weight<S::Event> + Clone + Send + 'static,
+{
+    if let Some(result) = check_initial(&initial_state, &settings.invariant, &settings.goal) {
+        return Ok((result, None));
+    }
+    let num_probe = Arc::new(AtomicU32::new(0));
+    let num_state = Arc::new(AtomicU32::new(0));
+    let search_finished = Arc::new((Mutex::new(None), Condvar::new(), AtomicBool::new(false)));
+
+    let report_progress = settings.progress.clone();
+    let result = search_internal(
+        max_duration,
+        (0..num_worker.get() as u64).map({
+            let num_probe = num_probe.clone();
+            let num_state = num_state.clone();
+            let search_finished = search_finished.clone();
+            move |index| {
+                let settings = settings.clone();
+                let initial_state = initial_state.clone();
+                let num_probe = num_probe.clone();
+                let num_state = num_state.clone();
+                let search_finished = search_finished.clone();
+                move || {
+                    random_depth_first_worker(
+                        settings,
+                        initial_state,
+                        StdRng::seed_from_u64(seed.wrapping_add(index)),
+                        num_probe,
+                        num_state,
+                        search_finished,
+                        lazy_trace,
+                    )
+                }
+            }
+        }),
+        move |elapsed| SearchProgress {
+            num_probe: Some(num_probe.load(SeqCst) as _),
+            ..SearchProgress::new(num_state.load(SeqCst) as _, elapsed)
+        },
+        report_progress,
+        search_finished,
+    )?;
+    Ok(result.unwrap_or((SearchResult::Timeout, None)))
+}
+
+pub type RandomSearchResult<S, E> = (SearchResult<S, E>, Option<RandomProbe>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RandomProbe {
+    pub seed: u64,
+    // the index of the chosen event among `events()` on every step
+    pub choices: Vec<usize>,
+}
+
+// replay the probe with `seed` for `choices.len()` steps and return its trace
+// the recorded choices are cross-checked against the ones made by the rng, so an `events()` that
+// does not list events in a deterministic order is detected instead of silently diverging
+// the `weight` must be the same one in the settings of the search
+pub fn replay_random<S>(
+    initial_state: S,
+    seed: u64,
+    choices: &[usize],
+    weight: &impl Weight<S::Event>,
+) -> anyhow::Result<Vec<(S::Event, S)>>
+where
+    S: State + Clone,
+    S::Event: Clone,
+{
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut state = initial_state;
+    let mut trace = Vec::new();
+    for (depth, &choice) in choices.iter().enumerate() {
+        let Some((index, event)) = choose_event(&state, weight, &mut rng) else {
+            anyhow::bail!("no event to choose at step {depth}")
+        };
+        anyhow::ensure!(
+            index == choice,
+            "diverged at step {depth}: choose {index} instead of {choice}"
+        );
+        step(&mut state, event.clone())?;
+        trace.push((event, state.clone()))
+    }
+    Ok(trace)
+}
+
+// return None if there's no event to choose, or every event is of zero weight
+fn choose_event<S: State>(
+    state: &S,
+    weight: &impl Weight<S::Event>,
+    rng: &mut StdRng,
+) -> Option<(usize, S::Event)> {
+    let events = state.events().collect::<Vec<_>>();
+    let index = weight.choose(&events, rng)?;
+    events.into_iter().nth(index).map(|event| (index, event))
+}
+
+// systematic depth first search with iterative deepening
+// memory footprint is bounded by the depth instead of the discovered states, at the cost of
+// revisiting shallower states on every iteration. unlike `random_depth_first` it is complete up to
+// `max_depth`, and like `breadth_first` it reports the shortest trace
+pub fn depth_first<S, I, G, P, C, W>(
+    initial_state: S,
+    settings: Settings<I, G, P, C, W>,
+    max_duration: impl Into<Option<Duration>>,
+) -> anyhow::Result<SearchResult<S, S::Event>>
+where
+    S: State + Clone + Send + 'static,
+    S::Event: Clone + Send,
+    I: Invariant<S> + Send + 'static,
+    G: Fn(&S) -> bool + Send + 'static,
+    P: Prune<S> + Send + 'static,
+    C: Send + 'static,
+    W: Weight<S::Event> + Send + 'static,
+{
+    if let Some(result) = check_initial(&initial_state, &settings.invariant, &settings.goal) {
+        return Ok(result);
+    }
+    let num_state = Arc::new(AtomicUsize::new(0));
+    let depth = Arc::new(AtomicUsize::new(0));
+    let search_finished = Arc::new((Mutex::new(None), Condvar::new(), AtomicBool::new(false)));
+
+    let report_progress = settings.progress.clone();
+    let result = search_internal(
+        max_duration,
+        once({
+            let num_state = num_state.clone();
+            let depth = depth.clone();
+            let search_finished = search_finished.clone();
+            move || depth_first_worker(settings, initial_state, num_state, depth, search_finished)
+        }),
+        move |elapsed| SearchProgress {
+            depth: Some(depth.load(SeqCst)),
+            ..SearchProgress::new(num_state.load(SeqCst), elapsed)
+        },
+        report_progress,
+        search_finished,
+    )?;
+    Ok(result.unwrap_or(SearchResult::Timeout))
+}
+
+// the reachable states and the transitions among them, as discovered by `breadth_first_graph`.
+// `edges` are (source, event, target) indexes into `states`, and `states[0]` is the initial state
+#[derive(Debug, Clone)]
+pub struct StateGraph<S, E> {
+    pub states: Vec<S>,
+    pub edges: Vec<(usize, E, usize)>,
+}
+
+impl<S, E: Debug> StateGraph<S, E> {
+    // Graphviz DOT with the states labeled by `label` and the transitions labeled by the `Debug`
+    // of the events
+    pub fn to_dot(&self, label: impl Fn(&S) -> String) -> String {
+        let mut dot = String::from("digraph {\n");
+        for (index, state) in self.states.iter().enumerate() {
+            // the `Debug` of strings is quoted and escaped in the same way as DOT strings
+            dot += &format!("    {index} [label={:?}];\n", label(state))
+        }
+        for (source, event, target) in &self.edges {
+            dot += &format!(
+                "    {source} -> {target} [label={:?}];\n",
+                format!("{event:?}")
+            )
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+impl<S: Clone, E: Clone> StateGraph<S, E> {
+    // `parents[i]` is the index of the edge that discovers `states[i]`
+    fn trace(&self, parents: &[Option<usize>], mut index: usize) -> Vec<(E, S)> {
+        let mut trace = Vec::new();
+        while let Some(edge) = parents[index] {
+            let (source, event, _) = &self.edges[edge];
+            trace.push((event.clone(), self.states[index].clone()));
+            index = *source
+        }
+        trace.reverse();
+        trace
+    }
+}
+
+pub type GraphSearchResult<S, E> = (SearchResult<S, E>, StateGraph<S, E>);
+
+// a single-threaded breadth first search that also records every transition it takes, for
+// visualizing the state space of toy setups. the stepped states are canonicalized, so a symmetry
+// class is a single node
+// this is only practical for small state spaces, so `max_states` is required, and the graph
+// discovered so far is returned along with `StateLimitReached` when it is reached
+// `weight` and `progress` are not used
+pub fn breadth_first_graph<S, I, G, P, C, W>(
+    initial_state: S,
+    settings: Settings<I, G, P, C, W>,
+) -> anyhow::Result<GraphSearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash,
+    S::Event: Clone,
+    I: Invariant<S>,
+    G: Fn(&S) -> bool,
+    P: Prune<S>,
+    C: Canonicalize<S>,
+{
+    let Some(max_states) = settings.max_states else {
+        anyhow::bail!("max_states is required for recording the state graph")
+    };
+    let initial_state = settings.canonicalize.canonicalize(initial_state);
+    let mut graph = StateGraph {
+        states: vec![initial_state.clone()],
+        edges: Vec::new(),
+    };
+    if let Some(result) = check_initial(&initial_state, &settings.invariant, &settings.goal) {
+        return Ok((result, graph));
+    }
+    let mut indexes = std::collections::HashMap::from([(initial_state, 0)]);
+    // the edge that first discovers each state, for rebuilding traces
+    let mut parents = vec![None];
+    let mut queue = std::collections::VecDeque::from([(0, 0)]);
+    while let Some((index, depth)) = queue.pop_front() {
+        if settings
+            .max_depth
+            .is_some_and(|max_depth| depth >= max_depth.get())
+        {
+            continue;
+        }
+        let events = graph.states[index].events().collect::<Vec<_>>();
+        for event in events {
+            let mut state = graph.states[index].clone();
+            if let Err(err) = step(&mut state, event.clone()) {
+                let result = SearchResult::Err(graph.trace(&parents, index), event, err);
+                return Ok((result, graph));
+            }
+            let state = settings.canonicalize.canonicalize(state);
+            if let Some(&target) = indexes.get(&state) {
+                graph.edges.push((index, event, target));
+                continue;
+            }
+            if graph.states.len() == max_states {
+                return Ok((SearchResult::StateLimitReached(graph.states.len()), graph));
+            }
+            let target = graph.states.len();
+            indexes.insert(state.clone(), target);
+            parents.push(Some(graph.edges.len()));
+            graph.edges.push((index, event, target));
+            graph.states.push(state);
+            let state = &graph.states[target];
+            if let Err((name, err)) = settings.invariant.check(state) {
+                let result =
+                    SearchResult::InvariantViolation(graph.trace(&parents, target), name, err);
+                return Ok((result, graph));
+            }
+            if (settings.goal)(state) {
+                return Ok((SearchResult::GoalFound(state.clone()), graph));
+            }
+            let context = PruneContext {
+                depth: depth + 1,
+                explored: graph.states.len(),
+                explored_at_depth: None,
+            };
+            if !settings.prune.prune(state, &context) {
+                queue.push_back((target, depth + 1))
+            }
         }
-        SearchWorkerResult::GoalFound(state) => SearchResult::GoalFound(state),
-        SearchWorkerResult::SpaceExhausted => SearchResult::SpaceExhausted,
-    };
-    // println!("search exit");
-    Ok(result)
+    }
+    Ok((SearchResult::SpaceExhausted, graph))
 }
 
-// the discussion above on `S` and `T` also applies here
-pub fn random_depth_first<S, I, G, P>(
+// liveness checking with nested depth first search
+// the outer search explores the state space (respecting `max_depth`), and starts an inner search
+// from every discovered state that does not make progress. the inner search only walks through
+// non-progress states, and a lasso is reported if it gets back to a state on its own path
+// the states that are fully explored by some inner search without closing a cycle are remembered
+// and skipped by later inner searches, so each state is inner-searched at most once in total
+// a state that is reached again at a shallower depth is explored again by the outer search, so
+// `max_depth` bounds the shortest path to the states rather than the first one found
+//
+// stuttering steps, i.e. events that lead back to the exact same state, are not considered as
+// cycles. they are typically duplicated deliveries that are ignored by the receiver, which would
+// otherwise trivially violate every progress property. as a consequence, a livelock made of a single
+// self-loop is not reported either, e.g. a resend timer that keeps going off while the resent
+// message is already in the (set) network. only cycles through two or more distinct states are
+pub fn nested_depth_first<S, I, G, P, C, W, Q>(
     initial_state: S,
-    settings: Settings<I, G, P>,
-    num_worker: NonZeroUsize,
+    settings: Settings<I, G, P, C, W>,
+    progress: Q,
     max_duration: impl Into<Option<Duration>>,
 ) -> anyhow::Result<SearchResult<S, S::Event>>
 where
-    S: State + Clone + Eq + Hash + Send + Sync + 'static,
-    S::Event: Clone + Send + Sync,
-    I: Fn(&S) -> anyhow::Result<()> + Clone + Send + 'static,
-    G: Fn(&S) -> bool + Clone + Send + 'static,
-    P: Fn(&S) -> bool + Clone + Send + 'static,
+    S: State + Clone + Eq + Hash + Send + 'static,
+    S::Event: Clone + Send,
+    I: Invariant<S> + Send + 'static,
+    G: Fn(&S) -> bool + Send + 'static,
+    P: Prune<S> + Send + 'static,
+    C: Send + 'static,
+    W: Weight<S::Event> + Send + 'static,
+    Q: Fn(&S) -> bool + Send + 'static,
 {
-    let num_probe = Arc::new(AtomicU32::new(0));
-    let num_state = Arc::new(AtomicU32::new(0));
+    if let Some(result) = check_initial(&initial_state, &settings.invariant, &settings.goal) {
+        return Ok(result);
+    }
+    let num_state = Arc::new(AtomicUsize::new(0));
+    let num_inner_state = Arc::new(AtomicUsize::new(0));
     let search_finished = Arc::new((Mutex::new(None), Condvar::new(), AtomicBool::new(false)));
 
+    let report_progress = settings.progress.clone();
     let result = search_internal(
         max_duration,
-        {
-            let num_probe = num_probe.clone();
+        once({
             let num_state = num_state.clone();
+            let num_inner_state = num_inner_state.clone();
             let search_finished = search_finished.clone();
-            let settings = settings.clone();
-            let initial_state = initial_state.clone();
-            repeat(move || {
-                random_depth_first_worker(
+            move || {
+                nested_depth_first_worker(
                     settings,
+                    progress,
                     initial_state,
-                    num_probe,
                     num_state,
+                    num_inner_state,
                     search_finished,
                 )
-            })
-            .take(num_worker.get())
-        },
-        move |elapsed| {
-            format!(
-                "Explored: {}, Num Probes: {} ({:.2}s, {:.2}K explored/s)",
-                num_state.load(SeqCst),
-                num_probe.load(SeqCst),
-                elapsed.as_secs_f32(),
-                num_state.load(SeqCst) as f32 / elapsed.as_secs_f32() / 1000.
-            )
+            }
+        }),
+        move |elapsed| SearchProgress {
+            inner_explored: Some(num_inner_state.load(SeqCst)),
+            ..SearchProgress::new(num_state.load(SeqCst), elapsed)
         },
+        report_progress,
         search_finished,
     )?;
     Ok(result.unwrap_or(SearchResult::Timeout))
 }
 
+// shrink the trace of an invariant violation by greedily removing chunks of events (halving the
+// chunk size down to single events) as long as the remaining events still lead to a violation
+// an event is only replayed when it is among the `events()` of the replayed state, otherwise an
+// event that is no longer possible, e.g. delivering a message that is not sent anymore, could be
+// applied and produce a bogus trace. that's why `PartialEq` is required for events
+// any invariant violation is accepted during shrinking, which is not necessarily the same one of
+// the original trace
+pub fn minimize_trace<S, I>(
+    initial_state: &S,
+    trace: &[(S::Event, S)],
+    invariant: I,
+) -> Vec<(S::Event, S)>
+where
+    S: State + Clone,
+    S::Event: Clone + PartialEq,
+    I: Invariant<S>,
+{
+    let replay = |events: &[S::Event]| {
+        let mut state = initial_state.clone();
+        let mut trace = Vec::new();
+        for event in events {
+            if !state.events().any(|enabled| enabled == *event) {
+                return None;
+            }
+            step(&mut state, event.clone()).ok()?;
+            trace.push((event.clone(), state.clone()));
+            if invariant.check(&state).is_err() {
+                return Some(trace);
+            }
+        }
+        None
+    };
+    let mut trace = trace.to_vec();
+    let mut chunk_size = trace.len().div_ceil(2);
+    while chunk_size > 0 {
+        let mut start = 0;
+        while start < trace.len() {
+            let end = (start + chunk_size).min(trace.len());
+            let events = trace[..start]
+                .iter()
+                .chain(&trace[end..])
+                .map(|(event, _)| event.clone())
+                .collect::<Vec<_>>();
+            if let Some(shorter_trace) = replay(&events) {
+                trace = shorter_trace
+            } else {
+                start = end
+            }
+        }
+        chunk_size /= 2
+    }
+    trace
+}
+
 fn error_from_panic(err: Box<dyn Any + Send>) -> anyhow::Error {
     if let Ok(err) = err.downcast::<anyhow::Error>() {
         *err
@@ -253,7 +1253,8 @@ fn error_from_panic(err: Box<dyn Any + Send>) -> anyhow::Error {
 fn search_internal<R: Send + 'static, F: FnOnce() + Send + 'static>(
     max_duration: impl Into<Option<Duration>>,
     workers: impl Iterator<Item = F>,
-    status: impl Fn(Duration) -> String + Send + 'static,
+    status: impl Fn(Duration) -> SearchProgress + Send + 'static,
+    progress: Option<ProgressCallback>,
     search_finished: SearchFinished<R>,
 ) -> anyhow::Result<Option<R>>
 where
@@ -266,7 +1267,7 @@ where
     }
     let status_worker = std::thread::spawn({
         let search_finished = search_finished.clone();
-        move || status_worker(status, search_finished)
+        move || status_worker(status, progress, search_finished)
     });
 
     let result = search_finished
@@ -302,7 +1303,18 @@ where
 
 type SearchFinished<R> = Arc<(Mutex<Option<R>>, Condvar, AtomicBool)>;
 
-fn status_worker<R>(status: impl Fn(Duration) -> String, search_finished: SearchFinished<R>) {
+fn status_worker<R>(
+    status: impl Fn(Duration) -> SearchProgress,
+    progress: Option<ProgressCallback>,
+    search_finished: SearchFinished<R>,
+) {
+    let report = |status| {
+        if let Some(progress) = &progress {
+            progress(status)
+        } else {
+            println!("{status}")
+        }
+    };
     let start = Instant::now();
     let mut result = search_finished.0.lock().unwrap();
     let mut wait_result;
@@ -315,9 +1327,9 @@ fn status_worker<R>(status: impl Fn(Duration) -> String, search_finished: Search
             .unwrap();
         wait_result.timed_out()
     } {
-        println!("{}", status(start.elapsed()))
+        report(status(start.elapsed()))
     }
-    println!("{}", status(start.elapsed()))
+    report(status(start.elapsed()))
 }
 
 #[derive_where(Clone; E)]
@@ -329,6 +1341,146 @@ struct StateInfo<S, E> {
 
 type Discovered<S, E> = HashMap<Arc<S>, StateInfo<S, E>, BuildHasherDefault<FxHasher>>;
 
+struct Checkpoint<D, S> {
+    path: PathBuf,
+    interval: Duration,
+    last_saved: Mutex<Instant>,
+    // captured where the states and events are known to be `Serialize`
+    save: SaveCheckpoint<D, S>,
+}
+
+type SaveCheckpoint<D, S> = fn(&Path, &D, &SegQueue<Arc<S>>, usize) -> anyhow::Result<()>;
+
+// states are saved once and referred by index, including in the `prev` links and the frontier
+type CheckpointEntry<S, E> = (S, Option<(E, usize)>, usize);
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointData<S, E> {
+    depth: usize,
+    states: Vec<CheckpointEntry<S, E>>,
+    frontier: Vec<usize>,
+}
+
+impl<D, S> Checkpoint<D, S> {
+    fn save_if_due(&self, discovered: &D, frontier: &SegQueue<Arc<S>>, depth: usize) {
+        let mut last_saved = self.last_saved.lock().unwrap();
+        if last_saved.elapsed() < self.interval {
+            return;
+        }
+        // failing to checkpoint is not fatal to the search itself
+        if let Err(err) = (self.save)(&self.path, discovered, frontier, depth) {
+            warn!(path = %self.path.display(), "failed to save checkpoint: {err}")
+        }
+        *last_saved = Instant::now()
+    }
+}
+
+impl<S, E> Checkpoint<Discovered<S, E>, S> {
+    fn new(path: PathBuf, interval: Duration) -> Self
+    where
+        S: Eq + Hash + Serialize,
+        E: Clone + Serialize,
+    {
+        Self {
+            path,
+            interval,
+            last_saved: Mutex::new(Instant::now()),
+            save: save_checkpoint,
+        }
+    }
+
+    // discovered states, frontier and depth
+    #[allow(clippy::type_complexity)]
+    fn load(&self) -> anyhow::Result<(Discovered<S, E>, SegQueue<Arc<S>>, usize)>
+    where
+        S: Eq + Hash + DeserializeOwned,
+        E: Clone + DeserializeOwned,
+    {
+        let data = bincode::deserialize_from::<_, CheckpointData<S, E>>(BufReader::new(
+            File::open(&self.path)?,
+        ))?;
+        let states = data
+            .states
+            .into_iter()
+            .map(|(state, prev, depth)| (Arc::new(state), prev, depth))
+            .collect::<Vec<_>>();
+        let discovered = HashMap::with_hasher(BuildHasherDefault::<FxHasher>::default());
+        for (state, prev, depth) in &states {
+            let prev = match prev {
+                Some((event, index)) => {
+                    let (prev_state, _, _) = states
+                        .get(*index)
+                        .ok_or(anyhow::format_err!("invalid checkpoint"))?;
+                    Some((event.clone(), prev_state.clone()))
+                }
+                None => None,
+            };
+            discovered
+                .insert(
+                    state.clone(),
+                    StateInfo {
+                        prev,
+                        depth: *depth,
+                    },
+                )
+                .map_err(|_| anyhow::format_err!("duplicated state in checkpoint"))?
+        }
+        let queue = SegQueue::new();
+        for index in data.frontier {
+            let (state, _, _) = states
+                .get(index)
+                .ok_or(anyhow::format_err!("invalid checkpoint"))?;
+            queue.push(state.clone())
+        }
+        Ok((discovered, queue, data.depth))
+    }
+}
+
+fn save_checkpoint<S: Eq + Hash + Serialize, E: Clone + Serialize>(
+    path: &Path,
+    discovered: &Discovered<S, E>,
+    frontier: &SegQueue<Arc<S>>,
+    depth: usize,
+) -> anyhow::Result<()> {
+    let mut entries = Vec::new();
+    discovered.scan(|state, info| entries.push((state.clone(), info.clone())));
+    let indexes = entries
+        .iter()
+        .enumerate()
+        .map(|(index, (state, _))| (state.clone(), index))
+        .collect::<std::collections::HashMap<_, _, BuildHasherDefault<FxHasher>>>();
+    let mut states = Vec::new();
+    for (state, info) in &entries {
+        let prev = (info.prev.as_ref()).map(|(event, prev_state)| (event, indexes[prev_state]));
+        states.push((&**state, prev, info.depth))
+    }
+    let mut frontier_states = Vec::new();
+    while let Some(state) = frontier.pop() {
+        frontier_states.push(state)
+    }
+    let frontier_indexes = frontier_states
+        .iter()
+        .map(|state| indexes[state])
+        .collect::<Vec<_>>();
+    for state in frontier_states {
+        frontier.push(state)
+    }
+    let data = CheckpointData {
+        depth,
+        states,
+        frontier: frontier_indexes,
+    };
+    // write to a temporary file first, so an interruption during saving does not corrupt the
+    // previous checkpoint
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    bincode::serialize_into(&mut writer, &data)?;
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
 fn trace<S: Eq + Hash + Clone, E: Clone>(discovered: &Discovered<S, E>, target: S) -> Vec<(E, S)> {
     let info = discovered.get(&target).unwrap();
     let Some((prev_event, prev_state)) = &info.get().prev else {
@@ -342,27 +1494,132 @@ fn trace<S: Eq + Hash + Clone, E: Clone>(discovered: &Discovered<S, E>, target:
     trace
 }
 
+// the set of discovered states that is shared among the breadth first workers
+trait Visited<S, E> {
+    // return false if the state has been discovered before
+    fn insert_new(&self, state: &Arc<S>, prev: impl FnOnce() -> (E, Arc<S>), depth: usize) -> bool;
+
+    fn len(&self) -> usize;
+}
+
+impl<S: Eq + Hash, E> Visited<S, E> for Discovered<S, E> {
+    fn insert_new(&self, state: &Arc<S>, prev: impl FnOnce() -> (E, Arc<S>), depth: usize) -> bool {
+        let mut inserted = false;
+        self.entry(state.clone()).or_insert_with(|| {
+            inserted = true;
+            StateInfo {
+                prev: Some(prev()),
+                depth,
+            }
+        });
+        inserted
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+type Fingerprints = scc::HashSet<u128, BuildHasherDefault<FxHasher>>;
+
+fn fingerprint<S: Hash>(state: &S) -> u128 {
+    let digest = state.blake2();
+    u128::from_le_bytes(digest[..16].try_into().unwrap())
+}
+
+impl<S: Hash, E> Visited<S, E> for Fingerprints {
+    fn insert_new(&self, state: &Arc<S>, _: impl FnOnce() -> (E, Arc<S>), _: usize) -> bool {
+        self.insert(fingerprint(&**state)).is_ok()
+    }
+
+    fn len(&self) -> usize {
+        scc::HashSet::len(self)
+    }
+}
+
+// find a shortest trace from `initial_state` to `target` by searching breadth-first again, with the
+// fingerprint of each discovered state mapped to the parent's and the event that reaches it, then
+// replaying the events from `initial_state`
+// `target` is a canonical state, and the trace is made of the states before canonicalization
+// the states are not pruned here, as a prune that consults the context may not decide the same as
+// during the search. the trace may pass through the states that the search pruned then, which is
+// still a valid one and no longer than the search found
+fn rebuild_trace<S, C>(initial_state: &S, target: &S, canonicalize: &C) -> Vec<(S::Event, S)>
+where
+    S: State + Clone + Hash,
+    S::Event: Clone,
+    C: Canonicalize<S>,
+{
+    let target = fingerprint(target);
+    let initial = fingerprint(&canonicalize.canonicalize(initial_state.clone()));
+    let mut parents = std::collections::HashMap::<_, _, BuildHasherDefault<FxHasher>>::default();
+    parents.insert(initial, None);
+    let mut queue = std::collections::VecDeque::from([(initial, initial_state.clone())]);
+    while !parents.contains_key(&target) {
+        // the target is known to be reachable, so it is discovered before the queue runs out
+        let (parent, state) = queue.pop_front().unwrap();
+        for event in state.events() {
+            let mut next_state = state.clone();
+            if step(&mut next_state, event.clone()).is_err() {
+                continue;
+            }
+            let next = fingerprint(&canonicalize.canonicalize(next_state.clone()));
+            if parents.contains_key(&next) {
+                continue;
+            }
+            parents.insert(next, Some((parent, event)));
+            if next == target {
+                break;
+            }
+            queue.push_back((next, next_state))
+        }
+    }
+    let mut events = Vec::new();
+    let mut current = target;
+    while let Some((parent, event)) = parents.remove(&current).unwrap() {
+        events.push(event);
+        current = parent
+    }
+    let mut state = initial_state.clone();
+    events
+        .into_iter()
+        .rev()
+        .map(|event| {
+            // the events are replayed on the same states they are collected from
+            step(&mut state, event.clone()).unwrap();
+            (event, state.clone())
+        })
+        .collect()
+}
+
 enum SearchWorkerResult<S, E> {
     Error(S, E, anyhow::Error),
-    InvariantViolation(S, anyhow::Error),
+    InvariantViolation(S, String, anyhow::Error),
     GoalFound(S),
     SpaceExhausted,
+    StateLimitReached(usize),
 }
 
-fn breath_first_worker<S, I, G, P>(
-    settings: Settings<I, G, P>,
-    discovered: Arc<Discovered<S, S::Event>>,
+#[allow(clippy::too_many_arguments)]
+fn breath_first_worker<S, I, G, P, C, W, D>(
+    settings: Settings<I, G, P, C, W>,
+    discovered: Arc<D>,
+    num_state: Arc<AtomicUsize>,
+    depth_start: Arc<AtomicUsize>,
     mut queue: Arc<SegQueue<Arc<S>>>,
     mut pushing_queue: Arc<SegQueue<Arc<S>>>,
     depth: Arc<AtomicUsize>,
     depth_barrier: Arc<Barrier>,
     search_finished: SearchFinished<SearchWorkerResult<S, S::Event>>,
+    checkpoint: Option<Arc<Checkpoint<D, S>>>,
 ) where
     S: State + Clone + Eq + Hash + Send + Sync + 'static,
     S::Event: Clone + Send + Sync,
-    I: Fn(&S) -> anyhow::Result<()>,
+    D: Visited<S, S::Event>,
+    I: Invariant<S>,
     G: Fn(&S) -> bool,
-    P: Fn(&S) -> bool,
+    P: Prune<S>,
+    C: Canonicalize<S>,
     // T: Debug,
     // S::Event: Debug,
 {
@@ -371,10 +1628,9 @@ fn breath_first_worker<S, I, G, P>(
         search_finished.2.store(true, SeqCst);
         search_finished.1.notify_all()
     };
-    for local_depth in 0.. {
+    for local_depth in depth.load(SeqCst).. {
         // println!("start depth {local_depth}");
         'depth: while let Some(state) = queue.pop() {
-            // TODO check initial state
             // println!("check events");
             for event in state.events() {
                 // println!("step {event:?}");
@@ -383,25 +1639,28 @@ fn breath_first_worker<S, I, G, P>(
                     search_finish(SearchWorkerResult::Error(S::clone(&state), event, err));
                     break 'depth;
                 }
-                let next_state = Arc::new(next_state);
+                let next_state = Arc::new(settings.canonicalize.canonicalize(next_state));
                 // do not replace a previously-found state, which may be reached with a shorter
                 // trace from initial state
-                let mut inserted = false;
-                discovered.entry(next_state.clone()).or_insert_with(|| {
-                    inserted = true;
-                    StateInfo {
-                        prev: Some((event, state.clone())),
-                        depth: local_depth + 1,
-                    }
-                });
+                let inserted =
+                    discovered.insert_new(&next_state, || (event, state.clone()), local_depth + 1);
                 // println!("dry state {next_dry_state:?} inserted {inserted}");
                 if !inserted {
                     continue;
                 }
+                let count = num_state.fetch_add(1, SeqCst) + 1;
+                if settings
+                    .max_states
+                    .is_some_and(|max_states| count >= max_states)
+                {
+                    search_finish(SearchWorkerResult::StateLimitReached(count));
+                    break 'depth;
+                }
                 // println!("check invariant");
-                if let Err(err) = (settings.invariant)(&next_state) {
+                if let Err((name, err)) = settings.invariant.check(&next_state) {
                     search_finish(SearchWorkerResult::InvariantViolation(
                         S::clone(&next_state),
+                        name,
                         err,
                     ));
                     break 'depth;
@@ -411,8 +1670,13 @@ fn breath_first_worker<S, I, G, P>(
                     search_finish(SearchWorkerResult::GoalFound(S::clone(&next_state)));
                     break 'depth;
                 }
+                let context = PruneContext {
+                    depth: local_depth + 1,
+                    explored: count,
+                    explored_at_depth: Some(count - depth_start.load(SeqCst)),
+                };
                 if Some(local_depth + 1) != settings.max_depth.map(Into::into)
-                    && !(settings.prune)(&next_state)
+                    && !settings.prune.prune(&next_state, &context)
                 {
                     pushing_queue.push(next_state)
                 }
@@ -423,79 +1687,117 @@ fn breath_first_worker<S, I, G, P>(
         }
         // println!("end depth {local_depth} pushed {}", pushing_queue.len());
 
+        // the workers share the frontier of the depth and pop it until it is drained, so the ones
+        // that run out of work early keep helping with the rest, and only sync up here at the end
+        // of the depth
         // even if the above loop breaks, this wait always traps every worker
         // so that if some worker trap here first, then other worker `search_finish()`, the former
         // worker does not stuck here
         let wait_result = depth_barrier.wait();
         // println!("barrier");
-        if search_finished.2.load(SeqCst) {
-            break;
-        }
-        // println!("continue on next depth");
-
-        if wait_result.is_leader() {
-            depth.store(local_depth + 1, SeqCst);
+        // every other worker is blocked on the second wait below, and no one is consuming the next
+        // frontier yet, so the leader is safe to check it for emptiness, and to drain and refill it
+        // for checkpointing
+        // the decision of whether to continue is published through `depth`, so all workers agree on
+        // it even if the search finishes (e.g. times out) right after the second wait
+        if wait_result.is_leader() && !search_finished.2.load(SeqCst) {
+            if pushing_queue.is_empty() {
+                search_finish(SearchWorkerResult::SpaceExhausted)
+            } else {
+                if let Some(checkpoint) = &checkpoint {
+                    checkpoint.save_if_due(&discovered, &pushing_queue, local_depth + 1)
+                }
+                depth_start.store(num_state.load(SeqCst), SeqCst);
+                depth.store(local_depth + 1, SeqCst)
+            }
         }
-        // one corner case: if some worker happen to perform empty check very late, and by that time
-        // other workers already working on the next depth for a while and have exhausted the queue,
-        // then the late worker will false positive report SpaceExhausted
-        if pushing_queue.is_empty() {
-            search_finish(SearchWorkerResult::SpaceExhausted);
+        // the second wait is unconditional, to not strand the workers that are already waiting
+        // there when the search finishes in between
+        depth_barrier.wait();
+        if depth.load(SeqCst) != local_depth + 1 {
             break;
         }
+        // println!("continue on next depth");
         assert_ne!(Some(local_depth + 1), settings.max_depth.map(Into::into));
-        // i don't want to deal with that seriously, so just slow down the fast wakers a little bit
-        std::thread::sleep(Duration::from_millis(10));
         (queue, pushing_queue) = (pushing_queue, queue)
     }
     // println!("worker exit");
 }
 
-fn random_depth_first_worker<S, I, G, P>(
-    settings: Settings<I, G, P>,
+fn random_depth_first_worker<S, I, G, P, C, W>(
+    settings: Settings<I, G, P, C, W>,
     initial_state: S,
+    mut rng: StdRng,
     num_probe: Arc<AtomicU32>,
     num_state: Arc<AtomicU32>,
-    search_finished: SearchFinished<SearchResult<S, S::Event>>,
+    search_finished: SearchFinished<RandomSearchResult<S, S::Event>>,
+    lazy_trace: bool,
 ) where
     S: State + Clone,
     S::Event: Clone,
-    I: Fn(&S) -> anyhow::Result<()>,
+    I: Invariant<S>,
     G: Fn(&S) -> bool,
-    P: Fn(&S) -> bool,
+    P: Prune<S>,
+    W: Weight<S::Event>,
 {
     let search_finish = |result| {
         search_finished.0.lock().unwrap().get_or_insert(result);
         search_finished.2.store(true, SeqCst);
         search_finished.1.notify_all()
     };
-    let mut rng = thread_rng();
     while !search_finished.2.load(SeqCst) {
         num_probe.fetch_add(1, SeqCst);
+        let seed = rng.gen();
+        let mut probe_rng = StdRng::seed_from_u64(seed);
+        let mut probe = RandomProbe {
+            seed,
+            choices: Vec::new(),
+        };
+        let replay = |choices: &[usize]| {
+            replay_random(initial_state.clone(), seed, choices, &settings.weight)
+                .unwrap_or_default()
+        };
         let mut state = initial_state.clone();
         let mut trace = Vec::new();
-        // TODO check initial state
         for depth in 0.. {
-            let Some(event) = state.events().choose(&mut rng).clone() else {
+            let Some((index, event)) = choose_event(&state, &settings.weight, &mut probe_rng)
+            else {
                 break;
             };
+            probe.choices.push(index);
             if let Err(err) = step(&mut state, event.clone()) {
-                search_finish(SearchResult::Err(trace, event, err));
+                if lazy_trace {
+                    trace = replay(&probe.choices[..probe.choices.len() - 1])
+                }
+                search_finish((SearchResult::Err(trace, event, err), Some(probe)));
                 break;
             }
-            num_state.fetch_add(1, SeqCst);
-            trace.push((event, state.clone()));
-            if let Err(err) = (settings.invariant)(&state) {
-                search_finish(SearchResult::InvariantViolation(trace, err));
+            let explored = num_state.fetch_add(1, SeqCst) + 1;
+            if !lazy_trace {
+                trace.push((event, state.clone()))
+            }
+            if let Err((name, err)) = settings.invariant.check(&state) {
+                if lazy_trace {
+                    trace = replay(&probe.choices)
+                }
+                search_finish((
+                    SearchResult::InvariantViolation(trace, name, err),
+                    Some(probe),
+                ));
                 break;
             }
             // highly unpractical
             // effectively monkey-typing an OSDI paper
             if (settings.goal)(&state) {
-                search_finish(SearchResult::GoalFound(state));
+                search_finish((SearchResult::GoalFound(state), Some(probe)));
                 break;
             }
-            if (settings.prune)(&state)
+            let context = PruneContext {
+                depth: depth + 1,
+                explored: explored as _,
+                explored_at_depth: None,
+            };
+            if settings.prune.prune(&state, &context)
                 || Some(depth + 1) == settings.max_depth.map(Into::into)
                 || search_finished.2.load(SeqCst)
             {
@@ -504,3 +1806,618 @@ fn random_depth_first_worker<S, I, G, P>(
         }
     }
 }
+
+fn depth_first_worker<S, I, G, P, C, W>(
+    settings: Settings<I, G, P, C, W>,
+    initial_state: S,
+    num_state: Arc<AtomicUsize>,
+    depth: Arc<AtomicUsize>,
+    search_finished: SearchFinished<SearchResult<S, S::Event>>,
+) where
+    S: State + Clone,
+    S::Event: Clone,
+    I: Invariant<S>,
+    G: Fn(&S) -> bool,
+    P: Prune<S>,
+{
+    let search_finish = |result| {
+        search_finished.0.lock().unwrap().get_or_insert(result);
+        search_finished.2.store(true, SeqCst);
+        search_finished.1.notify_all()
+    };
+    let events_of = |state: &S| {
+        let mut events = state.events().collect::<Vec<_>>();
+        // so `pop` takes events in the order of `events()`
+        events.reverse();
+        events
+    };
+    for max_depth in 1.. {
+        if settings.max_depth.map(|max| max_depth > max.get()) == Some(true) {
+            search_finish(SearchResult::SpaceExhausted);
+            break;
+        }
+        depth.store(max_depth, SeqCst);
+        // `trace[i]` is the i + 1 step on current path, and `stack[i]` is the events of the
+        // state after `i` steps (i.e. `trace[i - 1].1` or `initial_state`) that are not explored
+        // yet
+        let mut trace = Vec::<(S::Event, S)>::new();
+        let mut stack = vec![events_of(&initial_state)];
+        // whether any state on the frontier can be further stepped, i.e. deepening the bound may
+        // discover more states
+        let mut bounded = false;
+        while let Some(events) = stack.last_mut() {
+            let Some(event) = events.pop() else {
+                stack.pop();
+                trace.pop();
+                continue;
+            };
+            let mut state = trace
+                .last()
+                .map(|(_, state)| state.clone())
+                .unwrap_or_else(|| initial_state.clone());
+            if let Err(err) = step(&mut state, event.clone()) {
+                search_finish(SearchResult::Err(trace, event, err));
+                return;
+            }
+            let state_depth = trace.len() + 1;
+            if state_depth < max_depth {
+                // this state has been checked in previous iterations
+                let context = PruneContext {
+                    depth: state_depth,
+                    explored: num_state.load(SeqCst),
+                    explored_at_depth: None,
+                };
+                if !settings.prune.prune(&state, &context) {
+                    stack.push(events_of(&state));
+                    trace.push((event, state))
+                }
+                continue;
+            }
+            let explored = num_state.fetch_add(1, SeqCst) + 1;
+            trace.push((event, state));
+            let (_, state) = trace.last().unwrap();
+            if let Err((name, err)) = settings.invariant.check(state) {
+                search_finish(SearchResult::InvariantViolation(trace, name, err));
+                return;
+            }
+            if (settings.goal)(state) {
+                let (_, state) = trace.pop().unwrap();
+                search_finish(SearchResult::GoalFound(state));
+                return;
+            }
+            let context = PruneContext {
+                depth: state_depth,
+                explored,
+                explored_at_depth: None,
+            };
+            if !bounded && !settings.prune.prune(state, &context) && state.events().next().is_some()
+            {
+                bounded = true
+            }
+            trace.pop();
+            if search_finished.2.load(SeqCst) {
+                return;
+            }
+        }
+        if !bounded {
+            search_finish(SearchResult::SpaceExhausted);
+            break;
+        }
+    }
+}
+
+type FxHashSet<S> = std::collections::HashSet<S, BuildHasherDefault<FxHasher>>;
+type FxHashMap<K, V> = std::collections::HashMap<K, V, BuildHasherDefault<FxHasher>>;
+
+fn nested_depth_first_worker<S, I, G, P, C, W, Q>(
+    settings: Settings<I, G, P, C, W>,
+    progress: Q,
+    initial_state: S,
+    num_state: Arc<AtomicUsize>,
+    num_inner_state: Arc<AtomicUsize>,
+    search_finished: SearchFinished<SearchResult<S, S::Event>>,
+) where
+    S: State + Clone + Eq + Hash,
+    S::Event: Clone,
+    I: Invariant<S>,
+    G: Fn(&S) -> bool,
+    P: Prune<S>,
+    Q: Fn(&S) -> bool,
+{
+    let search_finish = |result| {
+        search_finished.0.lock().unwrap().get_or_insert(result);
+        search_finished.2.store(true, SeqCst);
+        search_finished.1.notify_all()
+    };
+    let events_of = |state: &S| {
+        let mut events = state.events().collect::<Vec<_>>();
+        events.reverse();
+        events
+    };
+    // discovered state -> the shallowest depth it is reached at
+    let mut discovered = FxHashMap::default();
+    let mut cycle_free = FxHashSet::default();
+    discovered.insert(initial_state.clone(), 0);
+    num_state.fetch_add(1, SeqCst);
+    if !progress(&initial_state) {
+        if let Some(result) = find_lasso(
+            &[],
+            &initial_state,
+            0,
+            &settings.prune,
+            &progress,
+            &mut cycle_free,
+            &num_inner_state,
+        ) {
+            search_finish(result);
+            return;
+        }
+    }
+    let mut trace = Vec::<(S::Event, S)>::new();
+    let mut stack = vec![events_of(&initial_state)];
+    while let Some(events) = stack.last_mut() {
+        let Some(event) = events.pop() else {
+            stack.pop();
+            trace.pop();
+            continue;
+        };
+        let mut state = trace
+            .last()
+            .map(|(_, state)| state.clone())
+            .unwrap_or_else(|| initial_state.clone());
+        if let Err(err) = step(&mut state, event.clone()) {
+            search_finish(SearchResult::Err(trace, event, err));
+            return;
+        }
+        let depth = trace.len() + 1;
+        match discovered.entry(state.clone()) {
+            std::collections::hash_map::Entry::Occupied(entry) if *entry.get() <= depth => continue,
+            // the state may have been cut off by `max_depth` (or a depth-aware prune) when it was
+            // reached deeper, so it is explored again from here
+            std::collections::hash_map::Entry::Occupied(mut entry) => {
+                entry.insert(depth);
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(depth);
+                num_state.fetch_add(1, SeqCst);
+            }
+        }
+        let explored = num_state.load(SeqCst);
+        trace.push((event, state));
+        let (_, state) = trace.last().unwrap();
+        if let Err((name, err)) = settings.invariant.check(state) {
+            search_finish(SearchResult::InvariantViolation(trace, name, err));
+            return;
+        }
+        if (settings.goal)(state) {
+            let (_, state) = trace.pop().unwrap();
+            search_finish(SearchResult::GoalFound(state));
+            return;
+        }
+        if !progress(state) && !cycle_free.contains(state) {
+            let (prefix, [(_, state)]) = trace.split_at(trace.len() - 1) else {
+                unreachable!()
+            };
+            if let Some(result) = find_lasso(
+                prefix,
+                state,
+                trace.len(),
+                &settings.prune,
+                &progress,
+                &mut cycle_free,
+                &num_inner_state,
+            ) {
+                search_finish(result);
+                return;
+            }
+        }
+        let context = PruneContext {
+            depth: trace.len(),
+            explored,
+            explored_at_depth: None,
+        };
+        if settings.prune.prune(state, &context)
+            || Some(trace.len()) == settings.max_depth.map(Into::into)
+        {
+            trace.pop();
+        } else {
+            stack.push(events_of(state))
+        }
+        if search_finished.2.load(SeqCst) {
+            return;
+        }
+    }
+    search_finish(SearchResult::SpaceExhausted)
+}
+
+// the inner search of `nested_depth_first`, walking through non-progress states from `initial_state`
+// (which is reached with `prefix` in `depth` steps) and looking for a cycle
+fn find_lasso<S, P, Q>(
+    prefix: &[(S::Event, S)],
+    initial_state: &S,
+    depth: usize,
+    prune: &P,
+    progress: &Q,
+    cycle_free: &mut FxHashSet<S>,
+    num_inner_state: &AtomicUsize,
+) -> Option<SearchResult<S, S::Event>>
+where
+    S: State + Clone + Eq + Hash,
+    S::Event: Clone,
+    P: Prune<S>,
+    Q: Fn(&S) -> bool,
+{
+    let events_of = |state: &S| {
+        let mut events = state.events().collect::<Vec<_>>();
+        events.reverse();
+        events
+    };
+    let mut path = Vec::<(S::Event, S)>::new();
+    let mut on_path = FxHashSet::default();
+    on_path.insert(initial_state.clone());
+    let mut stack = vec![events_of(initial_state)];
+    while let Some(events) = stack.last_mut() {
+        let state = path.last().map(|(_, state)| state).unwrap_or(initial_state);
+        let Some(event) = events.pop() else {
+            stack.pop();
+            let state = path
+                .pop()
+                .map(|(_, state)| state)
+                .unwrap_or_else(|| initial_state.clone());
+            on_path.remove(&state);
+            cycle_free.insert(state);
+            continue;
+        };
+        let mut next_state = state.clone();
+        if let Err(err) = step(&mut next_state, event.clone()) {
+            return Some(SearchResult::Err([prefix, &path].concat(), event, err));
+        }
+        let context = PruneContext {
+            depth: depth + path.len() + 1,
+            explored: num_inner_state.load(SeqCst),
+            explored_at_depth: None,
+        };
+        if next_state == *state
+            || progress(&next_state)
+            || prune.prune(&next_state, &context)
+            || cycle_free.contains(&next_state)
+        {
+            continue;
+        }
+        if on_path.contains(&next_state) {
+            let cycle_start = path
+                .iter()
+                .position(|(_, state)| *state == next_state)
+                .map(|pos| pos + 1)
+                .unwrap_or(0);
+            let mut cycle = path.split_off(cycle_start);
+            cycle.push((event, next_state));
+            return Some(SearchResult::LivenessViolation(
+                [prefix, &path].concat(),
+                cycle,
+            ));
+        }
+        num_inner_state.fetch_add(1, SeqCst);
+        on_path.insert(next_state.clone());
+        stack.push(events_of(&next_state));
+        path.push((event, next_state))
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // counts up to 3
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct Counter(u8);
+
+    impl SendEvent<()> for Counter {
+        fn send(&mut self, (): ()) -> anyhow::Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    impl State for Counter {
+        type Event = ();
+
+        fn events(&self) -> impl Iterator<Item = Self::Event> + '_ {
+            (self.0 < 3).then_some(()).into_iter()
+        }
+    }
+
+    #[test]
+    fn check_initial_state() -> anyhow::Result<()> {
+        let settings = Settings {
+            invariant: |state: &Counter| {
+                anyhow::ensure!(state.0 != 0);
+                Ok(())
+            },
+            goal: |_: &_| false,
+            prune: |_: &_| false,
+            canonicalize: Identity,
+            weight: Uniform,
+            max_depth: None,
+            max_states: None,
+            progress: None,
+        };
+        let one = NonZeroUsize::new(1).unwrap();
+        let is_violation = |result| matches!(result, SearchResult::InvariantViolation(trace, ..) if trace.is_empty());
+        anyhow::ensure!(is_violation(breadth_first(
+            Counter(0),
+            settings.clone(),
+            one,
+            None
+        )?));
+        anyhow::ensure!(is_violation(depth_first(
+            Counter(0),
+            settings.clone(),
+            None
+        )?));
+        anyhow::ensure!(is_violation(
+            random_depth_first(Counter(0), settings.clone(), one, 0, None)?.0
+        ));
+        // a valid initial state is explored as usual
+        anyhow::ensure!(matches!(
+            breadth_first(Counter(3), settings.clone(), one, None)?,
+            SearchResult::SpaceExhausted
+        ));
+
+        let settings = Settings {
+            invariant: |_: &_| Ok(()),
+            goal: |state: &Counter| state.0 == 0,
+            prune: settings.prune,
+            canonicalize: |state: &Counter| Counter(state.0 % 2),
+            weight: settings.weight,
+            max_depth: None,
+            max_states: None,
+            progress: None,
+        };
+        // checked on the canonical form of the initial state
+        anyhow::ensure!(matches!(
+            breadth_first(Counter(2), settings, one, None)?,
+            SearchResult::GoalFound(Counter(0))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn lazy_trace() -> anyhow::Result<()> {
+        let settings = Settings {
+            invariant: |state: &Counter| {
+                anyhow::ensure!(state.0 != 2);
+                Ok(())
+            },
+            goal: |_: &_| false,
+            prune: |_: &_| false,
+            canonicalize: Identity,
+            weight: Uniform,
+            max_depth: None,
+            max_states: None,
+            progress: None,
+        };
+        let one = NonZeroUsize::new(1).unwrap();
+        let (SearchResult::InvariantViolation(trace, ..), _) =
+            random_depth_first(Counter(0), settings.clone(), one, 0, None)?
+        else {
+            anyhow::bail!("unexpected result")
+        };
+        let (SearchResult::InvariantViolation(lazy_trace, ..), _) =
+            random_depth_first_lazy(Counter(0), settings.clone(), one, 0, None)?
+        else {
+            anyhow::bail!("unexpected result")
+        };
+        let SearchResult::InvariantViolation(rebuilt_trace, ..) =
+            breadth_first_fingerprint(Counter(0), settings, one, None)?
+        else {
+            anyhow::bail!("unexpected result")
+        };
+        anyhow::ensure!(trace == [((), Counter(1)), ((), Counter(2))]);
+        anyhow::ensure!(lazy_trace == trace);
+        anyhow::ensure!(rebuilt_trace == trace);
+        Ok(())
+    }
+
+    #[test]
+    fn state_graph() -> anyhow::Result<()> {
+        let settings = Settings {
+            invariant: |_: &_| Ok(()),
+            goal: |_: &_| false,
+            prune: |_: &_| false,
+            // 0 and 2 are considered symmetric, so the counter loops back
+            canonicalize: |state: &Counter| Counter(state.0 % 2),
+            weight: Uniform,
+            max_depth: None,
+            max_states: Some(10),
+            progress: None,
+        };
+        let (result, graph) = breadth_first_graph(Counter(0), settings.clone())?;
+        anyhow::ensure!(matches!(result, SearchResult::SpaceExhausted));
+        anyhow::ensure!(graph.states == [Counter(0), Counter(1)]);
+        anyhow::ensure!(graph.edges == [(0, (), 1), (1, (), 0)]);
+        anyhow::ensure!(
+            graph.to_dot(|state| format!("count {}", state.0))
+                == "digraph {\n    0 [label=\"count 0\"];\n    1 [label=\"count 1\"];\n    0 -> 1 [label=\"()\"];\n    1 -> 0 [label=\"()\"];\n}\n"
+        );
+        let settings = Settings {
+            invariant: settings.invariant,
+            goal: settings.goal,
+            prune: settings.prune,
+            canonicalize: Identity,
+            weight: settings.weight,
+            max_depth: None,
+            max_states: Some(2),
+            progress: None,
+        };
+        anyhow::ensure!(matches!(
+            breadth_first_graph(Counter(0), settings)?,
+            (SearchResult::StateLimitReached(2), graph) if graph.states.len() == 2
+        ));
+        Ok(())
+    }
+
+    // ticks up to 5, and fires once
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct Clock {
+        ticks: u8,
+        fired: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum ClockEvent {
+        Tick,
+        Fire,
+    }
+
+    impl SendEvent<ClockEvent> for Clock {
+        fn send(&mut self, event: ClockEvent) -> anyhow::Result<()> {
+            match event {
+                ClockEvent::Tick => self.ticks += 1,
+                ClockEvent::Fire => self.fired = true,
+            }
+            Ok(())
+        }
+    }
+
+    impl State for Clock {
+        type Event = ClockEvent;
+
+        fn events(&self) -> impl Iterator<Item = Self::Event> + '_ {
+            (self.ticks < 5)
+                .then_some(ClockEvent::Tick)
+                .into_iter()
+                .chain((!self.fired).then_some(ClockEvent::Fire))
+        }
+    }
+
+    #[test]
+    fn fair() -> anyhow::Result<()> {
+        let settings = |invariant: fn(&Fair<Clock>) -> anyhow::Result<()>| Settings {
+            invariant,
+            goal: |_: &_| false,
+            prune: |_: &_| false,
+            canonicalize: Identity,
+            weight: Uniform,
+            max_depth: None,
+            max_states: None,
+            progress: None,
+        };
+        let starved = |state: &Fair<Clock>| {
+            anyhow::ensure!(state.state.fired || state.state.ticks <= 2);
+            Ok(())
+        };
+        let initial_state = |bound| {
+            Fair::new(
+                Clock {
+                    ticks: 0,
+                    fired: false,
+                },
+                |event| *event == ClockEvent::Fire,
+                bound,
+            )
+        };
+        let one = NonZeroUsize::new(1).unwrap();
+        anyhow::ensure!(matches!(
+            breadth_first(initial_state(2), settings(starved), one, None)?,
+            SearchResult::SpaceExhausted
+        ));
+        // without the bound the fire can be postponed until the clock stops
+        anyhow::ensure!(matches!(
+            breadth_first(initial_state(usize::MAX), settings(starved), one, None)?,
+            SearchResult::InvariantViolation(..)
+        ));
+        Ok(())
+    }
+
+    // walks through a fixed graph
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct Node(u8);
+
+    impl SendEvent<u8> for Node {
+        fn send(&mut self, node: u8) -> anyhow::Result<()> {
+            self.0 = node;
+            Ok(())
+        }
+    }
+
+    impl State for Node {
+        type Event = u8;
+
+        fn events(&self) -> impl Iterator<Item = Self::Event> + '_ {
+            let nodes: &[_] = match self.0 {
+                // 2 is reached at depth 2 through 1 first, then at depth 1
+                0 => &[1, 2],
+                1 => &[2],
+                2 => &[3],
+                // self-loop
+                4 => &[4],
+                5 => &[6],
+                6 => &[5],
+                _ => &[],
+            };
+            nodes.iter().copied()
+        }
+    }
+
+    #[test]
+    fn nested_depth_first_graph() -> anyhow::Result<()> {
+        let settings = |max_depth| Settings {
+            invariant: |_: &_| Ok(()),
+            goal: |state: &Node| state.0 == 3,
+            prune: |_: &_| false,
+            canonicalize: Identity,
+            weight: Uniform,
+            max_depth: NonZeroUsize::new(max_depth),
+            max_states: None,
+            progress: None,
+        };
+        anyhow::ensure!(matches!(
+            nested_depth_first(Node(0), settings(2), |_: &_| true, None)?,
+            SearchResult::GoalFound(Node(3))
+        ));
+        // stuttering is not a cycle
+        anyhow::ensure!(matches!(
+            nested_depth_first(Node(4), settings(0), |_: &_| false, None)?,
+            SearchResult::SpaceExhausted
+        ));
+        let SearchResult::LivenessViolation(prefix, cycle) =
+            nested_depth_first(Node(5), settings(0), |_: &_| false, None)?
+        else {
+            anyhow::bail!("unexpected result")
+        };
+        anyhow::ensure!(prefix.is_empty());
+        anyhow::ensure!(cycle == [(6, Node(6)), (5, Node(5))]);
+        Ok(())
+    }
+
+    #[test]
+    fn prune_with_context() -> anyhow::Result<()> {
+        let settings = Settings {
+            invariant: |_: &_| Ok(()),
+            goal: |state: &Counter| state.0 == 2,
+            prune: WithContext(|_: &_, context: &PruneContext| {
+                assert!(context
+                    .explored_at_depth
+                    .is_none_or(|n| n <= context.explored));
+                context.depth >= 1
+            }),
+            canonicalize: Identity,
+            weight: Uniform,
+            max_depth: None,
+            max_states: None,
+            progress: None,
+        };
+        let one = NonZeroUsize::new(1).unwrap();
+        anyhow::ensure!(matches!(
+            breadth_first(Counter(0), settings.clone(), one, None)?,
+            SearchResult::SpaceExhausted
+        ));
+        anyhow::ensure!(matches!(
+            depth_first(Counter(0), settings, None)?,
+            SearchResult::SpaceExhausted
+        ));
+        Ok(())
+    }
+}
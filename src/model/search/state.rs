@@ -1,10 +1,10 @@
-use std::{collections::BTreeSet, fmt::Debug, time::Duration};
+use std::{collections::BTreeMap, fmt::Debug, time::Duration};
 
 use derive_where::derive_where;
 
 use crate::{
     event::{ActiveTimer, ScheduleEvent, SendEvent},
-    net::events::Cast,
+    net::events::{Cast, ReliableCast},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -61,6 +61,15 @@ impl<M> Schedule<M> {
         self.envelops.push(ticked);
         Ok(())
     }
+
+    pub fn period(&self, id: TimerId) -> Option<Duration> {
+        Some(
+            self.envelops
+                .iter()
+                .find(|envelop| envelop.id == id)?
+                .period,
+        )
+    }
 }
 
 impl<M: Clone> Schedule<M> {
@@ -76,27 +85,251 @@ impl<M: Clone> Schedule<M> {
     }
 }
 
+// the faults that `Network` enumerates besides plain deliveries
+// a network without faults configured keeps every sent message in flight forever, so any message
+// can be delivered arbitrary times, which was the only behavior before. a network with faults
+// configured consumes a message when it is delivered, and optionally allows dropping a message or
+// delivering it while keeping it in flight for up to `max_duplicates` more deliveries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NetworkFaults {
+    pub drop: bool,
+    pub duplicate: bool,
+    pub max_duplicates: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NetworkFault<A, M> {
+    Drop(A, M),
+    // deliver the message and keep it in flight
+    Duplicate(A, M),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[derive_where(Default)]
 pub struct Network<A, M> {
-    messages: BTreeSet<(A, M)>,
+    messages: BTreeMap<(A, M), InFlight>,
+    faults: Option<NetworkFaults>,
+    // the number of rounds that the messages sent to an address are delayed for. a round passes on
+    // every `tick`, which the model is expected to call when a timer goes off, so a message can be
+    // delayed past a timeout. the addresses without a delay receive messages right away
+    delays: BTreeMap<A, u32>,
+    // deliver the messages to each address in the order they are sent, e.g. modeling TCP instead of
+    // UDP. by default any in-flight message can be delivered, and `events()` yields them in the
+    // order of `(A, M)`, which is only an artifact of the message type
+    fifo: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+struct InFlight {
+    // the number of times the message has been duplicated
+    num_duplicate: usize,
+    // the remaining rounds before the message can be delivered
+    // the rounds are counted down instead of compared against a global clock, so the states that
+    // only differ in the elapsed rounds are the same one
+    delay: u32,
+    // the position in the queue of the destination, counting from 0 for the head of the line
+    // only tracked for a FIFO network, and kept as the rank instead of a global sequence number so
+    // the states that only differ in the number of sent messages are the same one
+    seq: usize,
 }
 
 impl<A, M> Network<A, M> {
     pub fn new() -> Self {
         Self::default()
     }
+
+    pub fn with_faults(faults: NetworkFaults) -> Self {
+        Self {
+            faults: Some(faults),
+            ..Self::default()
+        }
+    }
+
+    // a delivered message is consumed even without faults configured, or the head of the line
+    // would block the rest forever
+    pub fn new_fifo() -> Self {
+        Self {
+            fifo: true,
+            ..Self::default()
+        }
+    }
+
+    fn is_deliverable(&self, in_flight: &InFlight) -> bool {
+        in_flight.delay == 0 && (!self.fifo || in_flight.seq == 0)
+    }
+}
+
+impl<A: Ord, M> Network<A, M> {
+    pub fn with_delays(self, delays: impl IntoIterator<Item = (A, u32)>) -> Self {
+        Self {
+            delays: delays.into_iter().collect(),
+            ..self
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for in_flight in self.messages.values_mut() {
+            in_flight.delay = in_flight.delay.saturating_sub(1)
+        }
+    }
 }
 
 impl<A: Ord + Debug, M: Into<N>, N: Ord> SendEvent<Cast<A, M>> for Network<A, N> {
     fn send(&mut self, Cast(remote, message): Cast<A, M>) -> anyhow::Result<()> {
-        self.messages.insert((remote, message.into()));
+        let delay = self.delays.get(&remote).copied().unwrap_or_default();
+        let seq = if self.fifo {
+            self.messages
+                .keys()
+                .filter(|(other_remote, _)| *other_remote == remote)
+                .count()
+        } else {
+            0
+        };
+        // sending an identical message that is still in flight does not reset its duplication
+        // count, its delay or its position, or the duplications can be unbounded
+        self.messages
+            .entry((remote, message.into()))
+            .or_insert(InFlight {
+                num_duplicate: 0,
+                delay,
+                seq,
+            });
         Ok(())
     }
 }
 
+// the model does not tell reliable messages apart, and they are subject to the same faults, which
+// is the conservative choice for checking
+impl<A: Ord + Debug, M: Into<N>, N: Ord> SendEvent<ReliableCast<A, M>> for Network<A, N> {
+    fn send(&mut self, ReliableCast(remote, message): ReliableCast<A, M>) -> anyhow::Result<()> {
+        self.send(Cast(remote, message))
+    }
+}
+
 impl<A: Clone, M: Clone> Network<A, M> {
+    // the messages that are not delayed (anymore), and are at the head of the line for a FIFO
+    // network, in the order of `(A, M)`
     pub fn events(&self) -> impl Iterator<Item = (A, M)> + '_ {
-        self.messages.iter().cloned()
+        self.messages
+            .iter()
+            .filter(|(_, in_flight)| self.is_deliverable(in_flight))
+            .map(|(key, _)| key.clone())
+    }
+
+    pub fn fault_events(&self) -> impl Iterator<Item = NetworkFault<A, M>> + '_ {
+        let faults = self.faults.unwrap_or_default();
+        // a delayed message (or one behind the head of the line) can be dropped but not
+        // duplicated, which delivers it
+        self.messages
+            .iter()
+            .flat_map(move |((remote, message), in_flight)| {
+                let drop = Some(NetworkFault::Drop(remote.clone(), message.clone()))
+                    .filter(|_| faults.drop);
+                let duplicate = Some(NetworkFault::Duplicate(remote.clone(), message.clone()))
+                    .filter(|_| {
+                        faults.duplicate
+                            && self.is_deliverable(in_flight)
+                            && in_flight.num_duplicate < faults.max_duplicates
+                    });
+                drop.into_iter().chain(duplicate)
+            })
+    }
+}
+
+impl<A: Ord, M: Ord> Network<A, M> {
+    // rewrite every in-flight message, e.g. relabeling the nodes for symmetry reduction
+    // the delays are kept as is, which is expected to be symmetric for the relabeled addresses
+    pub fn map(self, mut f: impl FnMut(A, M) -> (A, M)) -> Self {
+        Self {
+            messages: self
+                .messages
+                .into_iter()
+                .map(|((remote, message), in_flight)| (f(remote, message), in_flight))
+                .collect(),
+            ..self
+        }
+    }
+}
+
+impl<A: Ord + Debug, M: Ord + Debug> Network<A, M> {
+    // to be called when a message from `events()` is delivered
+    pub fn deliver(&mut self, remote: A, message: M) -> anyhow::Result<()> {
+        let key = (remote, message);
+        let Some(in_flight) = self.messages.get(&key) else {
+            anyhow::bail!("delivering message not in flight")
+        };
+        anyhow::ensure!(in_flight.delay == 0, "delivering delayed message");
+        anyhow::ensure!(self.is_deliverable(in_flight), "delivering out of order");
+        if self.faults.is_some() || self.fifo {
+            self.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &(A, M)) -> Option<InFlight> {
+        let removed = self.messages.remove(key)?;
+        if self.fifo {
+            for ((remote, _), in_flight) in &mut self.messages {
+                if *remote == key.0 && in_flight.seq > removed.seq {
+                    in_flight.seq -= 1
+                }
+            }
+        }
+        Some(removed)
+    }
+
+    // return the message to be delivered, if any
+    pub fn fault(&mut self, fault: NetworkFault<A, M>) -> anyhow::Result<Option<(A, M)>> {
+        let faults = self.faults.unwrap_or_default();
+        match fault {
+            NetworkFault::Drop(remote, message) => {
+                anyhow::ensure!(faults.drop, "dropping is not enabled");
+                let Some(_) = self.remove(&(remote, message)) else {
+                    anyhow::bail!("dropping message not in flight")
+                };
+                Ok(None)
+            }
+            NetworkFault::Duplicate(remote, message) => {
+                anyhow::ensure!(faults.duplicate, "duplicating is not enabled");
+                let key = (remote, message);
+                let Some(in_flight) = self.messages.get_mut(&key) else {
+                    anyhow::bail!("duplicating message not in flight")
+                };
+                anyhow::ensure!(in_flight.delay == 0, "duplicating delayed message");
+                anyhow::ensure!(!self.fifo || in_flight.seq == 0, "duplicating out of order");
+                anyhow::ensure!(
+                    in_flight.num_duplicate < faults.max_duplicates,
+                    "too many duplications"
+                );
+                in_flight.num_duplicate += 1;
+                Ok(Some(key))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo() -> anyhow::Result<()> {
+        let mut network = Network::<u8, u8>::new_fifo();
+        for message in [3, 1, 2] {
+            network.send(Cast(0, message))?
+        }
+        network.send(Cast(1, 0))?;
+        anyhow::ensure!(network.events().eq([(0, 3), (1, 0)]));
+        anyhow::ensure!(network.deliver(0, 1).is_err());
+        network.deliver(0, 3)?;
+        anyhow::ensure!(network.events().eq([(0, 1), (1, 0)]));
+        // the same in-flight messages in the same order, regardless of the sending history
+        let mut other = Network::new_fifo();
+        for message in [1, 2] {
+            other.send(Cast(0, message))?
+        }
+        other.send(Cast(1, 0))?;
+        anyhow::ensure!(network == other);
+        Ok(())
     }
 }
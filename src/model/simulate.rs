@@ -8,7 +8,7 @@ use derive_where::derive_where;
 
 use crate::{
     event::{ActiveTimer, ScheduleEvent, SendEvent},
-    net::events::Cast,
+    net::events::{Cast, ReliableCast},
 };
 
 #[derive(Debug, Display, Error)]
@@ -109,3 +109,9 @@ impl<A, M: Into<N>, N> SendEvent<Cast<A, M>> for NetworkState<A, N> {
         Ok(())
     }
 }
+
+impl<A, M: Into<N>, N> SendEvent<ReliableCast<A, M>> for NetworkState<A, N> {
+    fn send(&mut self, ReliableCast(remote, message): ReliableCast<A, M>) -> anyhow::Result<()> {
+        self.send(Cast(remote, message))
+    }
+}
@@ -7,6 +7,10 @@ use crate::event::SendEvent;
 
 pub mod combinators;
 pub mod task {
+    pub mod channel;
+    #[cfg(feature = "quic")]
+    pub mod quic;
+    pub mod tcp;
     pub mod udp;
 }
 
@@ -16,6 +20,10 @@ pub mod events {
     #[derive(Debug)]
     pub struct Cast<A, M>(pub A, pub M);
 
+    // a `Cast` that is not recovered by resending on loss, see `SendMessageReliable`
+    #[derive(Debug)]
+    pub struct ReliableCast<A, M>(pub A, pub M);
+
     #[derive(Debug)]
     pub struct Recv<M>(pub M);
 }
@@ -30,6 +38,21 @@ impl<E: SendEvent<events::Cast<A, M>>, A, M> SendMessage<A, M> for E {
     }
 }
 
+// for the messages that the sender does not resend on loss, e.g. state transfer responses which are
+// sent once per query, as opposed to client requests which are resent on timeout
+// a transport delivers these as reliably as it can (the connection-based TCP and QUIC transports
+// reconnect instead of dropping them), or in the same way as `SendMessage` if it is best effort
+// anyway (UDP), so a protocol states the requirement without picking the transport
+pub trait SendMessageReliable<A, M> {
+    fn send_reliable(&mut self, remote: A, message: M) -> anyhow::Result<()>;
+}
+
+impl<E: SendEvent<events::ReliableCast<A, M>>, A, M> SendMessageReliable<A, M> for E {
+    fn send_reliable(&mut self, remote: A, message: M) -> anyhow::Result<()> {
+        SendEvent::send(self, events::ReliableCast(remote, message))
+    }
+}
+
 pub trait Addr:
     Debug + Clone + Eq + Ord + Hash + Serialize + DeserializeOwned + Send + Sync + 'static
 {
@@ -1,8 +1,20 @@
-use bytes::Bytes;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use bytes::{Buf as _, BufMut as _, Bytes, BytesMut};
+use derive_more::{Display, Error};
+use derive_where::derive_where;
+use rand::Rng;
 
 use crate::event::SendEvent;
 
-use super::{events::Cast, Addr};
+use super::{
+    events::{Cast, Recv, ReliableCast},
+    Addr,
+};
 
 #[derive(Debug)]
 pub struct Forward<A, N>(pub A, pub N);
@@ -21,6 +33,8 @@ pub struct IndexNet<A, N> {
     addrs: Vec<A>,
     all_except: Option<usize>,
     inner: N,
+    // the index of the next `round_robin_send` destination
+    next: usize,
 }
 
 impl<A, N> IndexNet<A, N> {
@@ -29,23 +43,69 @@ impl<A, N> IndexNet<A, N> {
             addrs,
             all_except: all_except.into(),
             inner: net,
+            next: 0,
         }
     }
+
+    // replace the routing table for reconfiguration. the sends after this resolve indexes against
+    // the new members, and an index that is beyond the new members (e.g. after shrinking) fails the
+    // send instead of reaching a stale address
+    // `all_except` still refers to the same index, so it should be kept valid by the caller
+    pub fn update_members(&mut self, addrs: Vec<A>) {
+        self.addrs = addrs
+    }
+
+    fn addr(&self, index: usize) -> anyhow::Result<&A> {
+        self.addrs.get(index).ok_or(anyhow::format_err!(
+            "index {index} out of {} members",
+            self.addrs.len()
+        ))
+    }
+}
+
+// spreading the messages across the members, e.g. the requests that any replica can serve
+// `all_except` is not excluded from the destinations
+impl<A: Addr, N> IndexNet<A, N> {
+    // the destination cycles through the members in index order on every call
+    pub fn round_robin_send<M>(&mut self, message: M) -> anyhow::Result<()>
+    where
+        N: SendEvent<Cast<A, M>>,
+    {
+        anyhow::ensure!(!self.addrs.is_empty(), "no member");
+        // the members may have shrunk since the last call
+        let index = self.next % self.addrs.len();
+        self.next = index + 1;
+        self.inner.send(Cast(self.addrs[index].clone(), message))
+    }
+
+    pub fn send_random<M>(&mut self, message: M, rng: &mut impl Rng) -> anyhow::Result<()>
+    where
+        N: SendEvent<Cast<A, M>>,
+    {
+        anyhow::ensure!(!self.addrs.is_empty(), "no member");
+        let index = rng.gen_range(0..self.addrs.len());
+        self.inner.send(Cast(self.addrs[index].clone(), message))
+    }
 }
 
 impl<A: Addr, N: SendEvent<Cast<A, M>>, M, I: Into<usize>> SendEvent<Cast<I, M>>
     for IndexNet<A, N>
 {
     fn send(&mut self, Cast(index, message): Cast<I, M>) -> anyhow::Result<()> {
-        let index = index.into();
-        let addr = self
-            .addrs
-            .get(index)
-            .ok_or(anyhow::format_err!("missing address of index {index}"))?;
+        let addr = self.addr(index.into())?;
         self.inner.send(Cast(addr.clone(), message))
     }
 }
 
+impl<A: Addr, N: SendEvent<ReliableCast<A, M>>, M, I: Into<usize>> SendEvent<ReliableCast<I, M>>
+    for IndexNet<A, N>
+{
+    fn send(&mut self, ReliableCast(index, message): ReliableCast<I, M>) -> anyhow::Result<()> {
+        let addr = self.addr(index.into())?;
+        self.inner.send(ReliableCast(addr.clone(), message))
+    }
+}
+
 impl<A: Addr, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<All, Bytes>> for IndexNet<A, N> {
     fn send(&mut self, Cast(All, message): Cast<All, Bytes>) -> anyhow::Result<()> {
         for (index, addr) in self.addrs.iter().enumerate() {
@@ -57,3 +117,571 @@ impl<A: Addr, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<All, Bytes>> for Inde
         Ok(())
     }
 }
+
+// length-delimited framing for stream transports, where each message is prefixed with its length
+// as a big-endian u32
+// on the sending side it wraps a `Bytes` net and prefixes every outgoing message. on the receiving
+// side it is fed with whatever the stream yields, and passes complete frames to the inner sender,
+// so the receiving side needs one instance per stream
+// the TCP and QUIC transports frame with `frame` and read with this
+#[derive(Debug)]
+pub struct Framed<N> {
+    inner: N,
+    buf: BytesMut,
+}
+
+impl<N> Framed<N> {
+    pub fn new(inner: N) -> Self {
+        Self {
+            inner,
+            buf: Default::default(),
+        }
+    }
+}
+
+// reject (and disconnect) on a corrupted or hostile length prefix instead of buffering whatever it
+// says, up to 4 GiB
+pub const MAX_FRAME_LEN: usize = 64 << 20;
+
+pub fn frame(message: &[u8]) -> anyhow::Result<Bytes> {
+    anyhow::ensure!(message.len() <= MAX_FRAME_LEN, "message too large");
+    let mut frame = BytesMut::with_capacity(4 + message.len());
+    frame.put_u32(message.len() as _);
+    frame.put_slice(message);
+    Ok(frame.freeze())
+}
+
+impl<A, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<A, Bytes>> for Framed<N> {
+    fn send(&mut self, Cast(remote, message): Cast<A, Bytes>) -> anyhow::Result<()> {
+        self.inner.send(Cast(remote, frame(&message)?))
+    }
+}
+
+impl<N: SendEvent<Recv<Bytes>>> SendEvent<Recv<Bytes>> for Framed<N> {
+    fn send(&mut self, Recv(buf): Recv<Bytes>) -> anyhow::Result<()> {
+        self.buf.extend_from_slice(&buf);
+        while let Some(len) = self.buf.get(..4) {
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            anyhow::ensure!(len <= MAX_FRAME_LEN, "frame too large");
+            if self.buf.len() < 4 + len {
+                break;
+            }
+            self.buf.advance(4);
+            let frame = self.buf.split_to(len).freeze();
+            self.inner.send(Recv(frame))?
+        }
+        Ok(())
+    }
+}
+
+// broadcast to a fixed address set for any cloneable message, the generic counterpart of the
+// `All` case of `IndexNet` that does not require messages to be encoded first
+// unicast messages are passed through as is
+#[derive(Debug)]
+pub struct Multicast<A, N> {
+    addrs: Vec<A>,
+    self_index: Option<usize>,
+    inner: N,
+}
+
+impl<A, N> Multicast<A, N> {
+    pub fn new(addrs: Vec<A>, self_index: impl Into<Option<usize>>, net: N) -> Self {
+        Self {
+            addrs,
+            self_index: self_index.into(),
+            inner: net,
+        }
+    }
+}
+
+impl<A: Addr, N: SendEvent<Cast<A, M>>, M> SendEvent<Cast<A, M>> for Multicast<A, N> {
+    fn send(&mut self, Cast(remote, message): Cast<A, M>) -> anyhow::Result<()> {
+        self.inner.send(Cast(remote, message))
+    }
+}
+
+impl<A: Addr, N: SendEvent<ReliableCast<A, M>>, M> SendEvent<ReliableCast<A, M>>
+    for Multicast<A, N>
+{
+    fn send(&mut self, ReliableCast(remote, message): ReliableCast<A, M>) -> anyhow::Result<()> {
+        self.inner.send(ReliableCast(remote, message))
+    }
+}
+
+impl<A: Clone, N: SendEvent<Cast<A, M>>, M: Clone> SendEvent<Cast<All, M>> for Multicast<A, N> {
+    fn send(&mut self, Cast(All, message): Cast<All, M>) -> anyhow::Result<()> {
+        for (index, addr) in self.addrs.iter().enumerate() {
+            if Some(index) == self.self_index {
+                continue;
+            }
+            self.inner.send(Cast(addr.clone(), message.clone()))?
+        }
+        Ok(())
+    }
+}
+
+// observe every outgoing message without touching protocol code, e.g. for logging, counting or
+// capturing for replay. the observer sees each message before it reaches the inner net, and errors
+// of the inner net are passed through as is
+// wrap it outside `Encode` to observe typed messages, or inside to observe the bytes, and under
+// `IndexNet` the observer sees the resolved addresses
+#[derive(Debug)]
+pub struct Tap<N, F> {
+    inner: N,
+    observe: F,
+}
+
+impl<N, F> Tap<N, F> {
+    pub fn new(net: N, observe: F) -> Self {
+        Self {
+            inner: net,
+            observe,
+        }
+    }
+}
+
+impl<A, M, N: SendEvent<Cast<A, M>>, F: FnMut(&A, &M)> SendEvent<Cast<A, M>> for Tap<N, F> {
+    fn send(&mut self, Cast(remote, message): Cast<A, M>) -> anyhow::Result<()> {
+        (self.observe)(&remote, &message);
+        self.inner.send(Cast(remote, message))
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub struct RateLimited;
+
+// token bucket per destination, refilled at `rate` bytes per second up to `burst` bytes
+// a message that exceeds the remaining tokens is dropped with `RateLimited` error instead of
+// delayed, since there's no timer at this layer. caller that prefers silent drop checks for it with
+// `err.is::<RateLimited>()`
+#[derive(Debug)]
+pub struct RateLimit<A, N> {
+    inner: N,
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<A, (f64, Instant)>, // (tokens, last refill)
+}
+
+impl<A, N> RateLimit<A, N> {
+    pub fn new(net: N, rate: u64, burst: u64) -> Self {
+        Self {
+            inner: net,
+            rate: rate as _,
+            burst: burst as _,
+            buckets: Default::default(),
+        }
+    }
+}
+
+impl<A: Addr, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<A, Bytes>> for RateLimit<A, N> {
+    fn send(&mut self, Cast(remote, message): Cast<A, Bytes>) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let (tokens, refilled) = self
+            .buckets
+            .entry(remote.clone())
+            .or_insert((self.burst, now));
+        *tokens = (*tokens + (now - *refilled).as_secs_f64() * self.rate).min(self.burst);
+        *refilled = now;
+        let len = message.len() as f64;
+        anyhow::ensure!(len <= *tokens, RateLimited);
+        *tokens -= len;
+        self.inner.send(Cast(remote, message))
+    }
+}
+
+// zstd-compress the encoded messages of at least `threshold` bytes, e.g. the PrePrepare batches
+// every message is prefixed with a one-byte marker of whether it is compressed, so the receiving
+// side must unwrap the messages with `decompress` before decoding, regardless of the threshold
+// wrap it directly inside `Encode` so protocol code is unaware of it
+// requires the `zstd` feature
+#[cfg(feature = "zstd")]
+#[derive(Debug)]
+pub struct Compress<N> {
+    inner: N,
+    threshold: usize,
+}
+
+#[cfg(feature = "zstd")]
+const UNCOMPRESSED: u8 = 0;
+#[cfg(feature = "zstd")]
+const COMPRESSED: u8 = 1;
+
+#[cfg(feature = "zstd")]
+impl<N> Compress<N> {
+    pub fn new(inner: N, threshold: usize) -> Self {
+        Self { inner, threshold }
+    }
+
+    fn compress(&self, message: Bytes) -> anyhow::Result<Bytes> {
+        let mut buf = BytesMut::with_capacity(1 + message.len());
+        if message.len() < self.threshold {
+            buf.put_u8(UNCOMPRESSED);
+            buf.put_slice(&message)
+        } else {
+            buf.put_u8(COMPRESSED);
+            buf.put_slice(&zstd::bulk::compress(&message, 0)?)
+        }
+        Ok(buf.freeze())
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<A, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<A, Bytes>> for Compress<N> {
+    fn send(&mut self, Cast(remote, message): Cast<A, Bytes>) -> anyhow::Result<()> {
+        let message = self.compress(message)?;
+        self.inner.send(Cast(remote, message))
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<A, N: SendEvent<ReliableCast<A, Bytes>>> SendEvent<ReliableCast<A, Bytes>> for Compress<N> {
+    fn send(
+        &mut self,
+        ReliableCast(remote, message): ReliableCast<A, Bytes>,
+    ) -> anyhow::Result<()> {
+        let message = self.compress(message)?;
+        self.inner.send(ReliableCast(remote, message))
+    }
+}
+
+// the receiving counterpart of `Compress`, wraps the decode function e.g. `to_replica_decode`
+// `max_len` bounds the decompressed size, so a malicious message cannot exhaust the memory
+#[cfg(feature = "zstd")]
+pub fn decompress<'a>(
+    max_len: usize,
+    mut on_buf: impl FnMut(&[u8]) -> anyhow::Result<()> + 'a,
+) -> impl FnMut(&[u8]) -> anyhow::Result<()> + 'a {
+    move |buf| match buf.split_first() {
+        Some((&UNCOMPRESSED, message)) => on_buf(message),
+        Some((&COMPRESSED, message)) => on_buf(&zstd::bulk::decompress(message, max_len)?),
+        _ => anyhow::bail!("unexpected compression marker"),
+    }
+}
+
+// host multiple independent protocol instances (shards) behind the same addresses, e.g. several PBFT
+// groups in one process sharing one socket. the effective address of a node is the (shard id,
+// address) pair: every message is prefixed with the shard id of the sending instance, and the
+// receiving side dispatches it to the instance of the same shard with `demux_shards`
+#[derive(Debug, Clone)]
+pub struct ShardedNet<N> {
+    inner: N,
+    shard: u8,
+}
+
+impl<N> ShardedNet<N> {
+    pub fn new(inner: N, shard: u8) -> Self {
+        Self { inner, shard }
+    }
+
+    fn prefix(&self, message: Bytes) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1 + message.len());
+        buf.put_u8(self.shard);
+        buf.put_slice(&message);
+        buf.freeze()
+    }
+}
+
+impl<A, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<A, Bytes>> for ShardedNet<N> {
+    fn send(&mut self, Cast(remote, message): Cast<A, Bytes>) -> anyhow::Result<()> {
+        let message = self.prefix(message);
+        self.inner.send(Cast(remote, message))
+    }
+}
+
+impl<A, N: SendEvent<ReliableCast<A, Bytes>>> SendEvent<ReliableCast<A, Bytes>> for ShardedNet<N> {
+    fn send(
+        &mut self,
+        ReliableCast(remote, message): ReliableCast<A, Bytes>,
+    ) -> anyhow::Result<()> {
+        let message = self.prefix(message);
+        self.inner.send(ReliableCast(remote, message))
+    }
+}
+
+// `on_bufs[i]` receives the messages of shard `i`, typically the decode functions of the instances
+pub fn demux_shards(
+    mut on_bufs: Vec<impl FnMut(&[u8]) -> anyhow::Result<()>>,
+) -> impl FnMut(&[u8]) -> anyhow::Result<()> {
+    move |buf| {
+        let Some((&shard, message)) = buf.split_first() else {
+            anyhow::bail!("missing shard id")
+        };
+        let Some(on_buf) = on_bufs.get_mut(shard as usize) else {
+            anyhow::bail!("unknown shard {shard}")
+        };
+        on_buf(message)
+    }
+}
+
+// drop the messages to and from the blocked addresses of a live deployment, for injecting network
+// partitions at runtime e.g. to trigger view changes over a real transport
+// wrap the net of every node with its own address and a clone of the same `PartitionHandle`, so a
+// message is dropped if either its destination or the sending node is blocked. the receiving side
+// is untouched, so the messages that are already in flight are still delivered
+// the drop is silent, in the same way as an actual partition
+#[derive(Debug)]
+pub struct Partition<A, N> {
+    inner: N,
+    local: A,
+    handle: PartitionHandle<A>,
+}
+
+#[derive(Debug, Clone)]
+#[derive_where(Default)]
+pub struct PartitionHandle<A>(Arc<Mutex<HashSet<A>>>);
+
+impl<A: Addr> PartitionHandle<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block(&self, addr: A) {
+        self.0.lock().unwrap().insert(addr);
+    }
+
+    pub fn unblock(&self, addr: &A) {
+        self.0.lock().unwrap().remove(addr);
+    }
+
+    pub fn heal(&self) {
+        self.0.lock().unwrap().clear()
+    }
+
+    fn is_blocked(&self, local: &A, remote: &A) -> bool {
+        let blocked = self.0.lock().unwrap();
+        blocked.contains(local) || blocked.contains(remote)
+    }
+}
+
+impl<A, N> Partition<A, N> {
+    pub fn new(net: N, local: A, handle: PartitionHandle<A>) -> Self {
+        Self {
+            inner: net,
+            local,
+            handle,
+        }
+    }
+}
+
+impl<A: Addr, N: SendEvent<Cast<A, M>>, M> SendEvent<Cast<A, M>> for Partition<A, N> {
+    fn send(&mut self, Cast(remote, message): Cast<A, M>) -> anyhow::Result<()> {
+        if self.handle.is_blocked(&self.local, &remote) {
+            return Ok(());
+        }
+        self.inner.send(Cast(remote, message))
+    }
+}
+
+impl<A: Addr, N: SendEvent<ReliableCast<A, M>>, M> SendEvent<ReliableCast<A, M>>
+    for Partition<A, N>
+{
+    fn send(&mut self, ReliableCast(remote, message): ReliableCast<A, M>) -> anyhow::Result<()> {
+        if self.handle.is_blocked(&self.local, &remote) {
+            return Ok(());
+        }
+        self.inner.send(ReliableCast(remote, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "zstd")]
+    use crate::codec::{bincode, Encode};
+    use crate::event::combinators::Transient;
+
+    use super::*;
+
+    #[test]
+    fn framed() -> anyhow::Result<()> {
+        let mut sender = Framed::new(Transient::<Cast<(), Bytes>>::new());
+        for message in ["hello", "", "world"] {
+            sender.send(Cast((), Bytes::from(message)))?
+        }
+        let stream = sender
+            .inner
+            .iter()
+            .flat_map(|Cast((), frame)| frame.clone())
+            .collect::<Vec<_>>();
+
+        // multiple messages in one read
+        let mut receiver = Framed::new(Transient::<Recv<Bytes>>::new());
+        receiver.send(Recv(Bytes::from(stream.clone())))?;
+        let messages = receiver.inner.iter().map(|Recv(message)| &message[..]);
+        anyhow::ensure!(messages.eq([&b"hello"[..], b"", b"world"]));
+
+        // messages spanning multiple reads
+        let mut receiver = Framed::new(Transient::<Recv<Bytes>>::new());
+        for chunk in stream.chunks(3) {
+            receiver.send(Recv(Bytes::copy_from_slice(chunk)))?
+        }
+        let messages = receiver.inner.iter().map(|Recv(message)| &message[..]);
+        anyhow::ensure!(messages.eq([&b"hello"[..], b"", b"world"]));
+        anyhow::ensure!(receiver.buf.is_empty());
+
+        // the length prefix is checked before the frame is buffered
+        let mut receiver = Framed::new(Transient::<Recv<Bytes>>::new());
+        let len = (MAX_FRAME_LEN as u32 + 1).to_be_bytes();
+        anyhow::ensure!(receiver.send(Recv(Bytes::copy_from_slice(&len))).is_err());
+        anyhow::ensure!(frame(&vec![0; MAX_FRAME_LEN + 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn update_members() -> anyhow::Result<()> {
+        let mut net = IndexNet::new(vec![1u8, 2, 3], None, Transient::<Cast<u8, ()>>::new());
+        net.send(Cast(2u8, ()))?;
+        net.update_members(vec![4, 5]);
+        net.send(Cast(1u8, ()))?;
+        let Err(err) = net.send(Cast(2u8, ())) else {
+            anyhow::bail!("unexpected Ok")
+        };
+        anyhow::ensure!(err.to_string() == "index 2 out of 2 members");
+        anyhow::ensure!(net.inner.iter().map(|Cast(addr, ())| *addr).eq([3, 5]));
+        Ok(())
+    }
+
+    #[test]
+    fn round_robin() -> anyhow::Result<()> {
+        let mut net = IndexNet::new(vec![1u8, 2, 3], None, Transient::<Cast<u8, ()>>::new());
+        for _ in 0..4 {
+            net.round_robin_send(())?
+        }
+        // continues from the next index among the new members
+        net.update_members(vec![4, 5]);
+        for _ in 0..2 {
+            net.round_robin_send(())?
+        }
+        anyhow::ensure!(net
+            .inner
+            .iter()
+            .map(|Cast(addr, ())| *addr)
+            .eq([1, 2, 3, 1, 5, 4]));
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compress() -> anyhow::Result<()> {
+        // a batch of similar requests, as in a PrePrepare
+        let batch = (0..100u32)
+            .map(|seq| (seq, Bytes::from(vec![(seq % 4) as u8; 100])))
+            .collect::<Vec<_>>();
+        let mut net = Encode::<Vec<(u32, Bytes)>, _>::bincode(Compress::new(
+            Transient::<Cast<u8, Bytes>>::new(),
+            1 << 10,
+        ));
+        net.send(Cast(0, batch.clone()))?;
+        net.send(Cast(0, batch[..1].to_vec()))?;
+        let [Cast(_, compressed), Cast(_, uncompressed)] = &net.inner[..] else {
+            anyhow::bail!("unexpected messages")
+        };
+        anyhow::ensure!(compressed.len() < 1 << 10);
+        anyhow::ensure!(uncompressed[0] == UNCOMPRESSED);
+
+        let mut received = Vec::new();
+        let mut on_buf = decompress(1 << 20, |buf| {
+            received.push(bincode::decode::<Vec<(u32, Bytes)>>(buf)?);
+            Ok(())
+        });
+        on_buf(compressed)?;
+        on_buf(uncompressed)?;
+        anyhow::ensure!(on_buf(&[]).is_err());
+        drop(on_buf);
+        anyhow::ensure!(received == [batch.clone(), batch[..1].to_vec()]);
+        Ok(())
+    }
+
+    #[test]
+    fn sharded() -> anyhow::Result<()> {
+        let mut nets =
+            [0, 1].map(|shard| ShardedNet::new(Transient::<Cast<u8, Bytes>>::new(), shard));
+        nets[0].send(Cast(0, Bytes::from("foo")))?;
+        nets[1].send(Cast(0, Bytes::from("bar")))?;
+        let received = std::cell::RefCell::new(Vec::new());
+        let on_shard = |shard| {
+            let received = &received;
+            move |buf: &[u8]| {
+                received.borrow_mut().push((shard, buf.to_vec()));
+                anyhow::Ok(())
+            }
+        };
+        let mut on_buf = demux_shards(vec![on_shard(0), on_shard(1)]);
+        for net in &nets {
+            for Cast(_, message) in net.inner.iter() {
+                on_buf(message)?
+            }
+        }
+        anyhow::ensure!(on_buf(&[2]).is_err());
+        anyhow::ensure!(*received.borrow() == [(0, b"foo".to_vec()), (1, b"bar".to_vec())]);
+        Ok(())
+    }
+
+    #[test]
+    fn partition() -> anyhow::Result<()> {
+        let handle = PartitionHandle::new();
+        let mut nets = [1u8, 2]
+            .map(|local| Partition::new(Transient::<Cast<u8, ()>>::new(), local, handle.clone()));
+        let send_all = |nets: &mut [Partition<_, Transient<_>>; 2]| {
+            for net in nets {
+                for remote in 1..=3 {
+                    net.send(Cast(remote, ()))?
+                }
+            }
+            anyhow::Ok(())
+        };
+        handle.block(3);
+        send_all(&mut nets)?;
+        // 1 is isolated in both directions
+        handle.block(1);
+        send_all(&mut nets)?;
+        handle.heal();
+        send_all(&mut nets)?;
+        let destinations = |net: &Partition<_, Transient<_>>| {
+            net.inner
+                .iter()
+                .map(|Cast(addr, ())| *addr)
+                .collect::<Vec<_>>()
+        };
+        anyhow::ensure!(destinations(&nets[0]) == [1, 2, 1, 2, 3]);
+        anyhow::ensure!(destinations(&nets[1]) == [1, 2, 2, 1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn rate_limit() -> anyhow::Result<()> {
+        let mut net = RateLimit::new(Transient::<Cast<u8, Bytes>>::new(), 0, 10);
+        net.send(Cast(0, Bytes::from_static(&[0; 6])))?;
+        let Err(err) = net.send(Cast(0, Bytes::from_static(&[0; 6]))) else {
+            anyhow::bail!("unexpected Ok")
+        };
+        anyhow::ensure!(err.is::<RateLimited>());
+        // buckets are per destination
+        net.send(Cast(1, Bytes::from_static(&[0; 6])))?;
+        net.send(Cast(0, Bytes::from_static(&[0; 4])))?;
+        anyhow::ensure!(net.inner.len() == 3);
+        Ok(())
+    }
+
+    #[test]
+    fn tap() -> anyhow::Result<()> {
+        let mut observed = Vec::new();
+        let mut net = IndexNet::new(
+            vec![1u8, 2],
+            None,
+            Tap::new(
+                RateLimit::new(Transient::<Cast<u8, Bytes>>::new(), 0, 10),
+                |remote: &_, message: &Bytes| observed.push((*remote, message.len())),
+            ),
+        );
+        net.send(Cast(0usize, Bytes::from_static(&[0; 6])))?;
+        net.send(Cast(All, Bytes::from_static(&[0; 2])))?;
+        // inner error is passed through, and the message is still observed
+        let Err(err) = net.send(Cast(1usize, Bytes::from_static(&[0; 9]))) else {
+            anyhow::bail!("unexpected Ok")
+        };
+        anyhow::ensure!(err.is::<RateLimited>());
+        anyhow::ensure!(net.inner.inner.inner.len() == 3);
+        anyhow::ensure!(observed == [(1, 6), (1, 2), (2, 2), (2, 9)]);
+        Ok(())
+    }
+}
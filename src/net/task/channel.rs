@@ -0,0 +1,69 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use derive_where::derive_where;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    event::SendEvent,
+    net::{
+        events::{Cast, ReliableCast},
+        Addr,
+    },
+};
+
+// in-process transport for running all nodes in one process, e.g. benchmarking protocol CPU cost
+// without network stack overhead
+// the registry is shared by all nodes. every node registers its address and gets the inbox to
+// `run` on. messages to unregistered addresses are dropped silently, as UDP does
+#[derive(Debug)]
+#[derive_where(Clone, Default)]
+pub struct Channel<A>(Arc<Mutex<HashMap<A, UnboundedSender<Bytes>>>>);
+
+pub type Inbox = UnboundedReceiver<Bytes>;
+
+impl<A: Addr> Channel<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, addr: A) -> Inbox {
+        let (sender, receiver) = unbounded_channel();
+        self.0.lock().unwrap().insert(addr, sender);
+        receiver
+    }
+}
+
+impl<A: Addr> SendEvent<Cast<A, Bytes>> for Channel<A> {
+    fn send(&mut self, Cast(remote, message): Cast<A, Bytes>) -> anyhow::Result<()> {
+        if let Some(sender) = self.0.lock().unwrap().get(&remote) {
+            if sender.send(message).is_err() {
+                // TODO log
+            }
+        }
+        Ok(())
+    }
+}
+
+// in-process delivery to a registered address does not lose messages in the first place
+impl<A: Addr> SendEvent<ReliableCast<A, Bytes>> for Channel<A> {
+    fn send(
+        &mut self,
+        ReliableCast(remote, message): ReliableCast<A, Bytes>,
+    ) -> anyhow::Result<()> {
+        SendEvent::send(self, Cast(remote, message))
+    }
+}
+
+pub async fn run(
+    inbox: &mut Inbox,
+    mut on_buf: impl FnMut(&[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    while let Some(buf) = inbox.recv().await {
+        on_buf(&buf)?
+    }
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use quinn::{
+    crypto::rustls::QuicClientConfig,
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+        pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    },
+    ClientConfig, Connection, Endpoint, Incoming, ServerConfig,
+};
+use tokio::{
+    select, spawn,
+    sync::mpsc::{error::SendError, unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{
+    event::SendEvent,
+    net::events::{Cast, Recv, ReliableCast},
+};
+
+use super::tcp::{read_frames, write_frame};
+
+const SERVER_NAME: &str = "neatworks";
+
+// the endpoint both accepts and initiates connections
+// peers are trusted (in the same way as the hardcoded public keys in `crypto`), so every endpoint
+// presents a fresh self-signed certificate and the server certificate is not verified. TLS here is
+// only for satisfying QUIC
+pub fn bind(addr: SocketAddr) -> anyhow::Result<Endpoint> {
+    let rcgen::CertifiedKey { cert, signing_key } =
+        rcgen::generate_simple_self_signed([SERVER_NAME.into()])?;
+    let server_config = ServerConfig::with_single_cert(
+        vec![cert.der().clone()],
+        PrivatePkcs8KeyDer::from(signing_key.serialize_der()).into(),
+    )?;
+    let provider = Arc::new(ring::default_provider());
+    let crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+        .with_no_client_auth();
+    let mut endpoint = Endpoint::server(server_config, addr)?;
+    endpoint.set_default_client_config(ClientConfig::new(Arc::new(QuicClientConfig::try_from(
+        crypto,
+    )?)));
+    Ok(endpoint)
+}
+
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// one connection per remote, and one stream per (remote, message class) on top of it, so that a
+// large message of one class (e.g. a PrePrepare batch) does not stall the messages of the others
+// (e.g. Commit) as a single TCP connection would
+// a handle sends on a single class; create handles for other classes with `class`, which share the
+// connections
+// failure is handled in the same way as the TCP transport: the stream is dropped silently and
+// reopened on a later send
+#[derive(Debug, Clone)]
+pub struct Quic {
+    endpoint: Endpoint,
+    class: u8,
+    connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    streams: Arc<Mutex<HashMap<StreamKey, UnboundedSender<Bytes>>>>,
+}
+
+type StreamKey = (SocketAddr, u8); // (remote, class)
+
+impl Quic {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            class: 0,
+            connections: Default::default(),
+            streams: Default::default(),
+        }
+    }
+
+    pub fn class(&self, class: u8) -> Self {
+        Self {
+            class,
+            ..self.clone()
+        }
+    }
+
+    async fn connect(&self, remote: SocketAddr) -> anyhow::Result<Connection> {
+        if let Some(connection) = self.connections.lock().unwrap().get(&remote) {
+            if connection.close_reason().is_none() {
+                return Ok(connection.clone());
+            }
+        }
+        // concurrent connecting to the same remote from multiple classes may race and end up with
+        // more than one connection, which is harmless
+        let connection = self.endpoint.connect(remote, SERVER_NAME)?.await?;
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(remote, connection.clone());
+        Ok(connection)
+    }
+
+    async fn write_frames(
+        self,
+        remote: SocketAddr,
+        mut messages: UnboundedReceiver<Bytes>,
+    ) -> anyhow::Result<()> {
+        let (mut stream, _) = self.connect(remote).await?.open_bi().await?;
+        while let Some(message) = messages.recv().await {
+            write_frame(&mut stream, &message).await?
+        }
+        Ok(())
+    }
+
+    fn stream<'a>(
+        &self,
+        streams: &'a mut HashMap<StreamKey, UnboundedSender<Bytes>>,
+        remote: SocketAddr,
+    ) -> &'a mut UnboundedSender<Bytes> {
+        streams.entry((remote, self.class)).or_insert_with(|| {
+            let (sender, receiver) = unbounded_channel();
+            let quic = self.clone();
+            spawn(async move {
+                if quic.write_frames(remote, receiver).await.is_err() {
+                    // TODO log
+                }
+            });
+            sender
+        })
+    }
+}
+
+impl SendEvent<Cast<SocketAddr, Bytes>> for Quic {
+    fn send(&mut self, Cast(remote, message): Cast<SocketAddr, Bytes>) -> anyhow::Result<()> {
+        let mut streams = self.streams.lock().unwrap();
+        if self.stream(&mut streams, remote).send(message).is_err() {
+            // the stream is gone, reopen on next send
+            streams.remove(&(remote, self.class));
+        }
+        Ok(())
+    }
+}
+
+// same as the TCP transport: reopen the stream right away instead of dropping the message
+impl SendEvent<ReliableCast<SocketAddr, Bytes>> for Quic {
+    fn send(
+        &mut self,
+        ReliableCast(remote, message): ReliableCast<SocketAddr, Bytes>,
+    ) -> anyhow::Result<()> {
+        let mut streams = self.streams.lock().unwrap();
+        if let Err(SendError(message)) =
+            UnboundedSender::send(self.stream(&mut streams, remote), message)
+        {
+            streams.remove(&(remote, self.class));
+            if self.stream(&mut streams, remote).send(message).is_err() {
+                streams.remove(&(remote, self.class));
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn accept_streams(
+    incoming: Incoming,
+    sender: UnboundedSender<Recv<Bytes>>,
+) -> anyhow::Result<()> {
+    let connection = incoming.await?;
+    loop {
+        let (_, stream) = connection.accept_bi().await?;
+        let sender = sender.clone();
+        spawn(async move {
+            if read_frames(stream, sender).await.is_err() {
+                // TODO log
+            }
+        });
+    }
+}
+
+pub async fn run(
+    endpoint: &Endpoint,
+    mut on_buf: impl FnMut(&[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let (sender, mut receiver) = unbounded_channel();
+    loop {
+        select! {
+            incoming = endpoint.accept() => {
+                let Some(incoming) = incoming else {
+                    return Ok(());
+                };
+                let sender = sender.clone();
+                spawn(async move {
+                    if accept_streams(incoming, sender).await.is_err() {
+                        // TODO log
+                    }
+                });
+            }
+            Some(Recv(buf)) = receiver.recv() => on_buf(&buf)?
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn loopback() -> anyhow::Result<()> {
+        let endpoint = bind(([127, 0, 0, 1], 0).into())?;
+        let receiver = bind(([127, 0, 0, 1], 0).into())?;
+        let addr = receiver.local_addr()?;
+
+        let mut batch = Quic::new(endpoint);
+        let mut consensus = batch.class(1);
+        let mut reply = batch.class(2);
+        for i in 0..10u8 {
+            batch.send(Cast(addr, Bytes::from(vec![i; 1 << 20])))?;
+            consensus.send(Cast(addr, Bytes::from(vec![i; 100])))?;
+            reply.send(Cast(addr, Bytes::from(vec![i; 10])))?
+        }
+
+        let mut received = HashMap::<_, Vec<_>>::new();
+        let result = run(&receiver, |buf| {
+            received.entry(buf.len()).or_default().push(buf[0]);
+            if received.values().map(Vec::len).sum::<usize>() == 30 {
+                anyhow::bail!("done")
+            }
+            Ok(())
+        })
+        .await;
+        anyhow::ensure!(result.is_err_and(|err| err.to_string() == "done"));
+        // messages of the same class are delivered in order
+        for len in [1 << 20, 100, 10] {
+            anyhow::ensure!(received[&len] == (0..10).collect::<Vec<_>>())
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+    select, spawn,
+    sync::mpsc::{error::SendError, unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{
+    event::SendEvent,
+    net::{
+        combinators::{frame, Framed},
+        events::{Cast, Recv, ReliableCast},
+    },
+};
+
+// outgoing connections keyed by remote address, established on the first send and reused after
+// that. a connection that fails is dropped silently (along with the messages queued on it), and a
+// later send to the same remote reconnects, so the delivery guarantee is no stronger than UDP's
+// when the remote is unreachable, only the message size limit is lifted
+#[derive(Debug, Clone, Default)]
+pub struct Tcp(Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Bytes>>>>);
+
+impl Tcp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn connection(
+        connections: &mut HashMap<SocketAddr, UnboundedSender<Bytes>>,
+        remote: SocketAddr,
+    ) -> &mut UnboundedSender<Bytes> {
+        connections.entry(remote).or_insert_with(|| {
+            let (sender, receiver) = unbounded_channel();
+            spawn(async move {
+                if write_frames(remote, receiver).await.is_err() {
+                    // TODO log
+                }
+            });
+            sender
+        })
+    }
+}
+
+impl SendEvent<Cast<SocketAddr, Bytes>> for Tcp {
+    fn send(&mut self, Cast(remote, message): Cast<SocketAddr, Bytes>) -> anyhow::Result<()> {
+        let mut connections = self.0.lock().unwrap();
+        if Self::connection(&mut connections, remote)
+            .send(message)
+            .is_err()
+        {
+            // the connection is gone, reconnect on next send
+            connections.remove(&remote);
+        }
+        Ok(())
+    }
+}
+
+// instead of being dropped along with a gone connection, the message reconnects right away
+// the messages that are already queued on a connection when it fails are still lost, as nothing
+// above TCP acknowledges them
+impl SendEvent<ReliableCast<SocketAddr, Bytes>> for Tcp {
+    fn send(
+        &mut self,
+        ReliableCast(remote, message): ReliableCast<SocketAddr, Bytes>,
+    ) -> anyhow::Result<()> {
+        let mut connections = self.0.lock().unwrap();
+        if let Err(SendError(message)) =
+            UnboundedSender::send(Self::connection(&mut connections, remote), message)
+        {
+            connections.remove(&remote);
+            if Self::connection(&mut connections, remote)
+                .send(message)
+                .is_err()
+            {
+                connections.remove(&remote);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn write_frames(
+    remote: SocketAddr,
+    mut messages: UnboundedReceiver<Bytes>,
+) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(remote).await?;
+    stream.set_nodelay(true)?;
+    while let Some(message) = messages.recv().await {
+        write_frame(&mut stream, &message).await?
+    }
+    Ok(())
+}
+
+// the framing is shared with other stream transports, see `Framed`
+pub(super) async fn write_frame(
+    stream: &mut (impl AsyncWrite + Unpin),
+    message: &[u8],
+) -> anyhow::Result<()> {
+    stream.write_all(&frame(message)?).await?;
+    Ok(())
+}
+
+pub(super) async fn read_frames(
+    mut stream: impl AsyncRead + Unpin,
+    sender: UnboundedSender<Recv<Bytes>>,
+) -> anyhow::Result<()> {
+    let mut framed = Framed::new(sender);
+    let mut buf = vec![0; 64 << 10];
+    loop {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(());
+        }
+        framed.send(Recv(Bytes::copy_from_slice(&buf[..len])))?
+    }
+}
+
+pub async fn run(
+    listener: &TcpListener,
+    mut on_buf: impl FnMut(&[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    // connections are read concurrently, while `on_buf` is only called from this task
+    let (sender, mut receiver) = unbounded_channel();
+    loop {
+        select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                stream.set_nodelay(true)?;
+                let sender = sender.clone();
+                spawn(async move {
+                    if read_frames(stream, sender).await.is_err() {
+                        // TODO log
+                    }
+                });
+            }
+            Some(Recv(buf)) = receiver.recv() => on_buf(&buf)?
+        }
+    }
+}
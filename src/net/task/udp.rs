@@ -3,7 +3,10 @@ use std::{net::SocketAddr, sync::Arc};
 use bytes::Bytes;
 use tokio::{net::UdpSocket, spawn};
 
-use crate::{event::SendEvent, net::events::Cast};
+use crate::{
+    event::SendEvent,
+    net::events::{Cast, ReliableCast},
+};
 
 impl SendEvent<Cast<SocketAddr, Bytes>> for Arc<UdpSocket> {
     fn send(&mut self, Cast(remote, message): Cast<SocketAddr, Bytes>) -> anyhow::Result<()> {
@@ -17,6 +20,16 @@ impl SendEvent<Cast<SocketAddr, Bytes>> for Arc<UdpSocket> {
     }
 }
 
+// best effort anyway
+impl SendEvent<ReliableCast<SocketAddr, Bytes>> for Arc<UdpSocket> {
+    fn send(
+        &mut self,
+        ReliableCast(remote, message): ReliableCast<SocketAddr, Bytes>,
+    ) -> anyhow::Result<()> {
+        SendEvent::send(self, Cast(remote, message))
+    }
+}
+
 pub async fn run(
     socket: &UdpSocket,
     mut on_buf: impl FnMut(&[u8]) -> anyhow::Result<()>,
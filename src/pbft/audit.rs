@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{Crypto, H256};
+
+use super::{
+    messages::{Commit, Quorum},
+    PublicParameters,
+};
+
+// a committed slot along with its commit certificate, i.e. the signed Commits of a quorum that
+// agree on the digest
+// the entries of a log are self-certifying: anyone with the public keys of the replicas can check
+// every decision with `verify`, without trusting the exporting replica
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub op_num: u32,
+    pub digest: H256,
+    pub commits: Quorum<Commit>,
+}
+
+// check that `log` is a prefix of the committed history: consecutive slots starting from the first
+// one, each certified by a quorum of valid Commits of the same view
+// the digests are not checked against the request batches, which are not part of the log
+pub fn verify(
+    log: &[AuditEntry],
+    crypto: &Crypto,
+    config: &PublicParameters,
+) -> anyhow::Result<()> {
+    for (entry, op_num) in log.iter().zip(1..) {
+        anyhow::ensure!(entry.op_num == op_num, "missing entry of op {op_num}");
+        anyhow::ensure!(
+            entry.commits.len() >= config.num_replica - config.num_faulty,
+            "op {op_num}: insufficient commits"
+        );
+        let view_num = entry.commits.values().next().map(|commit| commit.view_num);
+        for (replica_id, commit) in &entry.commits {
+            anyhow::ensure!(
+                commit.replica_id == *replica_id
+                    && Some(commit.view_num) == view_num
+                    && commit.op_num == op_num
+                    && commit.digest == entry.digest,
+                "op {op_num}: mismatched commit from replica {replica_id}"
+            );
+            crypto.verify(commit.replica_id, commit)?
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,13 @@
 use std::collections::BTreeMap;
 
 use bytes::Bytes;
+use tracing::{debug, warn};
 
 use crate::{
     codec::Payload,
     event::{ActiveTimer, OnErasedEvent, ScheduleEvent, SendEvent},
     net::{combinators::All, events::Recv, Addr, SendMessage},
+    timer::backoff,
     workload::events::{Invoke, InvokeOk},
 };
 
@@ -30,11 +32,13 @@ struct Outstanding {
     op: Payload,
     replies: BTreeMap<u8, Reply>,
     timer: ActiveTimer,
+    num_resend: u32,
 }
 
 impl<A> State<A> {
-    pub fn new(id: u32, addr: A, config: PublicParameters) -> Self {
-        Self {
+    pub fn new(id: u32, addr: A, config: PublicParameters) -> anyhow::Result<Self> {
+        config.validate()?;
+        Ok(Self {
             id,
             addr,
             config,
@@ -42,7 +46,34 @@ impl<A> State<A> {
             seq: 0,
             outstanding: Default::default(),
             view_num: 0,
-        }
+        })
+    }
+
+    // restart a crashed client with its previous id, resuming after `seq`
+    // the replicas dedupe requests by (client id, seq), so a client that restarts from scratch
+    // under the same id would have its next op mistaken for the executed one with the same seq.
+    // the invocation that was outstanding on crashing is abandoned rather than reissued, so it is
+    // executed at most once
+    pub fn recover(id: u32, addr: A, config: PublicParameters, seq: u32) -> anyhow::Result<Self> {
+        Ok(Self {
+            seq,
+            ..Self::new(id, addr, config)?
+        })
+    }
+
+    // the seq of the latest invocation, which should be persisted before the invocation is sent
+    // to recover from
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+}
+
+// for the symmetry reduction of model checking
+#[cfg(test)]
+impl<A> State<A> {
+    pub fn relabel(&mut self, id: u32, addr: A) {
+        self.id = id;
+        self.addr = addr
     }
 }
 
@@ -63,12 +94,19 @@ pub trait Context<A> {
 impl<A: Addr, C: Context<A>> OnErasedEvent<Invoke<Bytes>, C> for State<A> {
     fn on_event(&mut self, Invoke(op): Invoke<Bytes>, context: &mut C) -> anyhow::Result<()> {
         self.seq += 1;
+        debug!(
+            client_id = self.id,
+            seq = self.seq,
+            view_num = self.view_num,
+            "invoke"
+        );
         let replaced = self.outstanding.replace(Outstanding {
             op: Payload(op),
             timer: context
                 .schedule()
                 .set(self.config.client_resend_interval, events::Resend)?,
             replies: Default::default(),
+            num_resend: 0,
         });
         anyhow::ensure!(replaced.is_none());
         self.send_request(
@@ -80,7 +118,30 @@ impl<A: Addr, C: Context<A>> OnErasedEvent<Invoke<Bytes>, C> for State<A> {
 
 impl<A: Addr, C: Context<A>> OnErasedEvent<events::Resend, C> for State<A> {
     fn on_event(&mut self, events::Resend: events::Resend, context: &mut C) -> anyhow::Result<()> {
-        // warn!("Resend timeout on seq {}", self.seq);
+        let Some(outstanding) = &mut self.outstanding else {
+            anyhow::bail!("missing outstanding invocation")
+        };
+        // the timer is periodic, so it is only replaced when the interval changes
+        if self.config.client_max_resend_interval.is_some() {
+            outstanding.num_resend += 1;
+            context.schedule().unset(outstanding.timer.clone())?;
+            outstanding.timer = context.schedule().set(
+                backoff(
+                    self.config.client_resend_interval,
+                    self.config.client_max_resend_interval,
+                    outstanding.num_resend,
+                ),
+                events::Resend,
+            )?
+        }
+        warn!(
+            client_id = self.id,
+            seq = self.seq,
+            view_num = self.view_num,
+            "resend"
+        );
+        // the primary may be faulty or partitioned, so broadcast to get the backups relaying the
+        // request and watching the primary for it, which triggers view change if it keeps silent
         self.send_request(All, context)
     }
 }
@@ -107,6 +168,12 @@ impl<A, C: Context<A>> OnErasedEvent<Recv<Reply>, C> for State<A> {
         // paper is not saying what does it mean by "what it believes is the current primary"
         // either taking min or max of the view numbers seems wrong, so i choose to design nothing
         self.view_num = reply.view_num;
+        debug!(
+            client_id = self.id,
+            seq = self.seq,
+            view_num = self.view_num,
+            "invoke ok"
+        );
         context
             .schedule()
             .unset(self.outstanding.take().unwrap().timer)?;
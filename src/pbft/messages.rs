@@ -1,10 +1,14 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    hash::{Hash, Hasher},
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     codec::Payload,
     crypto::{Verifiable, H256},
+    quorum::Vote,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -30,7 +34,7 @@ pub struct Prepare {
     pub replica_id: u8,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Commit {
     pub view_num: u32,
     pub op_num: u32,
@@ -38,6 +42,18 @@ pub struct Commit {
     pub replica_id: u8,
 }
 
+// the fields are the same as `Prepare`, without the tag a signed Prepare would pass as a signed
+// Commit of the same replica
+impl Hash for Commit {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        "Commit".hash(state);
+        self.view_num.hash(state);
+        self.op_num.hash(state);
+        self.digest.hash(state);
+        self.replica_id.hash(state)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Reply {
     pub seq: u32,
@@ -73,8 +89,51 @@ pub struct QueryNewView {
     pub replica_id: u8,
 }
 
+// a replica that falls behind asks for the committed entries starting from `op_num`, which is the
+// one next to its commit number. the query is signed, so a response is only ever sent to the
+// replica that asks for it, and a response carries a bounded number of entries, the asking replica
+// pages through the rest with more queries
+// TODO there's no checkpoint yet, so the committed log is never garbage collected and the whole
+// suffix can always be served. after checkpoint is implemented, the query should be answered with
+// the latest stable checkpoint (and its proof) if `op_num` is already behind it, followed by the
+// entries after the checkpoint
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct QueryStateTransfer {
+    pub op_num: u32,
+    pub replica_id: u8,
+}
+
+// the committed entries in op number order, each one carries the commit certificate that proves it
+// is committed, so the response is not signed by the sender
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct StateTransfer<A> {
+    pub entries: Vec<CommittedEntry<A>>,
+}
+
+pub type CommittedEntry<A> = (Verifiable<PrePrepare>, Vec<Request<A>>, Quorum<Commit>);
+
 pub type Quorum<M> = BTreeMap<u8, Verifiable<M>>;
 
+impl Vote for Prepare {
+    fn replica_id(&self) -> u8 {
+        self.replica_id
+    }
+
+    fn digest(&self) -> H256 {
+        self.digest
+    }
+}
+
+impl Vote for Commit {
+    fn replica_id(&self) -> u8 {
+        self.replica_id
+    }
+
+    fn digest(&self) -> H256 {
+        self.digest
+    }
+}
+
 pub mod codec {
     use derive_more::From;
     use serde::{Deserialize, Serialize};
@@ -108,6 +167,8 @@ pub mod codec {
         ViewChange(Verifiable<ViewChange>),
         NewView(Verifiable<NewView>),
         QueryNewView(QueryNewView),
+        QueryStateTransfer(Verifiable<QueryStateTransfer>),
+        StateTransfer(StateTransfer<A>),
     }
 
     pub fn to_replica_encode<A: Addr, N>(net: N) -> Encode<ToReplica<A>, N> {
@@ -122,6 +183,8 @@ pub mod codec {
             + SendEvent<Recv<Verifiable<ViewChange>>>
             + SendEvent<Recv<Verifiable<NewView>>>
             + SendEvent<Recv<QueryNewView>>
+            + SendEvent<Recv<Verifiable<QueryStateTransfer>>>
+            + SendEvent<Recv<StateTransfer<A>>>
             + 'a,
     ) -> impl FnMut(&[u8]) -> anyhow::Result<()> + 'a {
         use ToReplica::*;
@@ -133,6 +196,97 @@ pub mod codec {
             ViewChange(message) => sender.send(Recv(message)),
             NewView(message) => sender.send(Recv(message)),
             QueryNewView(message) => sender.send(Recv(message)),
+            QueryStateTransfer(message) => sender.send(Recv(message)),
+            StateTransfer(message) => sender.send(Recv(message)),
+        }
+    }
+}
+
+// golden digests, so that an unintended change of the digested layout, e.g. reordering fields or
+// changing field types, does not go unnoticed. update the values only when the change is
+// intended, which breaks the compatibility with the signatures produced before
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::crypto::DigestHash as _;
+
+    use super::*;
+
+    fn digest() -> H256 {
+        [42; 32].into()
+    }
+
+    #[test]
+    fn golden_digests() {
+        let pre_prepare = PrePrepare {
+            view_num: 1,
+            op_num: 2,
+            digest: digest(),
+        };
+        let prepare = Prepare {
+            view_num: 1,
+            op_num: 2,
+            digest: digest(),
+            replica_id: 3,
+        };
+        let commit = Commit {
+            view_num: 1,
+            op_num: 2,
+            digest: digest(),
+            replica_id: 3,
+        };
+        let request = Request {
+            seq: 1,
+            op: Payload(Bytes::from_static(b"op")),
+            client_id: 2,
+            client_addr: 3u8,
+        };
+        let golden = |digest: &str| digest.parse::<H256>().unwrap();
+        assert_eq!(
+            pre_prepare.sha256(),
+            golden("1930b54a2fe2b3a5840bf39d378595175843b889eb2f0d88d5898659ae4d7875")
+        );
+        assert_eq!(
+            prepare.sha256(),
+            golden("6b7997f1bfa9dde006cf9597576382c4fbfd42bf746dac4e813cb2e6654da56f")
+        );
+        assert_eq!(
+            commit.sha256(),
+            golden("cec0242a04cff86234b4ec553aee3c101276c2b3e337470899c16cbd4001eeb6")
+        );
+        assert_eq!(
+            request.sha256(),
+            golden("c77cb1ea9601efa08ea84a0676f528e37ba0bdde352cb9937d3e505f6a4720b2")
+        );
+        // the batch digest that PrePrepare refers to
+        assert_eq!(
+            vec![request].sha256(),
+            golden("ac281eaaefd38127c8ffa1726b5ac9ed3d6b4266b636aff56a2becfbeee57fcb")
+        );
+    }
+
+    #[test]
+    fn distinct_batch_digests() {
+        let request = |client_id, op: &'static [u8]| Request {
+            seq: 1,
+            op: Payload(Bytes::from_static(op)),
+            client_id,
+            client_addr: client_id as u8,
+        };
+        let batches = [
+            vec![request(1, b"a"), request(1, b"bc")],
+            vec![request(1, b"ab"), request(1, b"c")],
+            vec![request(1, b"abc")],
+            vec![request(1, b"abc"), request(1, b"")],
+            vec![request(1, b""), request(1, b"abc")],
+            vec![request(1, b"abc"), request(2, b"abc")],
+            vec![],
+        ];
+        for (i, batch) in batches.iter().enumerate() {
+            for other_batch in &batches[i + 1..] {
+                assert_ne!(batch.sha256(), other_batch.sha256(), "{batch:?}")
+            }
         }
     }
 }
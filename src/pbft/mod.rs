@@ -1,5 +1,8 @@
 use std::time::Duration;
 
+use crate::crypto::{DigestHash, H256};
+
+pub mod audit;
 pub mod client;
 pub mod messages;
 pub mod replica;
@@ -13,18 +16,48 @@ pub struct PublicParameters {
 
     pub num_concurrent: usize,
     pub max_batch_size: usize,
+    // when set, the primary adapts the batch size within `1..=max_batch_size` to keep the tail
+    // latency of committing a batch under this target, see `replica::AdaptiveBatch`
+    pub batch_latency_target: Option<Duration>,
 
     pub client_resend_interval: Duration,
+    // when set, the client backs off exponentially on consecutive resends of the same request,
+    // starting from `client_resend_interval` and capped by this, to avoid retransmission storms
+    // under congestion
+    pub client_max_resend_interval: Option<Duration>,
     pub progress_prepare_interval: Duration,
     pub view_change_delay: Duration,
     pub progress_view_change_interval: Duration,
     pub state_transfer_delay: Duration,
+
+    pub digest_algorithm: DigestAlgorithm,
+}
+
+// the hash function of the request batch digests that PrePrepares refer to. it is part of the
+// public parameters so the primary and the backups always agree on it
+// the digests of the messages that get signed are always SHA-256, which is what the signing
+// schemes expect
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    #[default]
+    Sha256,
+    Blake2,
+}
+
+impl DigestAlgorithm {
+    pub fn digest(self, value: &impl DigestHash) -> H256 {
+        match self {
+            Self::Sha256 => value.sha256(),
+            Self::Blake2 => value.blake2(),
+        }
+    }
 }
 
 impl PublicParameters {
     pub fn durations(client_resend_interval: Duration) -> Self {
         Self {
             client_resend_interval,
+            client_max_resend_interval: None,
             progress_prepare_interval: client_resend_interval / 5,
             // keep track of the timing of start sending ViewChange for a view, do not repeat; alarm
             // (at most) once for each view
@@ -42,6 +75,122 @@ impl PublicParameters {
             num_faulty: Default::default(),
             num_concurrent: Default::default(),
             max_batch_size: Default::default(),
+            batch_latency_target: None,
+            digest_algorithm: Default::default(),
+        }
+    }
+}
+
+impl PublicParameters {
+    // the quorum thresholds are only safe with at most `num_faulty` faulty replicas out of
+    // `num_replica`, and replica ids are `u8` throughout
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.num_replica > 3 * self.num_faulty,
+            "num_replica >= 3 * num_faulty + 1 is required, got num_replica = {} and num_faulty = {}",
+            self.num_replica,
+            self.num_faulty
+        );
+        anyhow::ensure!(
+            self.num_replica <= u8::MAX as usize + 1,
+            "num_replica = {} exceeds the u8 replica id space",
+            self.num_replica
+        );
+        anyhow::ensure!(self.num_concurrent > 0, "num_concurrent must be positive");
+        anyhow::ensure!(self.max_batch_size > 0, "max_batch_size must be positive");
+        for (name, interval) in [
+            ("client_resend_interval", self.client_resend_interval),
+            ("progress_prepare_interval", self.progress_prepare_interval),
+            ("view_change_delay", self.view_change_delay),
+            (
+                "progress_view_change_interval",
+                self.progress_view_change_interval,
+            ),
+            ("state_transfer_delay", self.state_transfer_delay),
+        ] {
+            // a zero interval timer fires in a busy loop
+            anyhow::ensure!(!interval.is_zero(), "{name} must be positive")
+        }
+        if let Some(interval) = self.client_max_resend_interval {
+            anyhow::ensure!(
+                interval >= self.client_resend_interval,
+                "client_max_resend_interval = {interval:?} is shorter than client_resend_interval = {:?}",
+                self.client_resend_interval
+            )
         }
+        if let Some(target) = self.batch_latency_target {
+            anyhow::ensure!(!target.is_zero(), "batch_latency_target must be positive")
+        }
+        Ok(())
+    }
+
+    // `num_concurrent` and `max_batch_size` default to 1, and the durations are derived from a
+    // 100ms `client_resend_interval` as `durations` does
+    pub fn builder(num_replica: usize, num_faulty: usize) -> PublicParametersBuilder {
+        PublicParametersBuilder(Self {
+            num_replica,
+            num_faulty,
+            num_concurrent: 1,
+            max_batch_size: 1,
+            ..Self::durations(Duration::from_millis(100))
+        })
+    }
+}
+
+// the fields that are not covered by the methods can still be overridden with struct update syntax
+// on the built parameters, which are validated again by the client and replica constructors
+#[derive(Debug, Clone)]
+pub struct PublicParametersBuilder(PublicParameters);
+
+impl PublicParametersBuilder {
+    pub fn with_num_concurrent(mut self, num_concurrent: usize) -> Self {
+        self.0.num_concurrent = num_concurrent;
+        self
+    }
+
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.0.max_batch_size = max_batch_size;
+        self
+    }
+
+    pub fn with_batch_latency_target(mut self, target: Duration) -> Self {
+        self.0.batch_latency_target = Some(target);
+        self
+    }
+
+    // rederive the timer durations from `client_resend_interval` as `durations` does
+    pub fn with_durations(mut self, client_resend_interval: Duration) -> Self {
+        let PublicParameters {
+            client_resend_interval,
+            progress_prepare_interval,
+            view_change_delay,
+            progress_view_change_interval,
+            state_transfer_delay,
+            ..
+        } = PublicParameters::durations(client_resend_interval);
+        self.0 = PublicParameters {
+            client_resend_interval,
+            progress_prepare_interval,
+            view_change_delay,
+            progress_view_change_interval,
+            state_transfer_delay,
+            ..self.0
+        };
+        self
+    }
+
+    pub fn with_client_max_resend_interval(mut self, interval: Duration) -> Self {
+        self.0.client_max_resend_interval = Some(interval);
+        self
+    }
+
+    pub fn with_digest_algorithm(mut self, digest_algorithm: DigestAlgorithm) -> Self {
+        self.0.digest_algorithm = digest_algorithm;
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<PublicParameters> {
+        self.0.validate()?;
+        Ok(self.0)
     }
 }
@@ -1,20 +1,31 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use crate::{
     codec::Payload,
     crypto::{
         events::{Signed, Verified},
-        Crypto, DigestHash, Verifiable, H256,
+        Crypto, CryptoError, DigestHash, Verifiable, H256,
     },
     event::{OnErasedEvent, ScheduleEvent, SendEventFor, Submit},
-    net::{combinators::All, events::Recv, Addr, SendMessage},
+    net::{combinators::All, events::Recv, Addr, SendMessage, SendMessageReliable},
+    quorum::QuorumCert,
     timer::Timer,
     workload::App,
 };
 
+use derive_more::{Deref, DerefMut};
+use tracing::{debug, info, warn};
+
 use super::{
+    audit::AuditEntry,
     messages::{
-        Commit, NewView, PrePrepare, Prepare, QueryNewView, Quorum, Reply, Request, ViewChange,
+        Commit, NewView, PrePrepare, Prepare, QueryNewView, QueryStateTransfer, Quorum, Reply,
+        Request, StateTransfer, ViewChange,
     },
     PublicParameters,
 };
@@ -36,10 +47,13 @@ pub struct State<S, A> {
     // a more consistent design may be log[0] also has some `pre_prepare` and becomes a regular
     // no-op slot, but i don't bother
     log: Vec<LogEntry<A>>,
-    prepare_quorums: Quorums<u32, Prepare>, // u32 = op number
-    commit_quorums: Quorums<u32, Commit>,
+    prepare_quorums: BTreeMap<u32, QuorumCert<Prepare>>, // u32 = op number
+    commit_quorums: BTreeMap<u32, QuorumCert<Commit>>,
     commit_num: u32,
     app: S,
+    // the op number of the latest page query, so a page that comes from several responders is
+    // followed by only one query
+    state_transfer_paged: u32,
 
     do_view_change_timer: Timer<events::DoViewChange>,
     progress_view_change_timer: Timer<events::ProgressViewChange>,
@@ -51,6 +65,229 @@ pub struct State<S, A> {
     // invent enum for this if wants to improve readability later
     pending_prepares: BTreeMap<u32, Vec<Verifiable<Prepare>>>,
     pending_commits: BTreeMap<u32, Vec<Verifiable<Commit>>>,
+
+    metrics: Unhashed<ReplicaMetrics>,
+    batching: Unhashed<Batching>,
+    verified: Unhashed<VerifiedCache>,
+}
+
+// plain counters that are bumped by the handlers, read through `State::metrics`
+// the signature verifications are counted on submitting to the crypto worker, regardless of the
+// outcome, and a message that carries certificates counts all the signatures in it
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaMetrics {
+    pub requests_received: u64,
+    pub batches_proposed: u64,
+    pub prepares_sent: u64,
+    pub prepares_verified: u64,
+    pub commits_sent: u64,
+    pub commits_verified: u64,
+    pub view_changes_triggered: u64,
+    pub signature_verifications: u64,
+    // verified Prepares and Commits that agree on the view of a PrePrepare but not on its digest,
+    // which no honest replica sends, along with their senders. the ones that conflict with an
+    // already received PrePrepare are dropped before verification and not counted, as they may be
+    // forged by anyone
+    pub digest_conflicts: u64,
+    pub conflicting_replicas: BTreeSet<u8>,
+    // not a counter: the batch size that the primary closes batches with, which only changes with
+    // `PublicParameters::batch_latency_target` set
+    pub batch_size: usize,
+    // not a counter of the handlers either: the duplicates that skip verification on the crypto
+    // worker, see `VerifiedCache`
+    pub verified_cache_hits: u64,
+}
+
+// the claimed signer of a message with a bad signature is either faulty or being impersonated, as the
+// sender of a message is not authenticated otherwise. so this is only flagged rather than counted
+// into `ReplicaMetrics::conflicting_replicas`
+fn warn_bad_signature(message: &str, replica_id: u8) {
+    warn!(message, replica_id, "bad signature")
+}
+
+// the parts of the replica that are observations or caches rather than the protocol state, which
+// must not tell apart the states that only differ in them, e.g. in model checking
+#[derive(Debug, Clone, Default, Deref, DerefMut)]
+struct Unhashed<T>(T);
+
+impl<T> PartialEq for Unhashed<T> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> Eq for Unhashed<T> {}
+
+impl<T> Hash for Unhashed<T> {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+// the AIMD feedback loop on the primary: on every committed batch, the batch size grows by one if
+// the tail latency is within the target and the requests are queueing up to (at least) the current
+// batch size, and halves if the tail latency exceeds the target
+// the latency of a batch is from proposing it to committing it, and the tail is the 99th
+// percentile of a window of recent batches. the window is cleared on shrinking, so the following
+// decisions are made on the latencies of the smaller batches
+#[derive(Debug, Clone)]
+pub struct AdaptiveBatch {
+    target: Duration,
+    max_batch_size: usize,
+    batch_size: usize,
+    latencies: VecDeque<Duration>,
+}
+
+impl AdaptiveBatch {
+    const WINDOW: usize = 64;
+
+    pub fn new(target: Duration, max_batch_size: usize) -> Self {
+        Self {
+            target,
+            max_batch_size,
+            batch_size: 1,
+            latencies: Default::default(),
+        }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn on_commit(&mut self, latency: Duration, num_queued: usize) {
+        if self.latencies.len() == Self::WINDOW {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+        let mut latencies = self.latencies.iter().copied().collect::<Vec<_>>();
+        latencies.sort_unstable();
+        let tail = latencies[(latencies.len() * 99 / 100).min(latencies.len() - 1)];
+        if tail > self.target {
+            self.batch_size = (self.batch_size / 2).max(1);
+            self.latencies.clear()
+        } else if num_queued >= self.batch_size {
+            self.batch_size = (self.batch_size + 1).min(self.max_batch_size)
+        }
+    }
+}
+
+// the wall clock readings are not part of the protocol state, in the same way as `ReplicaMetrics`.
+// the adaptive batch size does affect the protocol, so `batch_latency_target` is left unset for
+// model checking
+#[derive(Debug, Clone, Default)]
+struct Batching {
+    adaptive: Option<AdaptiveBatch>,
+    proposed: BTreeMap<u32, Instant>, // op number -> when the batch was proposed
+}
+
+// the Prepares and Commits that have passed verification, so that a duplicate (e.g. resent, or
+// received again after the slot is requeued) short-circuits instead of being verified again
+// the key is the digest of the whole verifiable message tagged with its kind, so it covers the
+// signature: a duplicate with a forged signature misses and gets verified (and rejected)
+// the cache is shared with the crypto worker, which computes the keys and consults the cache in
+// place of verifying. a hit and a miss take the same path, i.e. a `Verified` event from the worker,
+// so the cache does not affect the protocol state. the clones of a state (e.g. in model checking)
+// share the cache, which is fine for the same reason
+// the least recently hit entry is evicted when full
+#[derive(Debug, Clone, Default)]
+struct VerifiedCache(Arc<Mutex<VerifiedCacheEntries>>);
+
+#[derive(Debug, Default)]
+struct VerifiedCacheEntries {
+    entries: HashMap<H256, u64>, // key -> tick of last hit
+    lru: BTreeMap<u64, H256>,
+    tick: u64,
+    hits: u64,
+}
+
+impl VerifiedCacheEntries {
+    const CAPACITY: usize = 4096;
+
+    fn contains(&mut self, key: &H256) -> bool {
+        let Some(tick) = self.entries.get_mut(key) else {
+            return false;
+        };
+        self.lru.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.lru.insert(self.tick, *key);
+        true
+    }
+
+    fn insert(&mut self, key: H256) {
+        if self.contains(&key) {
+            return;
+        }
+        if self.entries.len() == Self::CAPACITY {
+            if let Some((_, evicted)) = self.lru.pop_first() {
+                self.entries.remove(&evicted);
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(key, self.tick);
+        self.lru.insert(self.tick, key);
+    }
+}
+
+// on the crypto worker
+impl VerifiedCache {
+    fn key<M: DigestHash>(kind: &str, signed: &Verifiable<M>) -> H256 {
+        (kind, signed).sha256()
+    }
+
+    fn hits(&self) -> u64 {
+        self.0.lock().unwrap().hits
+    }
+
+    fn verify<M: DigestHash>(
+        &self,
+        kind: &str,
+        index: u8,
+        signed: &Verifiable<M>,
+        crypto: &Crypto,
+    ) -> Result<(), CryptoError> {
+        let key = Self::key(kind, signed);
+        let mut entries = self.0.lock().unwrap();
+        if entries.contains(&key) {
+            entries.hits += 1;
+            return Ok(());
+        }
+        drop(entries);
+        crypto.verify(index, signed)?;
+        self.0.lock().unwrap().insert(key);
+        Ok(())
+    }
+
+    // the valid ones of `signed`, the cached ones first
+    fn verify_batch<M: DigestHash>(
+        &self,
+        kind: &str,
+        indexes: &[u8],
+        signed: Vec<Verifiable<M>>,
+        crypto: &Crypto,
+    ) -> Result<Vec<Verifiable<M>>, CryptoError> {
+        if indexes.len() != signed.len() {
+            return Err(CryptoError::LengthMismatch(indexes.len(), signed.len()));
+        }
+        let mut verified = Vec::new();
+        let (mut uncached_indexes, mut uncached) = (Vec::new(), Vec::new());
+        let mut entries = self.0.lock().unwrap();
+        for (index, signed) in indexes.iter().zip(signed) {
+            if entries.contains(&Self::key(kind, &signed)) {
+                entries.hits += 1;
+                verified.push(signed)
+            } else {
+                uncached_indexes.push(*index);
+                uncached.push(signed)
+            }
+        }
+        drop(entries);
+        let valid = crypto.verify_batch_or_each(&uncached_indexes, uncached)?;
+        let mut entries = self.0.lock().unwrap();
+        for signed in valid {
+            entries.insert(Self::key(kind, &signed));
+            verified.push(signed)
+        }
+        Ok(verified)
+    }
 }
 
 type Quorums<K, M> = BTreeMap<K, Quorum<M>>;
@@ -69,7 +306,13 @@ struct LogEntry<A> {
 const NO_OP_DIGEST: H256 = H256::zero();
 
 impl<S, A> State<S, A> {
-    pub fn new(id: u8, app: S, config: PublicParameters) -> Self {
+    pub fn new(id: u8, app: S, config: PublicParameters) -> anyhow::Result<Self> {
+        config.validate()?;
+        anyhow::ensure!(
+            (id as usize) < config.num_replica,
+            "replica id {id} out of {} replicas",
+            config.num_replica
+        );
         let (
             replies,
             requests,
@@ -82,8 +325,15 @@ impl<S, A> State<S, A> {
             view_changes,
             pending_prepares,
             pending_commits,
+            metrics,
         ) = Default::default();
-        Self {
+        let batching = Unhashed(Batching {
+            adaptive: config
+                .batch_latency_target
+                .map(|target| AdaptiveBatch::new(target, config.max_batch_size)),
+            proposed: Default::default(),
+        });
+        Ok(Self {
             id,
             app,
 
@@ -99,9 +349,132 @@ impl<S, A> State<S, A> {
             prepare_quorums,
             commit_quorums,
             commit_num,
+            state_transfer_paged: 0,
             view_changes,
             pending_prepares,
             pending_commits,
+            metrics,
+            batching,
+            verified: Default::default(),
+        })
+    }
+}
+
+impl<S, A> State<S, A> {
+    pub fn metrics(&self) -> ReplicaMetrics {
+        ReplicaMetrics {
+            batch_size: self.batch_size(),
+            verified_cache_hits: self.verified.hits(),
+            ..self.metrics.0.clone()
+        }
+    }
+
+    fn batch_size(&self) -> usize {
+        match &self.batching.adaptive {
+            Some(adaptive) => adaptive.batch_size(),
+            None => self.config.max_batch_size,
+        }
+    }
+}
+
+impl<S, A> State<S, A> {
+    // the certified prefix of the executed slots, see `audit::verify`
+    // a view change clears the commits of the slots it carries over until they are committed again
+    // in the new view, so the export may stop short of the executed slots in the middle of one
+    pub fn export_audit_log(&self) -> Vec<AuditEntry> {
+        self.log
+            .iter()
+            .skip(1)
+            .take(self.commit_num as _)
+            .map_while(|entry| {
+                let pre_prepare = entry.pre_prepare.as_ref()?;
+                if entry.commits.is_empty() {
+                    return None;
+                }
+                Some(AuditEntry {
+                    op_num: pre_prepare.op_num,
+                    digest: pre_prepare.digest,
+                    commits: entry.commits.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+// digests of the executed slots in op number order, for checking agreement in model checking
+#[cfg(test)]
+impl<S, A> State<S, A> {
+    pub fn committed_digests(&self) -> impl Iterator<Item = H256> + '_ {
+        self.log
+            .iter()
+            .skip(1)
+            .take(self.commit_num as _)
+            .map(|entry| entry.pre_prepare.as_ref().unwrap().digest)
+    }
+
+    // the request batches in the log along with their digests
+    pub fn batches(&self) -> impl Iterator<Item = (H256, &[Request<A>])> + '_ {
+        self.log.iter().filter_map(|entry| {
+            let pre_prepare = entry.pre_prepare.as_ref()?;
+            Some((pre_prepare.digest, &*entry.requests)).filter(|_| !entry.requests.is_empty())
+        })
+    }
+
+    // (seq, result) of the latest request from the client
+    pub fn client_entry(&self, client_id: u32) -> Option<&(u32, Option<Reply>)> {
+        self.replies.get(&client_id)
+    }
+}
+
+// for the symmetry reduction of model checking
+#[cfg(test)]
+impl<S> State<S, super::tests::Addr> {
+    pub fn relabel_clients(&mut self, relabel: &super::tests::Relabel) {
+        use std::mem::take;
+
+        use super::tests::Relabel;
+
+        self.replies = take(&mut self.replies)
+            .into_iter()
+            .map(|(client_id, entry)| (relabel.client_id(client_id), entry))
+            .collect();
+        self.requests = relabel.requests(take(&mut self.requests));
+        self.new_views = take(&mut self.new_views)
+            .into_iter()
+            .map(|(view_num, new_view)| (view_num, relabel.new_view(new_view)))
+            .collect();
+        for entry in &mut self.log {
+            entry.pre_prepare = entry
+                .pre_prepare
+                .take()
+                .map(|pre_prepare| relabel.pre_prepare(pre_prepare));
+            entry.requests = relabel.requests(take(&mut entry.requests));
+            entry.prepares = relabel.quorum(take(&mut entry.prepares), Relabel::prepare);
+            entry.commits = relabel.quorum(take(&mut entry.commits), Relabel::commit);
+        }
+        self.prepare_quorums = take(&mut self.prepare_quorums)
+            .into_iter()
+            .map(|(op_num, quorum)| (op_num, quorum.map(|prepare| relabel.prepare(prepare))))
+            .collect();
+        self.commit_quorums = take(&mut self.commit_quorums)
+            .into_iter()
+            .map(|(op_num, quorum)| (op_num, quorum.map(|commit| relabel.commit(commit))))
+            .collect();
+        self.view_changes = take(&mut self.view_changes)
+            .into_iter()
+            .map(|(view_num, quorum)| (view_num, relabel.quorum(quorum, Relabel::view_change)))
+            .collect();
+        for prepares in self.pending_prepares.values_mut() {
+            *prepares = take(prepares)
+                .into_iter()
+                .map(|prepare| relabel.prepare(prepare))
+                .collect()
+        }
+        for commits in self.pending_commits.values_mut() {
+            *commits = take(commits)
+                .into_iter()
+                .map(|commit| relabel.commit(commit))
+                .collect()
         }
     }
 }
@@ -118,6 +491,10 @@ pub mod events {
 
     #[derive(Debug, Clone)]
     pub struct StateTransfer(pub u32);
+
+    // a full page of transferred entries has been verified, sent by the crypto worker
+    #[derive(Debug, Clone)]
+    pub struct StateTransferPage(pub u32); // the next op number after the page
 }
 
 pub trait Context<S, A> {
@@ -139,7 +516,9 @@ pub trait PeerNet<A>: SendMessage<u8, Request<A>> // for relaying to (seemingly
 + SendMessage<All, Verifiable<ViewChange>>
 + SendMessage<All, Verifiable<NewView>>
 + SendMessage<u8, QueryNewView>
-+ SendMessage<u8, Verifiable<NewView>> {}
++ SendMessage<u8, Verifiable<NewView>>
++ SendMessage<All, Verifiable<QueryStateTransfer>>
++ SendMessageReliable<u8, StateTransfer<A>> {}
 impl<
         N: SendMessage<u8, Request<A>> // for relaying to (seemingly unresponsive) primary
             + SendMessage<All, (Verifiable<PrePrepare>, Vec<Request<A>>)>
@@ -148,7 +527,9 @@ impl<
             + SendMessage<All, Verifiable<ViewChange>>
             + SendMessage<All, Verifiable<NewView>>
             + SendMessage<u8, QueryNewView>
-            + SendMessage<u8, Verifiable<NewView>>,
+            + SendMessage<u8, Verifiable<NewView>>
+            + SendMessage<All, Verifiable<QueryStateTransfer>>
+            + SendMessageReliable<u8, StateTransfer<A>>,
         A,
     > PeerNet<A> for N
 {
@@ -210,6 +591,7 @@ impl<S, A> State<S, A> {
 
 impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Request<A>>, C> for State<S, A> {
     fn on_event(&mut self, Recv(request): Recv<Request<A>>, context: &mut C) -> anyhow::Result<()> {
+        self.metrics.requests_received += 1;
         if self.view_change() {
             return Ok(());
         }
@@ -249,15 +631,20 @@ impl<S: App, A: Addr> State<S, A> {
         assert!(self.is_primary());
         assert!(!self.view_change());
         assert!(!self.requests.is_empty());
+        self.metrics.batches_proposed += 1;
         let requests = self
             .requests
-            .drain(..self.requests.len().min(self.config.max_batch_size))
+            .drain(..self.requests.len().min(self.batch_size()))
             .collect::<Vec<_>>();
         let op_num = self.op_num();
+        if self.batching.adaptive.is_some() {
+            self.batching.proposed.insert(op_num, Instant::now());
+        }
         if self.log.get(op_num as usize).is_none() {
             self.log.resize(op_num as usize + 1, self.default_entry())
         }
         let view_num = self.view_num;
+        let digest_algorithm = self.config.digest_algorithm;
         context
             .crypto_worker()
             // not `submit_sign` here because I want to postpone digesting to worker
@@ -265,7 +652,7 @@ impl<S: App, A: Addr> State<S, A> {
                 let pre_prepare = PrePrepare {
                     view_num,
                     op_num,
-                    digest: requests.sha256(),
+                    digest: digest_algorithm.digest(&requests),
                 };
                 context.send((Signed(crypto.sign(pre_prepare)), requests))
             }))
@@ -296,6 +683,13 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Signed<PrePrepare>, Ve
             .progress_timer
             .set(events::ProgressPrepare(op_num), context.schedule())?;
 
+        debug!(
+            replica_id = self.id,
+            view_num = self.view_num,
+            op_num,
+            num_request = requests.len(),
+            "propose"
+        );
         let digest = pre_prepare.digest;
         context.peer_net().send(All, (pre_prepare, requests))?;
 
@@ -377,10 +771,12 @@ impl<S: App, A: Addr, C: Context<Self, A>>
 
         // TODO should reject op number over high watermark here
         let replica_id = pre_prepare.view_num as usize % self.config.num_replica;
+        let digest_algorithm = self.config.digest_algorithm;
+        self.metrics.signature_verifications += 1;
         context
             .crypto_worker()
             .submit(Box::new(move |crypto, context| {
-                if (requests.sha256() == pre_prepare.digest
+                if (digest_algorithm.digest(&requests) == pre_prepare.digest
                     || requests.is_empty() && pre_prepare.digest == NO_OP_DIGEST)
                     && crypto.verify(replica_id, &pre_prepare).is_ok()
                 {
@@ -424,14 +820,39 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Verified<PrePrepare>,
         };
         context.submit_sign(prepare)?;
 
+        let metrics = &mut self.metrics;
+        let mut conflict = |replica_id| {
+            metrics.digest_conflicts += 1;
+            metrics.conflicting_replicas.insert(replica_id);
+            warn!(
+                view_num = pre_prepare.view_num,
+                op_num = pre_prepare.op_num,
+                conflicting_replica_id = replica_id,
+                "conflicting digest"
+            )
+        };
         if let Some(prepare_quorum) = self.prepare_quorums.get_mut(&pre_prepare.op_num) {
-            prepare_quorum.retain(|_, prepare| {
-                prepare.view_num == pre_prepare.view_num && prepare.digest == pre_prepare.digest
+            prepare_quorum.retain(|prepare| {
+                if prepare.view_num != pre_prepare.view_num {
+                    return false;
+                }
+                if prepare.digest != pre_prepare.digest {
+                    conflict(prepare.replica_id);
+                    return false;
+                }
+                true
             });
         }
         if let Some(commit_quorum) = self.commit_quorums.get_mut(&pre_prepare.op_num) {
-            commit_quorum.retain(|_, commit| {
-                commit.view_num == pre_prepare.view_num && commit.digest == pre_prepare.digest
+            commit_quorum.retain(|commit| {
+                if commit.view_num != pre_prepare.view_num {
+                    return false;
+                }
+                if commit.digest != pre_prepare.digest {
+                    conflict(commit.replica_id);
+                    return false;
+                }
+                true
             })
         }
         Ok(())
@@ -448,6 +869,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<Prepare>, C> for
             return Ok(());
         }
         context.peer_net().send(All, prepare.clone())?;
+        self.metrics.prepares_sent += 1;
         if self.log[prepare.op_num as usize].prepares.is_empty() {
             self.insert_prepare(prepare, context)?
         }
@@ -487,6 +909,69 @@ impl<S: App, A: Addr> State<S, A> {
         prepare: Verifiable<Prepare>,
         context: &mut impl Context<Self, A>,
     ) -> anyhow::Result<bool> {
+        let Some(prepare) = self.check_prepare(prepare, context)? else {
+            return Ok(false);
+        };
+        self.metrics.signature_verifications += 1;
+        let cache = self.verified.clone();
+        context
+            .crypto_worker()
+            .submit(Box::new(move |crypto, context| {
+                match cache.verify("Prepare", prepare.replica_id, &prepare, crypto) {
+                    Ok(()) => context.send(Verified(prepare)),
+                    Err(CryptoError::BadSignature) => {
+                        warn_bad_signature("Prepare", prepare.replica_id);
+                        Ok(())
+                    }
+                    Err(_) => Ok(()),
+                }
+            }))?;
+        Ok(true)
+    }
+
+    // the pending Prepares that pile up while one is verifying are verified together in one worker
+    // task, which is a single batch verification with `Schnorrkel`. in the common case it is the
+    // rest of the quorum
+    fn submit_pending_prepares(
+        &mut self,
+        op_num: u32,
+        context: &mut impl Context<Self, A>,
+    ) -> anyhow::Result<()> {
+        let Some(pending_prepares) = self.pending_prepares.get_mut(&op_num) else {
+            return Ok(());
+        };
+        let mut prepares = Vec::new();
+        for prepare in std::mem::take(pending_prepares) {
+            prepares.extend(self.check_prepare(prepare, context)?)
+        }
+        if prepares.is_empty() || !self.pending_prepares.contains_key(&op_num) {
+            // there's no pending task, remove the task list to indicate
+            self.pending_prepares.remove(&op_num);
+            return Ok(());
+        }
+        self.metrics.signature_verifications += prepares.len() as u64;
+        let cache = self.verified.clone();
+        context
+            .crypto_worker()
+            .submit(Box::new(move |crypto, context| {
+                let indexes = prepares
+                    .iter()
+                    .map(|prepare| prepare.replica_id)
+                    .collect::<Vec<_>>();
+                let verified = cache.verify_batch("Prepare", &indexes, prepares, crypto)?;
+                if verified.is_empty() {
+                    return Ok(());
+                }
+                context.send(verified.into_iter().map(Verified).collect::<Vec<_>>())
+            }))
+    }
+
+    // return the Prepare if it is to be verified
+    fn check_prepare(
+        &mut self,
+        prepare: Verifiable<Prepare>,
+        context: &mut impl Context<Self, A>,
+    ) -> anyhow::Result<Option<Verifiable<Prepare>>> {
         if prepare.view_num != self.view_num || self.view_change() {
             if prepare.view_num >= self.view_num {
                 let query_new_view = QueryNewView {
@@ -497,7 +982,7 @@ impl<S: App, A: Addr> State<S, A> {
                     .peer_net()
                     .send(prepare.replica_id, query_new_view)?
             }
-            return Ok(false);
+            return Ok(None);
         }
         if let Some(entry) = self.log.get(prepare.op_num as usize) {
             if !entry.prepares.is_empty() {
@@ -509,24 +994,15 @@ impl<S: App, A: Addr> State<S, A> {
                 // already collected enough Prepare
                 // yet another solution is to do nothing and rely on view change, but let's avoid
                 // that as long as primary is still around
-                return Ok(false);
+                return Ok(None);
             }
             if let Some(pre_prepare) = &entry.pre_prepare {
                 if prepare.digest != pre_prepare.digest {
-                    return Ok(false);
+                    return Ok(None);
                 }
             }
         }
-        context
-            .crypto_worker()
-            .submit(Box::new(move |crypto, context| {
-                if crypto.verify(prepare.replica_id, &prepare).is_ok() {
-                    context.send(Verified(prepare))
-                } else {
-                    Ok(())
-                }
-            }))?;
-        Ok(true)
+        Ok(Some(prepare))
     }
 }
 
@@ -540,21 +1016,32 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<Prepare>, C> f
         if prepare.view_num != self.view_num || !self.pending_prepares.contains_key(&op_num) {
             return Ok(());
         }
+        self.metrics.prepares_verified += 1;
         self.insert_prepare(prepare, context)?;
-        loop {
-            let Some(pending_prepares) = self.pending_prepares.get_mut(&op_num) else {
-                break;
-            };
-            let Some(prepare) = pending_prepares.pop() else {
-                // there's no pending task, remove the task list to indicate
-                self.pending_prepares.remove(&op_num);
-                break;
-            };
-            if self.submit_prepare(prepare, context)? {
-                break;
+        self.submit_pending_prepares(op_num, context)
+    }
+}
+
+impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Vec<Verified<Prepare>>, C>
+    for State<S, A>
+{
+    fn on_event(
+        &mut self,
+        verified: Vec<Verified<Prepare>>,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        let Some(Verified(prepare)) = verified.first() else {
+            return Ok(());
+        };
+        let op_num = prepare.op_num;
+        for Verified(prepare) in verified {
+            if prepare.view_num != self.view_num || !self.pending_prepares.contains_key(&op_num) {
+                continue;
             }
+            self.metrics.prepares_verified += 1;
+            self.insert_prepare(prepare, context)?
         }
-        Ok(())
+        self.submit_pending_prepares(op_num, context)
     }
 }
 
@@ -564,15 +1051,13 @@ impl<S: App, A: Addr> State<S, A> {
         prepare: Verifiable<Prepare>,
         context: &mut impl Context<Self, A>,
     ) -> anyhow::Result<()> {
-        let prepare_quorum = self.prepare_quorums.entry(prepare.op_num).or_default();
-        prepare_quorum.insert(prepare.replica_id, prepare.clone());
-        // println!(
-        //     "{} PrePrepare {} Prepare {}",
-        //     prepare.op_num,
-        //     self.log.get(prepare.op_num as usize).is_some(),
-        //     prepare_quorum.len()
-        // );
-        if prepare_quorum.len() + 1 < self.config.num_replica - self.config.num_faulty {
+        // the PrePrepare of the primary counts as its Prepare
+        let threshold = self.config.num_replica - self.config.num_faulty - 1;
+        let prepare_quorum = self
+            .prepare_quorums
+            .entry(prepare.op_num)
+            .or_insert_with(|| QuorumCert::new(threshold));
+        if !prepare_quorum.insert(prepare.clone()) {
             return Ok(());
         }
         let Some(entry) = self.log.get_mut(prepare.op_num as usize) else {
@@ -583,8 +1068,18 @@ impl<S: App, A: Addr> State<S, A> {
             return Ok(());
         }
         assert!(entry.prepares.is_empty());
-        entry.prepares = self.prepare_quorums.remove(&prepare.op_num).unwrap();
+        entry.prepares = self
+            .prepare_quorums
+            .remove(&prepare.op_num)
+            .unwrap()
+            .into_votes(prepare.digest);
         self.pending_prepares.remove(&prepare.op_num);
+        debug!(
+            replica_id = self.id,
+            view_num = self.view_num,
+            op_num = prepare.op_num,
+            "prepared"
+        );
 
         let commit = Commit {
             view_num: self.view_num,
@@ -602,6 +1097,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<Commit>, C> for
             return Ok(());
         }
         context.peer_net().send(All, commit.clone())?;
+        self.metrics.commits_sent += 1;
         if self.log[commit.op_num as usize].commits.is_empty() {
             self.insert_commit(commit, context)?
         }
@@ -636,6 +1132,69 @@ impl<S: App, A: Addr> State<S, A> {
         commit: Verifiable<Commit>,
         context: &mut impl Context<Self, A>,
     ) -> anyhow::Result<bool> {
+        let Some(commit) = self.check_commit(commit, context)? else {
+            return Ok(false);
+        };
+        self.metrics.signature_verifications += 1;
+        let cache = self.verified.clone();
+        context
+            .crypto_worker()
+            .submit(Box::new(move |crypto, context| {
+                match cache.verify("Commit", commit.replica_id, &commit, crypto) {
+                    Ok(()) => context.send(Verified(commit)),
+                    Err(CryptoError::BadSignature) => {
+                        warn_bad_signature("Commit", commit.replica_id);
+                        Ok(())
+                    }
+                    Err(_) => Ok(()),
+                }
+            }))?;
+        Ok(true)
+    }
+
+    // the pending Commits that pile up while one is verifying are verified together in one worker
+    // task, which is a single batch verification with `Schnorrkel`. in the common case it is the
+    // rest of the quorum
+    fn submit_pending_commits(
+        &mut self,
+        op_num: u32,
+        context: &mut impl Context<Self, A>,
+    ) -> anyhow::Result<()> {
+        let Some(pending_commits) = self.pending_commits.get_mut(&op_num) else {
+            return Ok(());
+        };
+        let mut commits = Vec::new();
+        for commit in std::mem::take(pending_commits) {
+            commits.extend(self.check_commit(commit, context)?)
+        }
+        if commits.is_empty() || !self.pending_commits.contains_key(&op_num) {
+            // there's no pending task, remove the task list to indicate
+            self.pending_commits.remove(&op_num);
+            return Ok(());
+        }
+        self.metrics.signature_verifications += commits.len() as u64;
+        let cache = self.verified.clone();
+        context
+            .crypto_worker()
+            .submit(Box::new(move |crypto, context| {
+                let indexes = commits
+                    .iter()
+                    .map(|commit| commit.replica_id)
+                    .collect::<Vec<_>>();
+                let verified = cache.verify_batch("Commit", &indexes, commits, crypto)?;
+                if verified.is_empty() {
+                    return Ok(());
+                }
+                context.send(verified.into_iter().map(Verified).collect::<Vec<_>>())
+            }))
+    }
+
+    // return the Commit if it is to be verified
+    fn check_commit(
+        &mut self,
+        commit: Verifiable<Commit>,
+        context: &mut impl Context<Self, A>,
+    ) -> anyhow::Result<Option<Verifiable<Commit>>> {
         if commit.view_num != self.view_num || self.view_change() {
             if commit.view_num >= self.view_num {
                 let query_new_view = QueryNewView {
@@ -644,28 +1203,19 @@ impl<S: App, A: Addr> State<S, A> {
                 };
                 context.peer_net().send(commit.replica_id, query_new_view)?
             }
-            return Ok(false);
+            return Ok(None);
         }
         if let Some(entry) = self.log.get(commit.op_num as usize) {
             if !entry.commits.is_empty() {
-                return Ok(false);
+                return Ok(None);
             }
             if let Some(pre_prepare) = &entry.pre_prepare {
                 if commit.digest != pre_prepare.digest {
-                    return Ok(false);
+                    return Ok(None);
                 }
             }
         }
-        context
-            .crypto_worker()
-            .submit(Box::new(move |crypto, context| {
-                if crypto.verify(commit.replica_id, &commit).is_ok() {
-                    context.send(Verified(commit))
-                } else {
-                    Ok(())
-                }
-            }))?;
-        Ok(true)
+        Ok(Some(commit))
     }
 }
 
@@ -679,21 +1229,26 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<Commit>, C> fo
         if commit.view_num != self.view_num || !self.pending_commits.contains_key(&op_num) {
             return Ok(());
         }
+        self.metrics.commits_verified += 1;
         self.insert_commit(commit, context)?;
-        loop {
-            let Some(pending_commits) = self.pending_commits.get_mut(&op_num) else {
-                break;
-            };
-            let Some(commit) = pending_commits.pop() else {
-                // there's no pending task, remove the task list to indicate
-                self.pending_commits.remove(&op_num);
-                break;
-            };
-            if self.submit_commit(commit, context)? {
-                break;
+        self.submit_pending_commits(op_num, context)
+    }
+}
+
+impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Vec<Verified<Commit>>, C> for State<S, A> {
+    fn on_event(&mut self, verified: Vec<Verified<Commit>>, context: &mut C) -> anyhow::Result<()> {
+        let Some(Verified(commit)) = verified.first() else {
+            return Ok(());
+        };
+        let op_num = commit.op_num;
+        for Verified(commit) in verified {
+            if commit.view_num != self.view_num || !self.pending_commits.contains_key(&op_num) {
+                continue;
             }
+            self.metrics.commits_verified += 1;
+            self.insert_commit(commit, context)?
         }
-        Ok(())
+        self.submit_pending_commits(op_num, context)
     }
 }
 
@@ -703,17 +1258,12 @@ impl<S: App, A: Addr> State<S, A> {
         commit: Verifiable<Commit>,
         context: &mut impl Context<Self, A>,
     ) -> anyhow::Result<()> {
-        let commit_quorum = self.commit_quorums.entry(commit.op_num).or_default();
-        commit_quorum.insert(commit.replica_id, commit.clone());
-        // println!(
-        //     "[{}] {} PrePrepare {} Commit {}",
-        //     self.id,
-        //     commit.op_num,
-        //     self.log.get(commit.op_num as usize).is_some(),
-        //     commit_quorum.len()
-        // );
-
-        if commit_quorum.len() < self.config.num_replica - self.config.num_faulty {
+        let threshold = self.config.num_replica - self.config.num_faulty;
+        let commit_quorum = self
+            .commit_quorums
+            .entry(commit.op_num)
+            .or_insert_with(|| QuorumCert::new(threshold));
+        if !commit_quorum.insert(commit.clone()) {
             return Ok(());
         }
         let is_primary = self.is_primary();
@@ -725,15 +1275,52 @@ impl<S: App, A: Addr> State<S, A> {
             return Ok(()); // shortcut: probably safe to commit as well
         }
 
-        log_entry.commits = self.commit_quorums.remove(&commit.op_num).unwrap();
+        log_entry.commits = self
+            .commit_quorums
+            .remove(&commit.op_num)
+            .unwrap()
+            .into_votes(commit.digest);
         self.pending_commits.remove(&commit.op_num);
-        // println!("[{}] Commit {}", self.id, commit.op_num);
+        debug!(
+            replica_id = self.id,
+            view_num = self.view_num,
+            op_num = commit.op_num,
+            "committed"
+        );
         if is_primary {
             log_entry.progress_timer.unset(context.schedule())?;
+            let batching = &mut *self.batching;
+            if let (Some(adaptive), Some(proposed)) = (
+                &mut batching.adaptive,
+                batching.proposed.remove(&commit.op_num),
+            ) {
+                adaptive.on_commit(proposed.elapsed(), self.requests.len())
+            }
         } else {
             self.do_view_change_timer.ensure_unset(context.schedule())?;
         }
 
+        self.execute(context)?;
+
+        if self.is_primary() {
+            while !self.requests.is_empty()
+                && self.op_num() <= self.commit_num + self.config.num_concurrent as u32
+            {
+                self.close_batch(context)?
+            }
+        } else if commit.op_num > self.commit_num {
+            for op_num in self.commit_num + 1..=commit.op_num {
+                self.log[op_num as usize]
+                    .state_transfer_timer
+                    .ensure_set(events::StateTransfer(op_num), context.schedule())?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: App, A: Addr> State<S, A> {
+    fn execute(&mut self, context: &mut impl Context<Self, A>) -> anyhow::Result<()> {
         while let Some(log_entry) = self.log.get_mut(self.commit_num as usize + 1) {
             if log_entry.commits.is_empty() {
                 break;
@@ -773,32 +1360,232 @@ impl<S: App, A: Addr> State<S, A> {
                     .send(request.client_addr.clone(), reply)?
             }
         }
-
-        if self.is_primary() {
-            while !self.requests.is_empty()
-                && self.op_num() <= self.commit_num + self.config.num_concurrent as u32
-            {
-                self.close_batch(context)?
-            }
-        } else if commit.op_num > self.commit_num {
-            for op_num in self.commit_num + 1..=commit.op_num {
-                self.log[op_num as usize]
-                    .state_transfer_timer
-                    .ensure_set(events::StateTransfer(op_num), context.schedule())?
-            }
-        }
         Ok(())
     }
 }
 
+// the slot has been committed by others (i.e. the replica has collected a commit certificate for a
+// later slot) but cannot be executed by this replica for long, probably because the replica has
+// missed the messages of it, e.g. was partitioned. fetch the committed entries from the others
+// the query always starts from the next slot to execute, so the timers of the following slots that
+// go off later just repeat the query, until the slots are executed and the timers are unset
 impl<S, A, C: Context<Self, A>> OnErasedEvent<events::StateTransfer, C> for State<S, A> {
     fn on_event(
         &mut self,
-        events::StateTransfer(op_num): events::StateTransfer,
-        _: &mut C,
+        events::StateTransfer(_): events::StateTransfer,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        let query = QueryStateTransfer {
+            op_num: self.commit_num + 1,
+            replica_id: self.id,
+        };
+        context.submit_sign(query)
+    }
+}
+
+impl<S, A, C: Context<Self, A>> OnErasedEvent<events::StateTransferPage, C> for State<S, A> {
+    fn on_event(
+        &mut self,
+        events::StateTransferPage(op_num): events::StateTransferPage,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        // every responder of the previous query may have sent the same full page
+        if op_num != self.commit_num + 1 || op_num <= self.state_transfer_paged {
+            return Ok(());
+        }
+        self.state_transfer_paged = op_num;
+        let query = QueryStateTransfer {
+            op_num,
+            replica_id: self.id,
+        };
+        context.submit_sign(query)
+    }
+}
+
+impl<S, A, C: Context<Self, A>> OnErasedEvent<Signed<QueryStateTransfer>, C> for State<S, A> {
+    fn on_event(
+        &mut self,
+        Signed(query): Signed<QueryStateTransfer>,
+        context: &mut C,
     ) -> anyhow::Result<()> {
-        // TODO
-        anyhow::bail!("{:?} timeout", events::StateTransfer(op_num))
+        context.peer_net().send(All, query)
+    }
+}
+
+// the most entries in one state transfer response
+const STATE_TRANSFER_PAGE_SIZE: usize = 64;
+
+impl<S, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<QueryStateTransfer>>, C>
+    for State<S, A>
+{
+    fn on_event(
+        &mut self,
+        Recv(query): Recv<Verifiable<QueryStateTransfer>>,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        if query.op_num == 0
+            || query.op_num > self.commit_num
+            || query.replica_id as usize >= self.config.num_replica
+            || query.replica_id == self.id
+        {
+            return Ok(());
+        }
+        self.metrics.signature_verifications += 1;
+        context
+            .crypto_worker()
+            .submit(Box::new(move |crypto, context| {
+                if crypto.verify(query.replica_id, &query).is_ok() {
+                    context.send(Verified(query))
+                } else {
+                    Ok(())
+                }
+            }))
+    }
+}
+
+impl<S, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<QueryStateTransfer>, C>
+    for State<S, A>
+{
+    fn on_event(
+        &mut self,
+        Verified(query): Verified<QueryStateTransfer>,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        if query.op_num > self.commit_num {
+            return Ok(());
+        }
+        // the executed slots that are being redone by a view change have their certificates
+        // cleared for now, and are served after they are committed again
+        let entries = self.log[query.op_num as usize..=self.commit_num as usize]
+            .iter()
+            .take(STATE_TRANSFER_PAGE_SIZE)
+            .map_while(|entry| {
+                if entry.commits.is_empty() {
+                    return None;
+                }
+                Some((
+                    entry.pre_prepare.clone()?,
+                    entry.requests.clone(),
+                    entry.commits.clone(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        // answered once per query, and a lost response is only recovered by the next query after a
+        // timeout, so it should not be dropped if the transport can help it
+        context
+            .peer_net()
+            .send_reliable(query.replica_id, StateTransfer { entries })
+    }
+}
+
+fn verify_committed<A: Addr>(
+    crypto: &Crypto,
+    pre_prepare: &Verifiable<PrePrepare>,
+    requests: &[Request<A>],
+    commits: &Quorum<Commit>,
+    config: &PublicParameters,
+) -> anyhow::Result<()> {
+    let num_replica = config.num_replica;
+    anyhow::ensure!(
+        config.digest_algorithm.digest(&requests) == pre_prepare.digest
+            || requests.is_empty() && pre_prepare.digest == NO_OP_DIGEST
+    );
+    crypto.verify(pre_prepare.view_num as usize % num_replica, pre_prepare)?;
+    anyhow::ensure!(commits.len() >= num_replica - config.num_faulty);
+    for (replica_id, commit) in commits {
+        anyhow::ensure!(commit.replica_id == *replica_id);
+        anyhow::ensure!(
+            commit.view_num == pre_prepare.view_num
+                && commit.op_num == pre_prepare.op_num
+                && commit.digest == pre_prepare.digest
+        );
+        crypto.verify(commit.replica_id, commit)?
+    }
+    Ok(())
+}
+
+impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<StateTransfer<A>>, C>
+    for State<S, A>
+{
+    fn on_event(
+        &mut self,
+        Recv(state_transfer): Recv<StateTransfer<A>>,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        let mut entries = state_transfer.entries;
+        // a full page is probably followed by more entries on the responder
+        let full_page = entries.len() >= STATE_TRANSFER_PAGE_SIZE;
+        // the prefix that has been executed is not interesting
+        entries.retain(|(pre_prepare, ..)| pre_prepare.op_num > self.commit_num);
+        if entries.first().map(|(pre_prepare, ..)| pre_prepare.op_num) != Some(self.commit_num + 1)
+        {
+            return Ok(());
+        }
+        self.metrics.signature_verifications += entries
+            .iter()
+            .map(|(_, _, commits)| 1 + commits.len() as u64)
+            .sum::<u64>();
+        let config = self.config.clone();
+        context
+            .crypto_worker()
+            .submit(Box::new(move |crypto, context| {
+                // the entries are delivered one by one, and the delivering stops at the first
+                // invalid one, so the delivered ones are always consecutive
+                let mut next_op_num = None;
+                for (pre_prepare, requests, commits) in entries {
+                    if verify_committed(crypto, &pre_prepare, &requests, &commits, &config).is_err()
+                    {
+                        return Ok(());
+                    }
+                    next_op_num = Some(pre_prepare.op_num + 1);
+                    context.send((Verified(pre_prepare), requests, commits))?
+                }
+                match next_op_num {
+                    Some(op_num) if full_page => context.send(events::StateTransferPage(op_num)),
+                    _ => Ok(()),
+                }
+            }))
+    }
+}
+
+impl<S: App, A: Addr, C: Context<Self, A>>
+    OnErasedEvent<(Verified<PrePrepare>, Vec<Request<A>>, Quorum<Commit>), C> for State<S, A>
+{
+    fn on_event(
+        &mut self,
+        (Verified(pre_prepare), requests, commits): (
+            Verified<PrePrepare>,
+            Vec<Request<A>>,
+            Quorum<Commit>,
+        ),
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        let op_num = pre_prepare.op_num;
+        if op_num != self.commit_num + 1 {
+            return Ok(());
+        }
+        if self.log.get(op_num as usize).is_none() {
+            self.log.resize(op_num as usize + 1, self.default_entry());
+        }
+        let log_entry = &mut self.log[op_num as usize];
+        // the slot may be in the middle of the agreement of the current view, and a committed
+        // slot always keeps its digest across views, so keep the PrePrepare in that case to not
+        // disturb the ongoing agreement
+        if log_entry
+            .pre_prepare
+            .as_ref()
+            .map(|prepared| prepared.digest != pre_prepare.digest)
+            .unwrap_or(true)
+        {
+            log_entry.pre_prepare = Some(pre_prepare);
+            log_entry.prepares.clear()
+        }
+        log_entry.requests = requests;
+        log_entry.commits = commits;
+        self.execute(context)
     }
 }
 
@@ -823,8 +1610,9 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<events::DoViewChange, C
         events::DoViewChange(view_num): events::DoViewChange,
         context: &mut C,
     ) -> anyhow::Result<()> {
-        // warn!("[{}] do view change for view {view_num}", self.id);
         assert!(view_num >= self.view_num);
+        self.metrics.view_changes_triggered += 1;
+        info!(replica_id = self.id, view_num, "view change started");
         self.view_num = view_num;
         // let DoViewChange(also_view_num) =
         self.do_view_change_timer.unset(context.schedule())?;
@@ -884,6 +1672,16 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<ViewChange>, C>
     }
 }
 
+// the ViewChange itself and the prepared certificates it carries, i.e. what `verify_view_change`
+// verifies
+fn num_signatures(view_change: &Verifiable<ViewChange>) -> u64 {
+    1 + view_change
+        .log
+        .iter()
+        .map(|(_, prepares)| 1 + prepares.len() as u64)
+        .sum::<u64>()
+}
+
 fn verify_view_change(
     crypto: &Crypto,
     view_change: &Verifiable<ViewChange>,
@@ -913,6 +1711,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<ViewCha
         if view_change.view_num < self.view_num {
             return Ok(());
         }
+        self.metrics.signature_verifications += num_signatures(&view_change);
         let num_replica = self.config.num_replica;
         let num_faulty = self.config.num_faulty;
         context
@@ -1118,6 +1917,12 @@ impl<S: App, A: Addr> State<S, A> {
         self.view_changes = self.view_changes.split_off(&(self.view_num + 1));
 
         self.new_views.insert(self.view_num, new_view);
+        info!(
+            replica_id = self.id,
+            view_num = self.view_num,
+            op_num = self.op_num(),
+            "view change completed"
+        );
         Ok(())
     }
 }
@@ -1133,6 +1938,13 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<NewView
         if self.have_entered(new_view.view_num) {
             return Ok(());
         }
+        self.metrics.signature_verifications += 1
+            + new_view
+                .view_changes
+                .values()
+                .map(num_signatures)
+                .sum::<u64>()
+            + new_view.pre_prepares.len() as u64;
         let num_replica = self.config.num_replica;
         let num_faulty = self.config.num_faulty;
         context
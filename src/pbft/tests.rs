@@ -1,21 +1,31 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use bytes::Bytes;
 use derive_more::From;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    crypto::{Crypto, Verifiable},
+    crypto::{Crypto, DigestHash as _, Verifiable, H256},
     event::{
         combinators::{erase::Transient as EraseTransient, Transient},
-        Erase, OnErasedEvent, ScheduleEvent, UntypedEvent, Work,
+        Erase, OnErasedEvent, ScheduleEvent, SendEvent, UntypedEvent, Work,
+    },
+    net::{
+        combinators::{All, Multicast},
+        events::{Cast, Recv, ReliableCast},
+        SendMessage,
     },
-    net::{combinators::All, events::Recv, SendMessage},
     workload::{app::kvstore, events::Invoke, CloseLoop, Workload},
 };
 
 use super::{
     client,
-    messages::{Commit, NewView, PrePrepare, Prepare, QueryNewView, Reply, Request, ViewChange},
+    messages::{
+        Commit, NewView, PrePrepare, Prepare, QueryNewView, QueryStateTransfer, Quorum, Reply,
+        Request, StateTransfer, ViewChange,
+    },
     replica::{self, PeerNet},
+    DigestAlgorithm,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -36,6 +46,8 @@ pub enum Message {
     ViewChange(Verifiable<ViewChange>),
     NewView(Verifiable<NewView>),
     QueryNewView(QueryNewView),
+    QueryStateTransfer(Verifiable<QueryStateTransfer>),
+    StateTransfer(StateTransfer<Addr>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -135,6 +147,12 @@ where
             Event::Message(_, Message::QueryNewView(message)) => {
                 self.on_event(Recv(message), context)
             }
+            Event::Message(_, Message::QueryStateTransfer(message)) => {
+                self.on_event(Recv(message), context)
+            }
+            Event::Message(_, Message::StateTransfer(message)) => {
+                self.on_event(Recv(message), context)
+            }
             Event::Timer(_, _, timer) => {
                 // context.schedule.tick(id)?;
                 match timer {
@@ -159,6 +177,123 @@ where
     }
 }
 
+// a Byzantine replica that tries to break agreement in view 0. as the primary it proposes every
+// request to the odd-numbered replicas and a no-op to the even-numbered ones for the same op
+// number, and as anyone it votes (Prepare and Commit) for every proposal it has heard of, so that
+// conflicting certificates form as soon as honest replicas can be fooled
+// it never sends Prepare as the primary: honest replicas already count the primary's PrePrepare
+// into the prepare quorum
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AdversaryState {
+    id: u8,
+    num_replica: usize,
+    op_num: u32,
+    voted: BTreeSet<(u32, H256)>, // (op number, digest)
+}
+
+impl AdversaryState {
+    pub fn new(id: u8, num_replica: usize) -> Self {
+        Self {
+            id,
+            num_replica,
+            op_num: 0,
+            voted: Default::default(),
+        }
+    }
+
+    pub fn relabel_clients(&mut self, relabel: &Relabel) {
+        self.voted = self
+            .voted
+            .iter()
+            .map(|(op_num, digest)| (*op_num, relabel.digest(*digest)))
+            .collect()
+    }
+
+    fn vote<N>(
+        &mut self,
+        op_num: u32,
+        digest: H256,
+        context: &mut ReplicaContext<'_, N, impl Sized>,
+    ) -> anyhow::Result<()>
+    where
+        N: SendMessage<All, Verifiable<Prepare>> + SendMessage<All, Verifiable<Commit>>,
+    {
+        if !self.voted.insert((op_num, digest)) {
+            return Ok(());
+        }
+        if self.id != 0 {
+            let prepare = Prepare {
+                view_num: 0,
+                op_num,
+                digest,
+                replica_id: self.id,
+            };
+            context.net.send(All, context.crypto.sign(prepare))?
+        }
+        let commit = Commit {
+            view_num: 0,
+            op_num,
+            digest,
+            replica_id: self.id,
+        };
+        context.net.send(All, context.crypto.sign(commit))
+    }
+}
+
+impl<N, T, D> OnErasedEvent<Event<D>, ReplicaContext<'_, N, T>> for AdversaryState
+where
+    N: SendMessage<Addr, (Verifiable<PrePrepare>, Vec<Request<Addr>>)>
+        + SendMessage<All, Verifiable<Prepare>>
+        + SendMessage<All, Verifiable<Commit>>,
+{
+    fn on_event(
+        &mut self,
+        event: Event<D>,
+        context: &mut ReplicaContext<'_, N, T>,
+    ) -> anyhow::Result<()> {
+        let Event::Message(_, message) = event else {
+            return Ok(());
+        };
+        match message {
+            Message::Request(request) if self.id == 0 => {
+                let requests = vec![request];
+                let digest = requests.sha256();
+                if self.voted.iter().any(|(_, voted)| *voted == digest) {
+                    return Ok(());
+                }
+                self.op_num += 1;
+                let pre_prepare = |digest| PrePrepare {
+                    view_num: 0,
+                    op_num: self.op_num,
+                    digest,
+                };
+                let pre_prepare_request = context.crypto.sign(pre_prepare(digest));
+                let pre_prepare_no_op = context.crypto.sign(pre_prepare(H256::zero()));
+                for id in 1..self.num_replica as u8 {
+                    let message = if id % 2 == 1 {
+                        (pre_prepare_request.clone(), requests.clone())
+                    } else {
+                        (pre_prepare_no_op.clone(), Default::default())
+                    };
+                    context.net.send(Addr::Replica(id), message)?
+                }
+                self.vote(self.op_num, digest, context)?;
+                self.vote(self.op_num, H256::zero(), context)
+            }
+            Message::PrePrepare(pre_prepare, _) if pre_prepare.view_num == 0 => {
+                self.vote(pre_prepare.op_num, pre_prepare.digest, context)
+            }
+            Message::Prepare(prepare) if prepare.view_num == 0 => {
+                self.vote(prepare.op_num, prepare.digest, context)
+            }
+            Message::Commit(commit) if commit.view_num == 0 => {
+                self.vote(commit.op_num, commit.digest, context)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 fn fix_invoke<'a, N, W: Workload<Op = Bytes, Result = Bytes>, T>(
     client: &mut client::State<Addr>,
     context: &mut ClientContext<'a, N, W, T>,
@@ -198,31 +333,172 @@ where
     Ok(())
 }
 
-#[derive(Debug)]
-pub struct NetworkContext<'a, N> {
-    state: &'a mut N,
-    all: Vec<Addr>,
+// relabel the clients for the symmetry reduction of model checking, client `id` becomes `ids[id]`
+// the digests of request batches cover the client identities, so they are rewritten as well. the
+// batches are collected with `insert_batch` in advance, and the digests of unknown batches are kept
+// as is
+pub struct Relabel {
+    ids: Vec<u32>,
+    digests: BTreeMap<H256, H256>,
+    digest_algorithm: DigestAlgorithm,
 }
 
-impl<N: SendMessage<Addr, M>, M: Clone> SendMessage<All, M> for NetworkContext<'_, N> {
-    fn send(&mut self, All: All, message: M) -> anyhow::Result<()> {
-        for addr in self.all.clone() {
-            SendMessage::send(self.state, addr, message.clone())?
+impl Relabel {
+    pub fn new(ids: Vec<u32>, digest_algorithm: DigestAlgorithm) -> Self {
+        Self {
+            ids,
+            digests: Default::default(),
+            digest_algorithm,
+        }
+    }
+
+    pub fn insert_batch(&mut self, digest: H256, requests: &[Request<Addr>]) {
+        if !requests.is_empty() {
+            let relabeled = self
+                .digest_algorithm
+                .digest(&self.requests(requests.to_vec()));
+            self.digests.insert(digest, relabeled);
+        }
+    }
+
+    pub fn client_id(&self, id: u32) -> u32 {
+        self.ids[id as usize]
+    }
+
+    pub fn addr(&self, addr: Addr) -> Addr {
+        match addr {
+            Addr::Client(id) => Addr::Client(self.client_id(id as _) as _),
+            addr => addr,
+        }
+    }
+
+    pub fn digest(&self, digest: H256) -> H256 {
+        self.digests.get(&digest).copied().unwrap_or(digest)
+    }
+
+    pub fn request(&self, request: Request<Addr>) -> Request<Addr> {
+        Request {
+            client_id: self.client_id(request.client_id),
+            client_addr: self.addr(request.client_addr),
+            ..request
+        }
+    }
+
+    pub fn requests(&self, requests: Vec<Request<Addr>>) -> Vec<Request<Addr>> {
+        requests
+            .into_iter()
+            .map(|request| self.request(request))
+            .collect()
+    }
+
+    pub fn pre_prepare(&self, pre_prepare: Verifiable<PrePrepare>) -> Verifiable<PrePrepare> {
+        pre_prepare.map(|pre_prepare| PrePrepare {
+            digest: self.digest(pre_prepare.digest),
+            ..pre_prepare
+        })
+    }
+
+    pub fn prepare(&self, prepare: Verifiable<Prepare>) -> Verifiable<Prepare> {
+        prepare.map(|prepare| Prepare {
+            digest: self.digest(prepare.digest),
+            ..prepare
+        })
+    }
+
+    pub fn commit(&self, commit: Verifiable<Commit>) -> Verifiable<Commit> {
+        commit.map(|commit| Commit {
+            digest: self.digest(commit.digest),
+            ..commit
+        })
+    }
+
+    pub fn quorum<M>(
+        &self,
+        quorum: Quorum<M>,
+        f: impl Fn(&Self, Verifiable<M>) -> Verifiable<M>,
+    ) -> Quorum<M> {
+        quorum
+            .into_iter()
+            .map(|(id, message)| (id, f(self, message)))
+            .collect()
+    }
+
+    pub fn view_change(&self, view_change: Verifiable<ViewChange>) -> Verifiable<ViewChange> {
+        view_change.map(|view_change| ViewChange {
+            log: view_change
+                .log
+                .into_iter()
+                .map(|(pre_prepare, prepares)| {
+                    (
+                        self.pre_prepare(pre_prepare),
+                        self.quorum(prepares, Self::prepare),
+                    )
+                })
+                .collect(),
+            ..view_change
+        })
+    }
+
+    pub fn new_view(&self, new_view: Verifiable<NewView>) -> Verifiable<NewView> {
+        new_view.map(|new_view| NewView {
+            view_changes: self.quorum(new_view.view_changes, Self::view_change),
+            pre_prepares: new_view
+                .pre_prepares
+                .into_iter()
+                .map(|pre_prepare| self.pre_prepare(pre_prepare))
+                .collect(),
+            ..new_view
+        })
+    }
+
+    pub fn state_transfer(&self, state_transfer: StateTransfer<Addr>) -> StateTransfer<Addr> {
+        StateTransfer {
+            entries: state_transfer
+                .entries
+                .into_iter()
+                .map(|(pre_prepare, requests, commits)| {
+                    (
+                        self.pre_prepare(pre_prepare),
+                        self.requests(requests),
+                        self.quorum(commits, Self::commit),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn message(&self, message: Message) -> Message {
+        match message {
+            Message::Request(request) => Message::Request(self.request(request)),
+            Message::PrePrepare(pre_prepare, requests) => {
+                Message::PrePrepare(self.pre_prepare(pre_prepare), self.requests(requests))
+            }
+            Message::Prepare(prepare) => Message::Prepare(self.prepare(prepare)),
+            Message::Commit(commit) => Message::Commit(self.commit(commit)),
+            Message::ViewChange(view_change) => Message::ViewChange(self.view_change(view_change)),
+            Message::NewView(new_view) => Message::NewView(self.new_view(new_view)),
+            Message::StateTransfer(state_transfer) => {
+                Message::StateTransfer(self.state_transfer(state_transfer))
+            }
+            message @ (Message::Reply(_)
+            | Message::QueryNewView(_)
+            | Message::QueryStateTransfer(_)) => message,
         }
-        Ok(())
     }
 }
 
+pub type NetworkContext<'a, N> = Multicast<Addr, &'a mut N>;
+
 // only for client, feel lazy to make distinct wrappers for client and replica
-impl<N: SendMessage<Addr, M>, M> SendMessage<u8, M> for NetworkContext<'_, N> {
-    fn send(&mut self, remote: u8, message: M) -> anyhow::Result<()> {
-        SendMessage::send(self.state, Addr::Replica(remote), message)
+impl<N: SendEvent<Cast<Addr, M>>, M> SendEvent<Cast<u8, M>> for Multicast<Addr, N> {
+    fn send(&mut self, Cast(remote, message): Cast<u8, M>) -> anyhow::Result<()> {
+        SendEvent::send(self, Cast(Addr::Replica(remote), message))
     }
 }
 
-impl<N: SendMessage<Addr, M>, M> SendMessage<Addr, M> for NetworkContext<'_, N> {
-    fn send(&mut self, remote: Addr, message: M) -> anyhow::Result<()> {
-        SendMessage::send(self.state, remote, message)
+impl<N: SendEvent<ReliableCast<Addr, M>>, M> SendEvent<ReliableCast<u8, M>> for Multicast<Addr, N> {
+    fn send(&mut self, ReliableCast(remote, message): ReliableCast<u8, M>) -> anyhow::Result<()> {
+        SendEvent::send(self, ReliableCast(Addr::Replica(remote), message))
     }
 }
 
@@ -286,26 +562,64 @@ where
 }
 
 mod search {
-    use std::borrow::Borrow;
+    use std::{
+        array::IntoIter,
+        borrow::Borrow,
+        num::NonZeroUsize,
+        sync::{
+            atomic::{AtomicUsize, Ordering::SeqCst},
+            Arc,
+        },
+        time::Duration,
+    };
 
     use bytes::Bytes;
     use derive_where::derive_where;
 
     use crate::{
-        crypto::Crypto,
-        event::{combinators::Transient, OnErasedEvent as _, SendEvent},
-        model::search::state::{Network, Schedule, TimerId},
-        pbft::{client, replica},
-        workload::{events::Invoke, CloseLoop, Workload},
+        codec::{Decode, Encode},
+        crypto::{events::Verified, Crypto, CryptoError, CryptoFlavor, DigestHash as _},
+        event::{
+            combinators::{Coalesce, Transient},
+            OnErasedEvent, SendEvent,
+        },
+        model::search::{
+            breadth_first, random_depth_first,
+            state::{Network, NetworkFault, NetworkFaults, Schedule, TimerId},
+            Identity, Invariants, SearchProgress, SearchResult, Settings, Uniform,
+        },
+        pbft::{
+            audit, client,
+            messages::{Commit, Prepare},
+            replica, DigestAlgorithm, PublicParameters,
+        },
+        workload::{
+            app::kvstore::{self, KVStore},
+            combinators::UncheckedIter,
+            events::Invoke,
+            CloseLoop, Workload,
+        },
     };
 
-    use super::{Addr, Message, NetworkContext, ReplicaState, Timer};
+    use super::{
+        fix_invoke, Addr, AdversaryState, Message, NetworkContext, Relabel, ReplicaState, Timer,
+    };
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
+    #[derive_where(PartialEq, Eq, Hash; N)]
     pub struct State<W, N> {
         pub clients: Vec<(client::State<Addr>, ClientContextState<W>)>,
-        pub replicas: Vec<(ReplicaState, ReplicaContextState)>,
+        pub replicas: Vec<(Replica, ReplicaContextState)>,
         network: N,
+        config: PublicParameters,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    #[allow(clippy::large_enum_variant)]
+    pub enum Replica {
+        Honest(ReplicaState),
+        Adversary(AdversaryState),
+        Crashed,
     }
 
     #[derive(Debug, Clone)]
@@ -335,6 +649,7 @@ mod search {
     where
         for<'a> ClientContext<'a, N, W>: client::Context<Addr>,
         for<'a> ReplicaContext<'a, N>: replica::Context<ReplicaState, Addr>,
+        for<'a> AdversaryState: OnErasedEvent<Event, ReplicaContext<'a, N>>,
     {
         fn send(&mut self, event: Event) -> anyhow::Result<()> {
             match event {
@@ -346,10 +661,11 @@ mod search {
                         context.schedule.tick(id)?
                     }
                     let mut context = ClientContext {
-                        net: NetworkContext {
-                            state: &mut self.network,
-                            all: (0..self.replicas.len() as u8).map(Addr::Replica).collect(),
-                        },
+                        net: NetworkContext::new(
+                            (0..self.replicas.len() as u8).map(Addr::Replica).collect(),
+                            None,
+                            &mut self.network,
+                        ),
                         upcall: &mut context.upcall,
                         schedule: &mut context.schedule,
                     };
@@ -357,10 +673,7 @@ mod search {
                 }
                 Event::Message(Addr::Replica(index), _)
                 | Event::Timer(Addr::Replica(index), ..) => {
-                    let all = (0..self.replicas.len() as u8)
-                        .filter(|id| *id != index)
-                        .map(Addr::Replica)
-                        .collect();
+                    let addrs = (0..self.replicas.len() as u8).map(Addr::Replica).collect();
                     let Some((replica, context)) = self.replicas.get_mut(index as usize) else {
                         anyhow::bail!("missing replica for index {index}")
                     };
@@ -368,15 +681,16 @@ mod search {
                         context.schedule.tick(id)?
                     }
                     let mut context = ReplicaContext {
-                        net: NetworkContext {
-                            state: &mut self.network,
-                            all,
-                        },
+                        net: NetworkContext::new(addrs, index as usize, &mut self.network),
                         crypto_worker: Transient::new(),
                         schedule: &mut context.schedule,
                         crypto: &mut context.crypto,
                     };
-                    replica.on_event(event, &mut context)
+                    match replica {
+                        Replica::Honest(replica) => replica.on_event(event, &mut context),
+                        Replica::Adversary(replica) => replica.on_event(event, &mut context),
+                        Replica::Crashed => Ok(()),
+                    }
                 }
             }?;
             Ok(())
@@ -387,6 +701,7 @@ mod search {
     where
         for<'a> ClientContext<'a, N, W>: client::Context<Addr>,
         for<'a> ReplicaContext<'a, N>: replica::Context<ReplicaState, Addr>,
+        for<'a> AdversaryState: OnErasedEvent<Event, ReplicaContext<'a, N>>,
         N: Borrow<Network<Addr, Message>>,
     {
         type Event = Event;
@@ -419,6 +734,687 @@ mod search {
                 .chain(replica_timers)
         }
     }
+
+    impl<W> State<W, Network<Addr, Message>> {
+        // replicas with ids in `adversaries` run `AdversaryState` instead of the protocol
+        pub fn new(config: PublicParameters, adversaries: &[u8]) -> anyhow::Result<Self> {
+            let replicas = (0..config.num_replica as u8)
+                .map(|id| {
+                    let replica = if adversaries.contains(&id) {
+                        Replica::Adversary(AdversaryState::new(id, config.num_replica))
+                    } else {
+                        Replica::Honest(ReplicaState::new(
+                            id,
+                            Decode::json(Encode::json(KVStore::new())),
+                            config.clone(),
+                        )?)
+                    };
+                    let context = ReplicaContextState {
+                        crypto: Crypto::new_hardcoded(config.num_replica, id, CryptoFlavor::Plain)?,
+                        schedule: Schedule::new(),
+                    };
+                    Ok((replica, context))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            Ok(Self {
+                clients: Default::default(),
+                replicas,
+                network: Network::new(),
+                config,
+            })
+        }
+    }
+
+    impl<W: Clone> State<W, Network<Addr, Message>> {
+        // relabel the clients into the order of their states, so the states that only differ in
+        // which client is which are explored once. the clients that are tied keep their current
+        // order, which leaves part of the symmetry unreduced but is still sound
+        pub fn canonicalize(&self) -> Self {
+            let keys = self
+                .clients
+                .iter()
+                .enumerate()
+                .map(|(index, (client, context))| {
+                    let mut client = client.clone();
+                    client.relabel(0, Addr::Client(0));
+                    let entries = self
+                        .replicas
+                        .iter()
+                        .map(|(replica, _)| match replica {
+                            Replica::Honest(replica) => replica.client_entry(index as _).cloned(),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    (client, &context.schedule, entries).sha256()
+                })
+                .collect::<Vec<_>>();
+            let mut order = (0..self.clients.len()).collect::<Vec<_>>();
+            order.sort_by_key(|index| keys[*index]);
+            if order.iter().enumerate().all(|(id, index)| id == *index) {
+                return self.clone();
+            }
+            let mut ids = vec![0; order.len()];
+            for (id, index) in order.iter().enumerate() {
+                ids[*index] = id as _
+            }
+            let mut relabel = Relabel::new(ids, self.config.digest_algorithm);
+            for (replica, _) in &self.replicas {
+                if let Replica::Honest(replica) = replica {
+                    for (digest, requests) in replica.batches() {
+                        relabel.insert_batch(digest, requests)
+                    }
+                }
+            }
+            for (_, message) in self.network.events() {
+                match message {
+                    Message::PrePrepare(pre_prepare, requests) => {
+                        relabel.insert_batch(pre_prepare.digest, &requests)
+                    }
+                    Message::StateTransfer(state_transfer) => {
+                        for (pre_prepare, requests, _) in &state_transfer.entries {
+                            relabel.insert_batch(pre_prepare.digest, requests)
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut state = self.clone();
+            state.clients = order
+                .iter()
+                .map(|index| {
+                    let (mut client, context) = self.clients[*index].clone();
+                    let id = relabel.client_id(*index as _);
+                    client.relabel(id, Addr::Client(id as _));
+                    (client, context)
+                })
+                .collect();
+            for (replica, _) in &mut state.replicas {
+                match replica {
+                    Replica::Honest(replica) => replica.relabel_clients(&relabel),
+                    Replica::Adversary(replica) => replica.relabel_clients(&relabel),
+                    Replica::Crashed => {}
+                }
+            }
+            state.network = self
+                .network
+                .clone()
+                .map(|addr, message| (relabel.addr(addr), relabel.message(message)));
+            state
+        }
+    }
+
+    impl<W, N> State<W, N> {
+        // the replica stays silent (and ignores everything) from now on
+        pub fn crash(&mut self, id: u8) {
+            self.replicas[id as usize] = (Replica::Crashed, self.replicas[id as usize].1.clone())
+        }
+    }
+
+    impl<W: Workload<Op = kvstore::Op, Result = kvstore::Result>, N>
+        State<Decode<kvstore::Result, Encode<kvstore::Op, W>>, N>
+    {
+        pub fn push_client(&mut self, workload: W) -> anyhow::Result<()> {
+            let index = self.clients.len();
+            let client =
+                client::State::new(index as _, Addr::Client(index as _), self.config.clone())?;
+            let context = ClientContextState {
+                upcall: CloseLoop::new(Decode::json(Encode::json(workload)), None),
+                schedule: Schedule::new(),
+            };
+            self.clients.push((client, context));
+            Ok(())
+        }
+    }
+
+    impl<W: Workload<Op = Bytes, Result = Bytes>, N> State<W, N>
+    where
+        for<'a> ClientContext<'a, N, W>: client::Context<Addr>,
+    {
+        pub fn init(&mut self) -> anyhow::Result<()> {
+            for (client, context) in &mut self.clients {
+                context.upcall.init()?;
+                let mut context = ClientContext {
+                    net: NetworkContext::new(
+                        (0..self.replicas.len() as u8).map(Addr::Replica).collect(),
+                        None,
+                        &mut self.network,
+                    ),
+                    upcall: &mut context.upcall,
+                    schedule: &mut context.schedule,
+                };
+                fix_invoke(client, &mut context)?
+            }
+            Ok(())
+        }
+    }
+
+    impl<W, N> State<W, N> {
+        // the safety invariant: honest replicas execute the same request batch for each op number
+        pub fn agreement(&self) -> anyhow::Result<()> {
+            let mut committed = Vec::new();
+            for (replica, _) in &self.replicas {
+                let Replica::Honest(replica) = replica else {
+                    continue;
+                };
+                for (index, digest) in replica.committed_digests().enumerate() {
+                    if let Some(other_digest) = committed.get(index) {
+                        anyhow::ensure!(
+                            digest == *other_digest,
+                            "conflicting commit at op number {}",
+                            index + 1
+                        )
+                    } else {
+                        committed.push(digest)
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn agreement_with_f_adversaries() -> anyhow::Result<()> {
+        let result = search_with_adversaries(&[0])?;
+        anyhow::ensure!(
+            matches!(result, SearchResult::SpaceExhausted | SearchResult::Timeout),
+            "{result}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_agreement_with_f_plus_one_adversaries() -> anyhow::Result<()> {
+        let result = search_with_adversaries(&[0, 1])?;
+        anyhow::ensure!(
+            matches!(&result, SearchResult::InvariantViolation(_, name, _) if name == "agreement"),
+            "{result}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn client_recovers_from_silent_primary() -> anyhow::Result<()> {
+        let mut state = State::new(config(), &[])?;
+        // consume the messages on delivery, so the timers go off once the network is drained
+        state.network = Network::with_faults(NetworkFaults {
+            drop: true,
+            ..Default::default()
+        });
+        state.push_client(UncheckedIter::new([kvstore::Op::Put(
+            String::from("foo"),
+            String::from("bar"),
+        )]))?;
+        state.crash(0);
+        state.init()?;
+        // the client resends to the backups, which time out on the relayed request, change to
+        // view 1 and commit it there. with the network drained, the timers go off in turns across
+        // the nodes, so a resending node cannot hold back the others
+        let mut last = None;
+        let mut steps = 0;
+        while !state.clients[0].1.upcall.workload.done {
+            anyhow::ensure!(steps < 10000, "not making progress");
+            let events = crate::model::search::State::events(&state).collect::<Vec<_>>();
+            // messages come before the timers
+            let event = match events.first() {
+                Some(event @ Event::Message(..)) => event,
+                _ => {
+                    let event = events
+                        .iter()
+                        .find(|event| matches!(event, Event::Timer(addr, ..) if Some(*addr) > last))
+                        .or(events.first())
+                        .ok_or(anyhow::format_err!("no event"))?;
+                    if let Event::Timer(addr, ..) = event {
+                        last = Some(*addr)
+                    }
+                    event
+                }
+            }
+            .clone();
+            if let Event::Message(addr, message) = &event {
+                state.network.deliver(*addr, message.clone())?
+            }
+            state.send(event)?;
+            steps += 1
+        }
+        state.agreement()
+    }
+
+    #[test]
+    fn symmetry_reduction() -> anyhow::Result<()> {
+        let mut state = put_state(&[])?;
+        state.push_client(UncheckedIter::new([kvstore::Op::Put(
+            String::from("foo"),
+            String::from("bar"),
+        )]))?;
+        state.init()?;
+        let explored = |canonicalize: fn(&PutState) -> PutState| {
+            let explored = Arc::new(AtomicUsize::new(0));
+            let settings = Settings {
+                invariant: State::agreement,
+                goal: |_: &_| false,
+                prune: |_: &_| false,
+                canonicalize,
+                weight: Uniform,
+                max_depth: Some(NonZeroUsize::new(6).unwrap()),
+                max_states: None,
+                progress: Some(Arc::new({
+                    let explored = explored.clone();
+                    move |progress: SearchProgress| explored.store(progress.explored, SeqCst)
+                })),
+            };
+            let result =
+                breadth_first(state.clone(), settings, NonZeroUsize::new(1).unwrap(), None)?;
+            anyhow::ensure!(matches!(result, SearchResult::SpaceExhausted), "{result}");
+            anyhow::Ok(explored.load(SeqCst))
+        };
+        let full = explored(PutState::clone)?;
+        let reduced = explored(State::canonicalize)?;
+        println!("explored {reduced} states with symmetry reduction, {full} states without");
+        anyhow::ensure!(reduced < full);
+        Ok(())
+    }
+
+    #[test]
+    fn lagging_replica_catches_up() -> anyhow::Result<()> {
+        const NUM_OP: usize = 10;
+        let mut state = State::new(config(), &[])?;
+        // consume the messages on delivery, so that the network is drained eventually
+        state.network = Network::with_faults(NetworkFaults {
+            drop: true,
+            ..Default::default()
+        });
+        state.push_client(UncheckedIter::new(std::array::from_fn::<
+            _,
+            { NUM_OP + 1 },
+            _,
+        >(|i| {
+            kvstore::Op::Put(format!("KEY-{i}"), String::from("bar"))
+        })))?;
+        state.init()?;
+        let num_committed =
+            |state: &PutsState<{ NUM_OP + 1 }>, id: usize| match &state.replicas[id].0 {
+                Replica::Honest(replica) => replica.committed_digests().count(),
+                _ => unreachable!(),
+            };
+        // replica 3 misses everything of the first `NUM_OP` ops
+        while num_committed(&state, 0) < NUM_OP {
+            step(&mut state, Some(Addr::Replica(3)))?
+        }
+        anyhow::ensure!(num_committed(&state, 3) == 0);
+        // and learns that it is behind from the agreement of the last op
+        for _ in 0..10000 {
+            if num_committed(&state, 3) == NUM_OP + 1 {
+                break;
+            }
+            step(&mut state, None)?
+        }
+        anyhow::ensure!(num_committed(&state, 3) == NUM_OP + 1);
+        state.agreement()
+    }
+
+    // deliver the messages first and only fire timers when nothing is in flight, and drop the
+    // messages to `silenced`
+    fn step<const N: usize>(
+        state: &mut PutsState<N>,
+        silenced: Option<Addr>,
+    ) -> anyhow::Result<()> {
+        let event = crate::model::search::State::events(state)
+            .next()
+            .ok_or(anyhow::format_err!("no event"))?;
+        if let Event::Message(addr, message) = &event {
+            if Some(*addr) == silenced {
+                state
+                    .network
+                    .fault(NetworkFault::Drop(*addr, message.clone()))?;
+                return Ok(());
+            }
+            state.network.deliver(*addr, message.clone())?
+        }
+        state.send(event)
+    }
+
+    #[test]
+    fn digest_algorithms() -> anyhow::Result<()> {
+        for digest_algorithm in [DigestAlgorithm::Sha256, DigestAlgorithm::Blake2] {
+            let config = PublicParameters {
+                digest_algorithm,
+                ..config()
+            };
+            let mut state = State::new(config, &[])?;
+            state.network = Network::with_faults(NetworkFaults {
+                drop: true,
+                ..Default::default()
+            });
+            state.push_client(UncheckedIter::new([
+                kvstore::Op::Put(String::from("foo"), String::from("bar")),
+                kvstore::Op::Append(String::from("foo"), String::from("baz")),
+            ]))?;
+            state.init()?;
+            let mut steps = 0;
+            while !state.clients[0].1.upcall.workload.done {
+                anyhow::ensure!(steps < 10000, "{digest_algorithm:?} not making progress");
+                step(&mut state, None)?;
+                steps += 1
+            }
+            state.agreement()?;
+            for (replica, _) in &state.replicas {
+                let Replica::Honest(replica) = replica else {
+                    unreachable!()
+                };
+                for (digest, requests) in replica.batches() {
+                    anyhow::ensure!(digest == digest_algorithm.digest(&requests))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn adaptive_batch() -> anyhow::Result<()> {
+        let target = Duration::from_millis(10);
+        let mut adaptive = replica::AdaptiveBatch::new(target, 8);
+        for _ in 0..10 {
+            adaptive.on_commit(target / 2, 100)
+        }
+        anyhow::ensure!(adaptive.batch_size() == 8);
+        adaptive.on_commit(target * 2, 100);
+        anyhow::ensure!(adaptive.batch_size() == 4);
+        // not growing without requests queueing up
+        adaptive.on_commit(target / 2, 1);
+        anyhow::ensure!(adaptive.batch_size() == 4);
+        adaptive.on_commit(target / 2, 4);
+        anyhow::ensure!(adaptive.batch_size() == 5);
+
+        let config = PublicParameters {
+            max_batch_size: 8,
+            batch_latency_target: Some(target),
+            ..config()
+        };
+        for (replica, _) in &State::<(), _>::new(config, &[])?.replicas {
+            let Replica::Honest(replica) = replica else {
+                unreachable!()
+            };
+            anyhow::ensure!(replica.metrics().batch_size == 1)
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_verified_once() -> anyhow::Result<()> {
+        let mut state = PutState::new(config(), &[])?;
+        let prepare = Prepare {
+            view_num: 0,
+            op_num: 1,
+            digest: Default::default(),
+            replica_id: 2,
+        };
+        let signed = state.replicas[2].1.crypto.sign(prepare.clone());
+        let cache_hits = |state: &PutState| match &state.replicas[1].0 {
+            Replica::Honest(replica) => replica.metrics().verified_cache_hits,
+            _ => unreachable!(),
+        };
+        for _ in 0..10 {
+            state.send(Event::Message(
+                Addr::Replica(1),
+                Message::Prepare(signed.clone()),
+            ))?
+        }
+        anyhow::ensure!(cache_hits(&state) == 9);
+        // same message signed by someone else
+        let forged = state.replicas[3].1.crypto.sign(prepare);
+        state.send(Event::Message(Addr::Replica(1), Message::Prepare(forged)))?;
+        anyhow::ensure!(cache_hits(&state) == 9);
+        Ok(())
+    }
+
+    #[test]
+    fn digest_conflict() -> anyhow::Result<()> {
+        let mut state = put_state(&[])?;
+        state.network = Network::with_faults(NetworkFaults {
+            drop: true,
+            ..Default::default()
+        });
+        state.init()?;
+        // replica 2 equivocates to replica 1 ahead of the PrePrepare
+        let prepare = Prepare {
+            view_num: 0,
+            op_num: 1,
+            digest: [1; 32].into(),
+            replica_id: 2,
+        };
+        let signed = state.replicas[2].1.crypto.sign(prepare);
+        state.send(Event::Message(Addr::Replica(1), Message::Prepare(signed)))?;
+        let metrics = |state: &PutState| match &state.replicas[1].0 {
+            Replica::Honest(replica) => replica.metrics(),
+            _ => unreachable!(),
+        };
+        anyhow::ensure!(metrics(&state).digest_conflicts == 0);
+        while !state.clients[0].1.upcall.workload.done {
+            step(&mut state, None)?
+        }
+        state.agreement()?;
+        anyhow::ensure!(metrics(&state).digest_conflicts == 1);
+        anyhow::ensure!(metrics(&state).conflicting_replicas == [2].into());
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_configs() -> anyhow::Result<()> {
+        anyhow::ensure!(PublicParameters::builder(4, 1).build()? == config());
+        let builder = || PublicParameters::builder(4, 1);
+        for rejected in [
+            PublicParameters::builder(3, 1),
+            PublicParameters::builder(300, 1),
+            builder().with_num_concurrent(0),
+            builder().with_max_batch_size(0),
+            builder().with_durations(Duration::ZERO),
+            builder()
+                .with_durations(Duration::from_millis(100))
+                .with_client_max_resend_interval(Duration::from_millis(50)),
+            builder().with_batch_latency_target(Duration::ZERO),
+        ] {
+            anyhow::ensure!(rejected.build().is_err())
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn resend_backoff() -> anyhow::Result<()> {
+        let config = PublicParameters {
+            client_max_resend_interval: Some(Duration::from_millis(400)),
+            ..config()
+        };
+        let mut state = State::new(config, &[])?;
+        state.push_client(UncheckedIter::new([kvstore::Op::Get(String::from("foo"))]))?;
+        state.init()?;
+        // no message is ever delivered, so the client keeps resending
+        let mut intervals = Vec::new();
+        for _ in 0..5 {
+            let schedule = &state.clients[0].1.schedule;
+            let Some((id, timer)) = schedule.events().next() else {
+                anyhow::bail!("missing resend timer")
+            };
+            intervals.push(schedule.period(id).unwrap().as_millis());
+            state.send(Event::Timer(Addr::Client(0), id, timer))?
+        }
+        anyhow::ensure!(intervals == [100, 200, 400, 400, 400], "{intervals:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn client_restart() -> anyhow::Result<()> {
+        let append = |postfix: &str| {
+            UncheckedIter::new([kvstore::Op::Append(String::from("foo"), postfix.into())])
+        };
+        let mut state = State::new(config(), &[])?;
+        state.network = Network::with_faults(NetworkFaults {
+            drop: true,
+            ..Default::default()
+        });
+        state.push_client(append("a"))?;
+        state.init()?;
+        // the client crashes with the first append in flight, and restarts with the same id
+        let seq = state.clients[0].0.seq();
+        state.clients.clear();
+        state.push_client(append("b"))?;
+        state.clients[0].0 = client::State::recover(0, Addr::Client(0), config(), seq)?;
+        state.init()?;
+        while !state.clients[0].1.upcall.workload.done {
+            step(&mut state, None)?
+        }
+        let Replica::Honest(replica) = &state.replicas[0].0 else {
+            unreachable!()
+        };
+        let Some((2, Some(reply))) = replica.client_entry(0) else {
+            anyhow::bail!("second append not executed")
+        };
+        // rather than replied with the result of the first one
+        let result = serde_json::from_slice::<kvstore::Result>(&reply.result)?;
+        anyhow::ensure!(
+            result == kvstore::Result::AppendResult("ab".into()),
+            "{result:?}"
+        );
+        state.agreement()
+    }
+
+    #[test]
+    fn audit_log() -> anyhow::Result<()> {
+        let mut state = put_state(&[])?;
+        state.network = Network::with_faults(NetworkFaults {
+            drop: true,
+            ..Default::default()
+        });
+        state.init()?;
+        while !state.clients[0].1.upcall.workload.done {
+            step(&mut state, None)?
+        }
+        let Replica::Honest(replica) = &state.replicas[0].0 else {
+            unreachable!()
+        };
+        let mut log = replica.export_audit_log();
+        anyhow::ensure!(!log.is_empty());
+        // the auditor is not one of the replicas, any public key set works
+        let crypto = Crypto::new_hardcoded(config().num_replica, 0usize, CryptoFlavor::Plain)?;
+        audit::verify(&log, &crypto, &config())?;
+        log[0].digest = [1; 32].into();
+        anyhow::ensure!(audit::verify(&log, &crypto, &config()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_commit_quorum() -> anyhow::Result<()> {
+        // 7 replicas tolerating 2 faulty ones, so a Commit quorum is 5 signatures
+        let crypto = (0..7usize)
+            .map(|index| Crypto::new_hardcoded(7, index, CryptoFlavor::Schnorrkel))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let commits = (0..5u8)
+            .map(|replica_id| {
+                crypto[replica_id as usize].sign(Commit {
+                    view_num: 0,
+                    op_num: 1,
+                    digest: Default::default(),
+                    replica_id,
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut indexes = commits
+            .iter()
+            .map(|commit| commit.replica_id)
+            .collect::<Vec<_>>();
+        let verified = crypto[6].verify_batch_or_each(&indexes, commits.clone())?;
+        anyhow::ensure!(verified == commits);
+        // an unmatched Commit is rejected rather than silently dropped
+        anyhow::ensure!(crypto[6]
+            .verify_batch_or_each(&indexes[..4], commits.clone())
+            .is_err_and(|err| err == CryptoError::LengthMismatch(4, 5)));
+
+        // the batch fails as a whole, and the falsely attributed one is left out
+        indexes[4] = 5;
+        let verified = crypto[6].verify_batch_or_each(&indexes, commits.clone())?;
+        anyhow::ensure!(verified == commits[..4]);
+        Ok(())
+    }
+
+    #[test]
+    fn coalesce_verified() -> anyhow::Result<()> {
+        let state = PutState::new(config(), &[])?;
+        let verified = [2, 3].map(|replica_id| {
+            Verified(state.replicas[replica_id as usize].1.crypto.sign(Prepare {
+                view_num: 0,
+                op_num: 1,
+                digest: Default::default(),
+                replica_id,
+            }))
+        });
+        let mut coalesce = Coalesce::new();
+        for i in [0, 1, 0, 0, 1] {
+            coalesce.send(verified[i].clone())?
+        }
+        anyhow::ensure!(coalesce.len() == 2);
+        let mut delivered = Transient::<Verified<Prepare>>::new();
+        coalesce.flush(&mut delivered)?;
+        anyhow::ensure!(coalesce.is_empty());
+        anyhow::ensure!(*delivered == verified);
+        // pending again after flushed
+        coalesce.send(verified[0].clone())?;
+        anyhow::ensure!(coalesce.len() == 1);
+        Ok(())
+    }
+
+    type PutsState<const N: usize> = State<
+        Decode<
+            kvstore::Result,
+            Encode<kvstore::Op, UncheckedIter<kvstore::Result, IntoIter<kvstore::Op, N>>>,
+        >,
+        Network<Addr, Message>,
+    >;
+
+    type PutState = PutsState<1>;
+
+    fn config() -> PublicParameters {
+        PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            num_concurrent: 1,
+            max_batch_size: 1,
+            ..PublicParameters::durations(Duration::from_millis(100))
+        }
+    }
+
+    fn put_state(adversaries: &[u8]) -> anyhow::Result<PutState> {
+        let mut state = State::new(config(), adversaries)?;
+        state.push_client(UncheckedIter::new([kvstore::Op::Put(
+            String::from("foo"),
+            String::from("bar"),
+        )]))?;
+        Ok(state)
+    }
+
+    fn search_with_adversaries(
+        adversaries: &[u8],
+    ) -> anyhow::Result<SearchResult<PutState, Event>> {
+        let mut state = put_state(adversaries)?;
+        state.init()?;
+        let settings = Settings {
+            invariant: Invariants::new().with("agreement", State::agreement),
+            goal: |_: &_| false,
+            prune: |_: &_| false,
+            canonicalize: Identity,
+            weight: Uniform,
+            max_depth: Some(NonZeroUsize::new(100).unwrap()),
+            max_states: None,
+            progress: None,
+        };
+        let (result, _) = random_depth_first(
+            state,
+            settings,
+            NonZeroUsize::new(1).unwrap(),
+            0,
+            Duration::from_secs(5),
+        )?;
+        Ok(result)
+    }
 }
 
 mod simulate {
@@ -512,10 +1508,11 @@ mod simulate {
                         anyhow::bail!("missing client for index {index}")
                     };
                     let mut context = ClientContext {
-                        net: NetworkContext {
-                            state: &mut self.network,
-                            all: (0..self.replicas.len() as u8).map(Addr::Replica).collect(),
-                        },
+                        net: NetworkContext::new(
+                            (0..self.replicas.len() as u8).map(Addr::Replica).collect(),
+                            None,
+                            &mut self.network,
+                        ),
                         upcall: &mut context.upcall,
                         schedule: &mut Schedule { addr, temporal },
                     };
@@ -523,18 +1520,12 @@ mod simulate {
                 }
                 Event::Message(addr @ Addr::Replica(index), _)
                 | Event::Timer(addr @ Addr::Replica(index), ..) => {
-                    let all = (0..self.replicas.len() as u8)
-                        .filter(|id| *id != index)
-                        .map(Addr::Replica)
-                        .collect();
+                    let addrs = (0..self.replicas.len() as u8).map(Addr::Replica).collect();
                     let Some((replica, context)) = self.replicas.get_mut(index as usize) else {
                         anyhow::bail!("missing replica for index {index}")
                     };
                     let mut context = ReplicaContext {
-                        net: NetworkContext {
-                            state: &mut self.network,
-                            all,
-                        },
+                        net: NetworkContext::new(addrs, index as usize, &mut self.network),
                         crypto_worker: Transient::new(),
                         schedule: &mut Schedule { addr, temporal },
                         crypto: &mut context.crypto,
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use crate::crypto::{Verifiable, H256};
+
+// the votes that a quorum certificate is made of, e.g. PBFT Prepare and Commit
+pub trait Vote {
+    fn replica_id(&self) -> u8;
+    fn digest(&self) -> H256;
+}
+
+// accumulates the votes for a single slot (e.g. an op number) until `threshold` replicas vote for
+// the same digest
+// each replica votes at most once: a later vote from the same replica is ignored, so a faulty
+// replica cannot replace its vote with a conflicting one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuorumCert<M> {
+    threshold: usize,
+    votes: BTreeMap<u8, Verifiable<M>>,
+}
+
+impl<M> QuorumCert<M> {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            votes: Default::default(),
+        }
+    }
+
+    // drop the votes that turn out to be invalid, e.g. the ones not matching the PrePrepare that is
+    // received after them
+    pub fn retain(&mut self, mut f: impl FnMut(&M) -> bool) {
+        self.votes.retain(|_, vote| f(vote))
+    }
+}
+
+#[cfg(test)]
+impl<M> QuorumCert<M> {
+    // rewrite every vote, e.g. for relabeling the model checking states
+    // the replica id of a vote is not expected to change
+    pub fn map(self, mut f: impl FnMut(Verifiable<M>) -> Verifiable<M>) -> Self {
+        Self {
+            threshold: self.threshold,
+            votes: self
+                .votes
+                .into_iter()
+                .map(|(id, vote)| (id, f(vote)))
+                .collect(),
+        }
+    }
+}
+
+impl<M: Vote> QuorumCert<M> {
+    // return whether the threshold is met for the digest of the vote
+    pub fn insert(&mut self, vote: Verifiable<M>) -> bool {
+        let digest = vote.digest();
+        self.votes.entry(vote.replica_id()).or_insert(vote);
+        self.is_certified(digest)
+    }
+
+    pub fn count(&self, digest: H256) -> usize {
+        self.votes
+            .values()
+            .filter(|vote| vote.digest() == digest)
+            .count()
+    }
+
+    pub fn is_certified(&self, digest: H256) -> bool {
+        self.count(digest) >= self.threshold
+    }
+
+    // the certificate of the votes for `digest`, the conflicting votes are not included
+    pub fn into_votes(self, digest: H256) -> BTreeMap<u8, Verifiable<M>> {
+        let mut votes = self.votes;
+        votes.retain(|_, vote| vote.digest() == digest);
+        votes
+    }
+}
@@ -0,0 +1,1004 @@
+// crash fault tolerant replication in the style of Raft, the cheaper counterpart of `pbft` for
+// crash-only settings
+// clients are the ones of `unreplicated`, which send requests to all replicas (only the leader takes
+// them) and take the first reply
+use std::{collections::BTreeMap, time::Duration};
+
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::{ActiveTimer, OnErasedEvent, ScheduleEvent},
+    net::{combinators::All, events::Recv, Addr, SendMessage},
+    unreplicated::{Reply, Request},
+    workload::App,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PublicParameters {
+    pub num_replica: usize,
+    // a follower that hears nothing from the leader for this long, plus a random extra of up to the
+    // same length, starts an election
+    pub election_interval: Duration,
+    pub heartbeat_interval: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct LogEntry<A> {
+    pub term: u32,
+    // `None` for the no-op entry appended by a new leader, which commits the entries of previous
+    // terms along with it
+    pub request: Option<Request<A>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RequestVote {
+    pub term: u32,
+    pub candidate_id: u8,
+    pub last_log_index: u32,
+    pub last_log_term: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Vote {
+    pub term: u32,
+    pub replica_id: u8,
+    pub granted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct AppendEntries<A> {
+    pub term: u32,
+    pub leader_id: u8,
+    pub prev_log_index: u32,
+    pub prev_log_term: u32,
+    pub entries: Vec<LogEntry<A>>,
+    pub leader_commit: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct AppendEntriesOk {
+    pub term: u32,
+    pub replica_id: u8,
+    pub success: bool,
+    // the index of the last entry that is known to match the leader's log on success
+    pub match_index: u32,
+}
+
+pub mod events {
+    #[derive(Debug, Clone)]
+    pub struct Election;
+
+    #[derive(Debug, Clone)]
+    pub struct Heartbeat;
+}
+
+pub trait Context<A> {
+    type PeerNet: SendMessage<All, RequestVote>
+        + SendMessage<u8, Vote>
+        + SendMessage<u8, AppendEntries<A>>
+        + SendMessage<u8, AppendEntriesOk>;
+    type DownlinkNet: SendMessage<A, Reply>;
+    type Schedule: ScheduleEvent<events::Election> + ScheduleEvent<events::Heartbeat>;
+    fn peer_net(&mut self) -> &mut Self::PeerNet;
+    fn downlink_net(&mut self) -> &mut Self::DownlinkNet;
+    fn schedule(&mut self) -> &mut Self::Schedule;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct State<S, A> {
+    id: u8,
+    config: PublicParameters,
+
+    term: u32,
+    voted_for: Option<u8>,
+    role: Role,
+    // the entry of index `i` is at `log[i - 1]`
+    log: Vec<LogEntry<A>>,
+    commit_index: u32,
+    timer: Option<ActiveTimer>, // election timer for followers and candidates, heartbeat for leader
+
+    app: S,
+    replies: BTreeMap<u32, Reply>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Role {
+    Follower,
+    Candidate {
+        votes: Vec<u8>,
+    },
+    Leader {
+        next_index: Vec<u32>,
+        match_index: Vec<u32>,
+    },
+}
+
+impl<S, A> State<S, A> {
+    pub fn new(id: u8, app: S, config: PublicParameters) -> Self {
+        Self {
+            id,
+            config,
+            term: 0,
+            voted_for: None,
+            role: Role::Follower,
+            log: Default::default(),
+            commit_index: 0,
+            timer: None,
+            app,
+            replies: Default::default(),
+        }
+    }
+
+    pub fn init(&mut self, context: &mut impl Context<A>) -> anyhow::Result<()> {
+        anyhow::ensure!(self.timer.is_none());
+        self.timer = Some(
+            context
+                .schedule()
+                .set(self.election_timeout(), events::Election)?,
+        );
+        Ok(())
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader { .. })
+    }
+
+    pub fn term(&self) -> u32 {
+        self.term
+    }
+
+    pub fn committed_entries(&self) -> &[LogEntry<A>] {
+        &self.log[..self.commit_index as usize]
+    }
+
+    fn last_log_index(&self) -> u32 {
+        self.log.len() as _
+    }
+
+    fn log_term(&self, index: u32) -> u32 {
+        if index == 0 {
+            0
+        } else {
+            self.log[index as usize - 1].term
+        }
+    }
+
+    fn quorum(&self) -> usize {
+        self.config.num_replica / 2 + 1
+    }
+
+    // randomized so that the candidates of a split vote time out at different moments and one of
+    // them wins the next term. the randomness is seeded with the replica id and the term instead of
+    // drawn from an entropy source, so the state stays deterministic for model checking
+    fn election_timeout(&self) -> Duration {
+        let mut rng = StdRng::seed_from_u64((self.id as u64) << 32 | self.term as u64);
+        self.config.election_interval + self.config.election_interval.mul_f64(rng.gen())
+    }
+
+    fn reset_timer<M: Send + Clone + 'static, C: Context<A>>(
+        &mut self,
+        period: Duration,
+        event: M,
+        context: &mut C,
+    ) -> anyhow::Result<()>
+    where
+        C::Schedule: ScheduleEvent<M>,
+    {
+        if let Some(timer) = self.timer.take() {
+            ScheduleEvent::<M>::unset(context.schedule(), timer)?
+        }
+        self.timer = Some(context.schedule().set(period, event)?);
+        Ok(())
+    }
+
+    // step down on discovering a higher term
+    fn update_term(&mut self, term: u32, context: &mut impl Context<A>) -> anyhow::Result<()> {
+        if term <= self.term {
+            return Ok(());
+        }
+        self.term = term;
+        self.voted_for = None;
+        if !matches!(self.role, Role::Follower) {
+            self.role = Role::Follower;
+            self.reset_timer(self.election_timeout(), events::Election, context)?
+        }
+        Ok(())
+    }
+}
+
+impl<S: App, A: Addr, C: Context<A>> OnErasedEvent<Recv<Request<A>>, C> for State<S, A> {
+    fn on_event(&mut self, Recv(request): Recv<Request<A>>, context: &mut C) -> anyhow::Result<()> {
+        if !self.is_leader() {
+            return Ok(());
+        }
+        match self.replies.get(&request.client_id) {
+            Some(reply) if reply.seq > request.seq => return Ok(()),
+            Some(reply) if reply.seq == request.seq => {
+                return context
+                    .downlink_net()
+                    .send(request.client_addr, reply.clone())
+            }
+            _ => {}
+        }
+        // resent request that is still replicating
+        if self.log[self.commit_index as usize..].iter().any(|entry| {
+            entry.request.as_ref().is_some_and(|other_request| {
+                other_request.client_id == request.client_id && other_request.seq == request.seq
+            })
+        }) {
+            return Ok(());
+        }
+        self.append(Some(request), context)
+    }
+}
+
+impl<S: App, A: Addr, C: Context<A>> OnErasedEvent<events::Election, C> for State<S, A> {
+    fn on_event(
+        &mut self,
+        events::Election: events::Election,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        if self.is_leader() {
+            return Ok(());
+        }
+        // the timer is reset with the timeout of the new term, and goes off again to start the next
+        // election if this one does not end up with a leader
+        self.term += 1;
+        self.reset_timer(self.election_timeout(), events::Election, context)?;
+        self.voted_for = Some(self.id);
+        self.role = Role::Candidate {
+            votes: vec![self.id],
+        };
+        if self.quorum() == 1 {
+            return self.become_leader(context);
+        }
+        context.peer_net().send(
+            All,
+            RequestVote {
+                term: self.term,
+                candidate_id: self.id,
+                last_log_index: self.last_log_index(),
+                last_log_term: self.log_term(self.last_log_index()),
+            },
+        )
+    }
+}
+
+impl<S, A: Addr, C: Context<A>> OnErasedEvent<Recv<RequestVote>, C> for State<S, A> {
+    fn on_event(
+        &mut self,
+        Recv(request_vote): Recv<RequestVote>,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        self.update_term(request_vote.term, context)?;
+        let granted = request_vote.term == self.term
+            && self
+                .voted_for
+                .is_none_or(|candidate_id| candidate_id == request_vote.candidate_id)
+            && (request_vote.last_log_term, request_vote.last_log_index)
+                >= (self.log_term(self.last_log_index()), self.last_log_index());
+        if granted {
+            self.voted_for = Some(request_vote.candidate_id);
+            self.reset_timer(self.election_timeout(), events::Election, context)?
+        }
+        context.peer_net().send(
+            request_vote.candidate_id,
+            Vote {
+                term: self.term,
+                replica_id: self.id,
+                granted,
+            },
+        )
+    }
+}
+
+impl<S: App, A: Addr, C: Context<A>> OnErasedEvent<Recv<Vote>, C> for State<S, A> {
+    fn on_event(&mut self, Recv(vote): Recv<Vote>, context: &mut C) -> anyhow::Result<()> {
+        self.update_term(vote.term, context)?;
+        if vote.term != self.term || !vote.granted {
+            return Ok(());
+        }
+        let Role::Candidate { votes } = &mut self.role else {
+            return Ok(());
+        };
+        if !votes.contains(&vote.replica_id) {
+            votes.push(vote.replica_id)
+        }
+        if votes.len() >= self.quorum() {
+            self.become_leader(context)?
+        }
+        Ok(())
+    }
+}
+
+impl<S: App, A: Addr> State<S, A> {
+    fn become_leader(&mut self, context: &mut impl Context<A>) -> anyhow::Result<()> {
+        self.role = Role::Leader {
+            next_index: vec![self.last_log_index() + 1; self.config.num_replica],
+            match_index: vec![0; self.config.num_replica],
+        };
+        self.reset_timer(self.config.heartbeat_interval, events::Heartbeat, context)?;
+        self.append(None, context)
+    }
+
+    fn append(
+        &mut self,
+        request: Option<Request<A>>,
+        context: &mut impl Context<A>,
+    ) -> anyhow::Result<()> {
+        self.log.push(LogEntry {
+            term: self.term,
+            request,
+        });
+        let last_log_index = self.last_log_index();
+        let Role::Leader { match_index, .. } = &mut self.role else {
+            unreachable!()
+        };
+        match_index[self.id as usize] = last_log_index;
+        self.advance_commit(context)?;
+        self.broadcast_append_entries(context)
+    }
+
+    fn broadcast_append_entries(&mut self, context: &mut impl Context<A>) -> anyhow::Result<()> {
+        for replica_id in 0..self.config.num_replica as u8 {
+            if replica_id != self.id {
+                self.send_append_entries(replica_id, context)?
+            }
+        }
+        Ok(())
+    }
+
+    fn send_append_entries(
+        &self,
+        replica_id: u8,
+        context: &mut impl Context<A>,
+    ) -> anyhow::Result<()> {
+        let Role::Leader { next_index, .. } = &self.role else {
+            unreachable!()
+        };
+        let prev_log_index = next_index[replica_id as usize] - 1;
+        let append_entries = AppendEntries {
+            term: self.term,
+            leader_id: self.id,
+            prev_log_index,
+            prev_log_term: self.log_term(prev_log_index),
+            entries: self.log[prev_log_index as usize..].to_vec(),
+            leader_commit: self.commit_index,
+        };
+        context.peer_net().send(replica_id, append_entries)
+    }
+
+    // an entry of the current term is committed once it is replicated to a quorum, and so are all
+    // the entries before it. entries of previous terms are never committed by counting replicas
+    fn advance_commit(&mut self, context: &mut impl Context<A>) -> anyhow::Result<()> {
+        let Role::Leader { match_index, .. } = &self.role else {
+            unreachable!()
+        };
+        let mut match_index = match_index.clone();
+        match_index.sort_unstable_by(|index, other_index| other_index.cmp(index));
+        let quorum_index = match_index[self.quorum() - 1];
+        if quorum_index > self.commit_index && self.log_term(quorum_index) == self.term {
+            self.execute(quorum_index, context)?
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, commit_index: u32, context: &mut impl Context<A>) -> anyhow::Result<()> {
+        while self.commit_index < commit_index {
+            self.commit_index += 1;
+            let Some(request) = &self.log[self.commit_index as usize - 1].request else {
+                continue;
+            };
+            // a request may be appended more than once across terms, e.g. by different leaders
+            if self
+                .replies
+                .get(&request.client_id)
+                .is_some_and(|reply| reply.seq >= request.seq)
+            {
+                continue;
+            }
+            let reply = Reply {
+                seq: request.seq,
+                result: crate::codec::Payload(self.app.execute(&request.op)?),
+            };
+            self.replies.insert(request.client_id, reply.clone());
+            // every replica executes to keep `replies` for answering resending after leader change,
+            // but only the leader replies
+            if self.is_leader() {
+                context
+                    .downlink_net()
+                    .send(request.client_addr.clone(), reply)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: App, A: Addr, C: Context<A>> OnErasedEvent<events::Heartbeat, C> for State<S, A> {
+    fn on_event(
+        &mut self,
+        events::Heartbeat: events::Heartbeat,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        if !self.is_leader() {
+            return Ok(());
+        }
+        self.broadcast_append_entries(context)
+    }
+}
+
+impl<S: App, A: Addr, C: Context<A>> OnErasedEvent<Recv<AppendEntries<A>>, C> for State<S, A> {
+    fn on_event(
+        &mut self,
+        Recv(append_entries): Recv<AppendEntries<A>>,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        self.update_term(append_entries.term, context)?;
+        let mut ok = AppendEntriesOk {
+            term: self.term,
+            replica_id: self.id,
+            success: false,
+            match_index: 0,
+        };
+        if append_entries.term < self.term {
+            return context.peer_net().send(append_entries.leader_id, ok);
+        }
+        anyhow::ensure!(
+            !self.is_leader(),
+            "multiple leaders in term {}",
+            append_entries.term
+        );
+        self.role = Role::Follower;
+        self.reset_timer(self.election_timeout(), events::Election, context)?;
+        if append_entries.prev_log_index > self.last_log_index()
+            || self.log_term(append_entries.prev_log_index) != append_entries.prev_log_term
+        {
+            return context.peer_net().send(append_entries.leader_id, ok);
+        }
+        let mut index = append_entries.prev_log_index;
+        for entry in append_entries.entries {
+            index += 1;
+            if index <= self.last_log_index() {
+                if self.log_term(index) == entry.term {
+                    continue;
+                }
+                // conflicting entries are never committed, which is guaranteed by the election
+                anyhow::ensure!(index > self.commit_index, "conflict on committed entry");
+                self.log.truncate(index as usize - 1)
+            }
+            self.log.push(entry)
+        }
+        // the entries after `index` (if any) are not confirmed to match the leader's log, so they
+        // are not committed even if `leader_commit` goes beyond
+        let commit_index = append_entries.leader_commit.min(index);
+        if commit_index > self.commit_index {
+            self.execute(commit_index, context)?
+        }
+        ok.success = true;
+        ok.match_index = index;
+        context.peer_net().send(append_entries.leader_id, ok)
+    }
+}
+
+impl<S: App, A: Addr, C: Context<A>> OnErasedEvent<Recv<AppendEntriesOk>, C> for State<S, A> {
+    fn on_event(&mut self, Recv(ok): Recv<AppendEntriesOk>, context: &mut C) -> anyhow::Result<()> {
+        self.update_term(ok.term, context)?;
+        if ok.term != self.term {
+            return Ok(());
+        }
+        let Role::Leader {
+            next_index,
+            match_index,
+        } = &mut self.role
+        else {
+            return Ok(());
+        };
+        let replica_index = ok.replica_id as usize;
+        if !ok.success {
+            // the replica may fall far behind, in which case the entries are retried one by one
+            // backward. acceptable for now since the retried messages carry all the entries
+            // anyway
+            if next_index[replica_index] > match_index[replica_index] + 1 {
+                next_index[replica_index] -= 1
+            }
+            return self.send_append_entries(ok.replica_id, context);
+        }
+        if ok.match_index <= match_index[replica_index] {
+            return Ok(());
+        }
+        match_index[replica_index] = ok.match_index;
+        next_index[replica_index] = next_index[replica_index].max(ok.match_index + 1);
+        self.advance_commit(context)
+    }
+}
+
+pub mod codec {
+    use crate::{
+        codec::{bincode, Encode},
+        event::SendEvent,
+        net::Addr,
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, derive_more::From)]
+    pub enum ToReplica<A> {
+        Request(Request<A>),
+        RequestVote(RequestVote),
+        Vote(Vote),
+        AppendEntries(AppendEntries<A>),
+        AppendEntriesOk(AppendEntriesOk),
+    }
+
+    pub fn to_replica_encode<A: Addr, N>(net: N) -> Encode<ToReplica<A>, N> {
+        Encode::bincode(net)
+    }
+
+    pub fn to_replica_decode<'a, A: Addr>(
+        mut sender: impl SendEvent<Recv<Request<A>>>
+            + SendEvent<Recv<RequestVote>>
+            + SendEvent<Recv<Vote>>
+            + SendEvent<Recv<AppendEntries<A>>>
+            + SendEvent<Recv<AppendEntriesOk>>
+            + 'a,
+    ) -> impl FnMut(&[u8]) -> anyhow::Result<()> + 'a {
+        move |buf| match bincode::decode(buf)? {
+            ToReplica::Request(message) => sender.send(Recv(message)),
+            ToReplica::RequestVote(message) => sender.send(Recv(message)),
+            ToReplica::Vote(message) => sender.send(Recv(message)),
+            ToReplica::AppendEntries(message) => sender.send(Recv(message)),
+            ToReplica::AppendEntriesOk(message) => sender.send(Recv(message)),
+        }
+    }
+}
+
+pub mod model {
+    use bytes::Bytes;
+    use derive_more::From;
+    use derive_where::derive_where;
+
+    use crate::{
+        codec::{Decode, Encode},
+        event::SendEvent,
+        model::search::state::{Network, NetworkFault, NetworkFaults, Schedule, TimerId},
+        net::{combinators::Multicast, events::Cast},
+        unreplicated::{client, ClientState},
+        workload::{
+            app::kvstore::{self, KVStore},
+            events::Invoke,
+            CloseLoop, Workload,
+        },
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    pub enum Addr {
+        Client(u8),
+        Replica(u8),
+    }
+
+    impl crate::net::Addr for Addr {}
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, From)]
+    pub enum Message {
+        Request(Request<Addr>),
+        Reply(Reply),
+        RequestVote(RequestVote),
+        Vote(Vote),
+        AppendEntries(AppendEntries<Addr>),
+        AppendEntriesOk(AppendEntriesOk),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Timer {
+        ClientResend,
+        Election,
+        Heartbeat,
+    }
+
+    impl From<client::Resend> for Timer {
+        fn from(client::Resend: client::Resend) -> Self {
+            Self::ClientResend
+        }
+    }
+
+    impl From<events::Election> for Timer {
+        fn from(events::Election: events::Election) -> Self {
+            Self::Election
+        }
+    }
+
+    impl From<events::Heartbeat> for Timer {
+        fn from(events::Heartbeat: events::Heartbeat) -> Self {
+            Self::Heartbeat
+        }
+    }
+
+    pub type ReplicaState = super::State<kvstore::App, Addr>;
+
+    #[derive(Debug, Clone)]
+    #[derive_where(PartialEq, Eq, Hash)]
+    pub struct State<W> {
+        pub clients: Vec<(ClientState<Addr>, ClientContextState<W>)>,
+        pub replicas: Vec<(ReplicaState, Schedule<Timer>)>,
+        network: Network<Addr, Message>,
+    }
+
+    #[derive(Debug, Clone)]
+    #[derive_where(PartialEq, Eq, Hash)]
+    pub struct ClientContextState<W> {
+        // see `unreplicated::model::ClientContextState` for skipping
+        #[derive_where(skip)]
+        pub upcall: CloseLoop<W, Option<Invoke<Bytes>>>,
+        schedule: Schedule<Timer>,
+    }
+
+    type NetworkContext<'a> = Multicast<Addr, &'a mut Network<Addr, Message>>;
+
+    impl<N: SendEvent<Cast<Addr, M>>, M> SendEvent<Cast<u8, M>> for Multicast<Addr, N> {
+        fn send(&mut self, Cast(remote, message): Cast<u8, M>) -> anyhow::Result<()> {
+            SendEvent::send(self, Cast(Addr::Replica(remote), message))
+        }
+    }
+
+    // requests are sent to all replicas, since the client does not keep track of the leader
+    impl<N: SendEvent<Cast<Addr, M>>, M: Clone> SendEvent<Cast<(), M>> for Multicast<Addr, N> {
+        fn send(&mut self, Cast((), message): Cast<(), M>) -> anyhow::Result<()> {
+            SendEvent::send(self, Cast(All, message))
+        }
+    }
+
+    pub struct ClientContext<'a, W>(&'a mut ClientContextState<W>, NetworkContext<'a>);
+
+    impl<'a, W: Workload<Op = Bytes, Result = Bytes>> crate::unreplicated::ClientContext<Addr>
+        for ClientContext<'a, W>
+    {
+        type Net = NetworkContext<'a>;
+        type Upcall = CloseLoop<W, Option<Invoke<Bytes>>>;
+        type Schedule = Schedule<Timer>;
+        fn net(&mut self) -> &mut Self::Net {
+            &mut self.1
+        }
+        fn upcall(&mut self) -> &mut Self::Upcall {
+            &mut self.0.upcall
+        }
+        fn schedule(&mut self) -> &mut Self::Schedule {
+            &mut self.0.schedule
+        }
+    }
+
+    pub struct ReplicaContext<'a>(NetworkContext<'a>, &'a mut Schedule<Timer>);
+
+    impl<'a> super::Context<Addr> for ReplicaContext<'a> {
+        type PeerNet = NetworkContext<'a>;
+        type DownlinkNet = NetworkContext<'a>;
+        type Schedule = Schedule<Timer>;
+        fn peer_net(&mut self) -> &mut Self::PeerNet {
+            &mut self.0
+        }
+        fn downlink_net(&mut self) -> &mut Self::DownlinkNet {
+            &mut self.0
+        }
+        fn schedule(&mut self) -> &mut Self::Schedule {
+            self.1
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub enum Event {
+        Message(Addr, Message),
+        Timer(Addr, TimerId, Timer),
+        NetworkFault(NetworkFault<Addr, Message>),
+    }
+
+    impl<W: Workload<Op = Bytes, Result = Bytes>> SendEvent<Event> for State<W> {
+        fn send(&mut self, event: Event) -> anyhow::Result<()> {
+            let event = match event {
+                Event::Message(addr, message) => {
+                    self.network.deliver(addr, message.clone())?;
+                    Event::Message(addr, message)
+                }
+                Event::NetworkFault(fault) => {
+                    let Some((addr, message)) = self.network.fault(fault)? else {
+                        return Ok(());
+                    };
+                    Event::Message(addr, message)
+                }
+                event => event,
+            };
+            let addrs = self.addrs();
+            match event {
+                Event::Message(Addr::Client(index), _) | Event::Timer(Addr::Client(index), ..) => {
+                    let Some((client, context)) = self.clients.get_mut(index as usize) else {
+                        anyhow::bail!("unexpected client index {index}")
+                    };
+                    let mut context =
+                        ClientContext(context, NetworkContext::new(addrs, None, &mut self.network));
+                    match event {
+                        Event::Message(_, Message::Reply(message)) => {
+                            client.on_event(Recv(message), &mut context)
+                        }
+                        Event::Timer(_, id, Timer::ClientResend) => {
+                            context.0.schedule.tick(id)?;
+                            client.on_event(client::Resend, &mut context)
+                        }
+                        _ => anyhow::bail!("unexpected event {event:?}"),
+                    }
+                }
+                Event::Message(Addr::Replica(index), _)
+                | Event::Timer(Addr::Replica(index), ..) => {
+                    let Some((replica, schedule)) = self.replicas.get_mut(index as usize) else {
+                        anyhow::bail!("unexpected replica index {index}")
+                    };
+                    if let Event::Timer(_, id, _) = &event {
+                        schedule.tick(*id)?
+                    }
+                    let mut context = ReplicaContext(
+                        NetworkContext::new(addrs, index as usize, &mut self.network),
+                        schedule,
+                    );
+                    match event {
+                        Event::Message(_, Message::Request(message)) => {
+                            replica.on_event(Recv(message), &mut context)
+                        }
+                        Event::Message(_, Message::RequestVote(message)) => {
+                            replica.on_event(Recv(message), &mut context)
+                        }
+                        Event::Message(_, Message::Vote(message)) => {
+                            replica.on_event(Recv(message), &mut context)
+                        }
+                        Event::Message(_, Message::AppendEntries(message)) => {
+                            replica.on_event(Recv(message), &mut context)
+                        }
+                        Event::Message(_, Message::AppendEntriesOk(message)) => {
+                            replica.on_event(Recv(message), &mut context)
+                        }
+                        Event::Timer(_, _, Timer::Election) => {
+                            replica.on_event(events::Election, &mut context)
+                        }
+                        Event::Timer(_, _, Timer::Heartbeat) => {
+                            replica.on_event(events::Heartbeat, &mut context)
+                        }
+                        _ => anyhow::bail!("unexpected event {event:?}"),
+                    }
+                }
+                _ => anyhow::bail!("unexpected event {event:?}"),
+            }?;
+            self.fix()
+        }
+    }
+
+    impl<W: Workload<Op = Bytes, Result = Bytes>> State<W> {
+        pub fn init(&mut self) -> anyhow::Result<()> {
+            let addrs = self.addrs();
+            for (index, (replica, schedule)) in self.replicas.iter_mut().enumerate() {
+                let mut context = ReplicaContext(
+                    NetworkContext::new(addrs.clone(), index, &mut self.network),
+                    schedule,
+                );
+                replica.init(&mut context)?
+            }
+            for (_, context) in &mut self.clients {
+                context.upcall.init()?
+            }
+            self.fix()
+        }
+
+        fn fix(&mut self) -> anyhow::Result<()> {
+            let addrs = self.addrs();
+            for (client, context) in &mut self.clients {
+                if let Some(invoke) = context.upcall.sender.take() {
+                    let mut context = ClientContext(
+                        context,
+                        NetworkContext::new(addrs.clone(), None, &mut self.network),
+                    );
+                    client.on_event(invoke, &mut context)?
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<W> State<W> {
+        fn addrs(&self) -> Vec<Addr> {
+            (0..self.replicas.len() as u8).map(Addr::Replica).collect()
+        }
+
+        // the safety invariants: at most one leader for each term, and replicas never commit
+        // different entries for the same index
+        pub fn consistency(&self) -> anyhow::Result<()> {
+            let mut leaders = BTreeMap::new();
+            let mut committed = Vec::new();
+            for (replica, _) in &self.replicas {
+                if replica.is_leader() {
+                    if let Some(other_id) = leaders.insert(replica.term(), replica.id) {
+                        anyhow::bail!(
+                            "replica {} and {other_id} are both leader in term {}",
+                            replica.id,
+                            replica.term()
+                        )
+                    }
+                }
+                for (index, entry) in replica.committed_entries().iter().enumerate() {
+                    if let Some(other_entry) = committed.get(index) {
+                        anyhow::ensure!(
+                            entry == other_entry,
+                            "conflicting commit at index {}",
+                            index + 1
+                        )
+                    } else {
+                        committed.push(entry.clone())
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<W: Workload<Op = Bytes, Result = Bytes>> crate::model::search::State for State<W> {
+        type Event = Event;
+
+        fn events(&self) -> impl Iterator<Item = Self::Event> + '_ {
+            let client_timers =
+                self.clients
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, (_, context))| {
+                        assert!(context.upcall.sender.is_none());
+                        context.schedule.events().map(move |(id, event)| {
+                            Event::Timer(Addr::Client(index as _), id, event)
+                        })
+                    });
+            let replica_timers =
+                self.replicas
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, (_, schedule))| {
+                        schedule.events().map(move |(id, event)| {
+                            Event::Timer(Addr::Replica(index as _), id, event)
+                        })
+                    });
+            self.network
+                .events()
+                .map(|(addr, message)| Event::Message(addr, message))
+                .chain(self.network.fault_events().map(Event::NetworkFault))
+                .chain(client_timers)
+                .chain(replica_timers)
+        }
+    }
+
+    impl<W> State<W> {
+        pub fn new(config: PublicParameters) -> Self {
+            Self::with_network_faults(config, Default::default())
+        }
+
+        pub fn with_network_faults(config: PublicParameters, faults: NetworkFaults) -> Self {
+            let replicas = (0..config.num_replica as u8)
+                .map(|id| {
+                    let replica = ReplicaState::new(
+                        id,
+                        Decode::json(Encode::json(KVStore::new())),
+                        config.clone(),
+                    );
+                    (replica, Schedule::new())
+                })
+                .collect();
+            Self {
+                clients: Default::default(),
+                replicas,
+                network: if faults == NetworkFaults::default() {
+                    Network::new()
+                } else {
+                    Network::with_faults(faults)
+                },
+            }
+        }
+    }
+
+    impl<W: Workload<Op = kvstore::Op, Result = kvstore::Result>>
+        State<Decode<kvstore::Result, Encode<kvstore::Op, W>>>
+    {
+        pub fn push_client(&mut self, workload: W) {
+            let index = self.clients.len();
+            let client = ClientState::new(index as _, Addr::Client(index as _));
+            let context = ClientContextState {
+                upcall: CloseLoop::new(Decode::json(Encode::json(workload)), None),
+                schedule: Schedule::new(),
+            };
+            self.clients.push((client, context));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{array::IntoIter, num::NonZeroUsize, time::Duration};
+
+    use crate::{
+        codec::{Decode, Encode},
+        model::search::{
+            breadth_first, random_depth_first, Identity, SearchResult, Settings, Uniform,
+        },
+        workload::{app::kvstore, combinators::UncheckedIter},
+    };
+
+    use super::{model::State, PublicParameters};
+
+    type PutState = State<
+        Decode<
+            kvstore::Result,
+            Encode<kvstore::Op, UncheckedIter<kvstore::Result, IntoIter<kvstore::Op, 1>>>,
+        >,
+    >;
+
+    fn put_state(num_replica: usize) -> anyhow::Result<PutState> {
+        let mut state = State::new(PublicParameters {
+            num_replica,
+            election_interval: Duration::from_millis(100),
+            heartbeat_interval: Duration::from_millis(10),
+        });
+        state.push_client(UncheckedIter::new([kvstore::Op::Put(
+            String::from("foo"),
+            String::from("bar"),
+        )]));
+        state.init()?;
+        Ok(state)
+    }
+
+    #[test]
+    fn commit() -> anyhow::Result<()> {
+        for num_replica in [1, 3] {
+            let settings = Settings {
+                invariant: State::consistency,
+                goal: |state: &PutState| {
+                    state
+                        .clients
+                        .iter()
+                        .all(|(_, context)| context.upcall.workload.done)
+                },
+                prune: |_: &_| false,
+                canonicalize: Identity,
+                weight: Uniform,
+                max_depth: Some(NonZeroUsize::new(1000).unwrap()),
+                max_states: None,
+                progress: None,
+            };
+            let (result, _) = random_depth_first(
+                put_state(num_replica)?,
+                settings,
+                NonZeroUsize::new(1).unwrap(),
+                0,
+                Duration::from_secs(5),
+            )?;
+            anyhow::ensure!(matches!(result, SearchResult::GoalFound(..)), "{result}")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn safety() -> anyhow::Result<()> {
+        let settings = Settings {
+            invariant: State::consistency,
+            goal: |_: &_| false,
+            prune: |_: &_| false,
+            canonicalize: Identity,
+            weight: Uniform,
+            max_depth: None,
+            // every state up to some depth, instead of however many the machine explores in a
+            // wall-clock budget
+            max_states: Some(20_000),
+            progress: None,
+        };
+        let result = breadth_first(put_state(3)?, settings, NonZeroUsize::new(1).unwrap(), None)?;
+        anyhow::ensure!(
+            matches!(
+                result,
+                SearchResult::SpaceExhausted | SearchResult::StateLimitReached(..)
+            ),
+            "{result}"
+        );
+        Ok(())
+    }
+}
@@ -59,3 +59,14 @@ impl<M> Timer<M> {
         Ok(())
     }
 }
+
+// the interval before resending for the `num_resend`-th time, doubling from `initial` up to `max`.
+// without `max` the interval stays at `initial`
+pub fn backoff(initial: Duration, max: Option<Duration>, num_resend: u32) -> Duration {
+    let Some(max) = max else {
+        return initial;
+    };
+    initial
+        .saturating_mul(2u32.saturating_pow(num_resend))
+        .min(max)
+}
@@ -10,24 +10,26 @@ use crate::{
         events::{Cast, Recv},
         Addr,
     },
+    timer::backoff,
     workload::{
-        events::{Invoke, InvokeOk},
+        events::{Executed, Invoke, InvokeOk},
+        task::Offload,
         App,
     },
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Request<A> {
-    seq: u32,
-    op: Payload,
-    client_id: u32,
-    client_addr: A,
+    pub(crate) seq: u32,
+    pub(crate) op: Payload,
+    pub(crate) client_id: u32,
+    pub(crate) client_addr: A,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Reply {
-    seq: u32,
-    result: Payload,
+    pub(crate) seq: u32,
+    pub(crate) result: Payload,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -36,14 +38,18 @@ pub struct ClientState<A> {
     addr: A,
     seq: u32,
     outstanding: Option<Outstanding>,
+    max_resend_interval: Option<Duration>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Outstanding {
     op: Payload,
     timer: ActiveTimer,
+    num_resend: u32,
 }
 
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
 impl<A> ClientState<A> {
     pub fn new(id: u32, addr: A) -> Self {
         Self {
@@ -51,8 +57,29 @@ impl<A> ClientState<A> {
             addr,
             seq: 0,
             outstanding: Default::default(),
+            max_resend_interval: None,
+        }
+    }
+
+    // back off exponentially on consecutive resends up to `max`, see `pbft::PublicParameters`
+    pub fn with_resend_backoff(self, max: Duration) -> Self {
+        Self {
+            max_resend_interval: Some(max),
+            ..self
         }
     }
+
+    // see `pbft::client::State::recover`
+    pub fn recover(id: u32, addr: A, seq: u32) -> Self {
+        Self {
+            seq,
+            ..Self::new(id, addr)
+        }
+    }
+
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
 }
 
 pub mod client {
@@ -74,9 +101,8 @@ impl<A: Addr, C: ClientContext<A>> OnErasedEvent<Invoke<Bytes>, C> for ClientSta
         self.seq += 1;
         let replaced = self.outstanding.replace(Outstanding {
             op: Payload(op),
-            timer: context
-                .schedule()
-                .set(Duration::from_millis(100), client::Resend)?,
+            timer: context.schedule().set(RESEND_INTERVAL, client::Resend)?,
+            num_resend: 0,
         });
         anyhow::ensure!(replaced.is_none());
         self.send_request(context)
@@ -116,49 +142,272 @@ impl<A, C: ClientContext<A>> OnErasedEvent<Recv<Reply>, C> for ClientState<A> {
 
 impl<A: Addr, C: ClientContext<A>> OnErasedEvent<client::Resend, C> for ClientState<A> {
     fn on_event(&mut self, client::Resend: client::Resend, context: &mut C) -> anyhow::Result<()> {
+        let Some(outstanding) = &mut self.outstanding else {
+            anyhow::bail!("missing outstanding invocation")
+        };
+        if self.max_resend_interval.is_some() {
+            outstanding.num_resend += 1;
+            context.schedule().unset(outstanding.timer.clone())?;
+            outstanding.timer = context.schedule().set(
+                backoff(
+                    RESEND_INTERVAL,
+                    self.max_resend_interval,
+                    outstanding.num_resend,
+                ),
+                client::Resend,
+            )?
+        }
         // TODO log
         self.send_request(context)
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ServerState<S> {
-    replies: BTreeMap<u32, Reply>,
+pub struct ServerState<S, A> {
+    // client id -> (seq, reply, when the client last acknowledged), and the acknowledging order ->
+    // client id for evicting the replies of the least recently acknowledging clients
+    // the seq is kept after the reply is evicted, so a duplicate request is still recognized and
+    // never executed again
+    replies: BTreeMap<u32, (u32, Option<Reply>, Option<u64>)>,
+    acked: BTreeMap<u64, u32>,
+    clock: u64,
+    num_replies: usize,
+    // with churning clients the replies grow without bound, so the replies are evicted beyond the
+    // capacity. a client only sends the request of the next seq after receiving the reply of the
+    // current one, so a request of a later seq acknowledges the reply. the latest reply of a client
+    // becomes evictable once the client has acknowledged any reply, and the clients that acknowledge
+    // least recently, i.e. the ones that have left, are evicted first. a client that has never
+    // acknowledged keeps its reply, and a client that is still waiting for an evicted reply is not
+    // replied again, so the capacity should be above the number of concurrent clients
+    reply_capacity: Option<usize>,
     app: S,
+    // for comparing against batched PBFT without the consensus overhead: requests are buffered for
+    // the window and executed together when it closes, while still replied individually
+    // `None` executes every request immediately
+    batch_interval: Option<Duration>,
+    requests: Vec<Request<A>>,
+    flush_timer: Option<ActiveTimer>,
+    // client id -> seq of the request that is submitted to `AsyncApp` and not executed yet
+    executing: BTreeMap<u32, u32>,
 }
 
-impl<S> ServerState<S> {
-    pub fn new(app: S) -> Self {
+impl<S, A> ServerState<S, A> {
+    pub fn new(app: S, reply_capacity: impl Into<Option<usize>>) -> Self {
         Self {
             app,
             replies: Default::default(),
+            acked: Default::default(),
+            clock: 0,
+            num_replies: 0,
+            reply_capacity: reply_capacity.into(),
+            batch_interval: None,
+            requests: Default::default(),
+            flush_timer: None,
+            executing: Default::default(),
+        }
+    }
+
+    pub fn with_batching(
+        app: S,
+        reply_capacity: impl Into<Option<usize>>,
+        batch_interval: Duration,
+    ) -> Self {
+        Self {
+            batch_interval: Some(batch_interval),
+            ..Self::new(app, reply_capacity)
         }
     }
 }
 
+pub mod server {
+    #[derive(Debug, Clone)]
+    pub struct Flush;
+}
+
 pub trait ServerContext<A> {
     type Net: SendEvent<Cast<A, Reply>>;
+    type Schedule: ScheduleEvent<server::Flush>;
     fn net(&mut self) -> &mut Self::Net;
+    fn schedule(&mut self) -> &mut Self::Schedule;
 }
 
-impl<S: App, A, C: ServerContext<A>> OnErasedEvent<Recv<Request<A>>, C> for ServerState<S> {
+impl<S: App, A: Clone, C: ServerContext<A>> OnErasedEvent<Recv<Request<A>>, C>
+    for ServerState<S, A>
+{
     fn on_event(&mut self, Recv(request): Recv<Request<A>>, context: &mut C) -> anyhow::Result<()> {
-        match self.replies.get(&request.client_id) {
-            Some(reply) if reply.seq > request.seq => return Ok(()),
-            Some(reply) if reply.seq == request.seq => {
-                return context.net().send(Cast(request.client_addr, reply.clone()))
-            }
-            _ => {}
+        // acknowledged on receiving, so the reply can be evicted while the request is buffered
+        self.acknowledge(&request);
+        let Some(batch_interval) = self.batch_interval else {
+            return self.execute(request, context);
+        };
+        if matches!(self.replies.get(&request.client_id), Some((seq, ..)) if *seq >= request.seq) {
+            // stale or resent request, handled without waiting for the batch
+            return self.execute(request, context);
+        }
+        self.requests.push(request);
+        if self.flush_timer.is_none() {
+            self.flush_timer = Some(context.schedule().set(batch_interval, server::Flush)?)
+        }
+        Ok(())
+    }
+}
+
+impl<S: App, A: Clone, C: ServerContext<A>> OnErasedEvent<server::Flush, C> for ServerState<S, A> {
+    fn on_event(&mut self, server::Flush: server::Flush, context: &mut C) -> anyhow::Result<()> {
+        let Some(timer) = self.flush_timer.take() else {
+            return Ok(());
+        };
+        context.schedule().unset(timer)?;
+        // resent requests may be buffered more than once, the later copies are replied from
+        // `replies` instead of being executed again
+        for request in std::mem::take(&mut self.requests) {
+            self.execute(request, context)?
+        }
+        Ok(())
+    }
+}
+
+impl<S: App, A: Clone> ServerState<S, A> {
+    fn execute(
+        &mut self,
+        request: Request<A>,
+        context: &mut impl ServerContext<A>,
+    ) -> anyhow::Result<()> {
+        if self.replied(&request, context)? {
+            return Ok(());
         }
         let reply = Reply {
             seq: request.seq,
             result: Payload(self.app.execute(&request.op)?),
         };
-        self.replies.insert(request.client_id, reply.clone());
+        self.insert_reply(request.client_id, reply.clone());
         context.net().send(Cast(request.client_addr, reply))
     }
 }
 
+// execution offloaded to `AsyncApp`, with the request as the token to reply when it is `Executed`
+// requests are not batched in this case, since the offloaded execution already takes the requests
+// as they come without blocking
+impl<A: Clone, C: ServerContext<A>> OnErasedEvent<Recv<Request<A>>, C>
+    for ServerState<Offload<Request<A>>, A>
+{
+    fn on_event(&mut self, Recv(request): Recv<Request<A>>, context: &mut C) -> anyhow::Result<()> {
+        if self.replied(&request, context)?
+            || self
+                .executing
+                .get(&request.client_id)
+                .is_some_and(|seq| *seq >= request.seq)
+        {
+            return Ok(());
+        }
+        self.executing.insert(request.client_id, request.seq);
+        let Payload(op) = request.op.clone();
+        self.app.submit(request, op)
+    }
+}
+
+impl<A, C: ServerContext<A>> OnErasedEvent<Executed<Request<A>>, C>
+    for ServerState<Offload<Request<A>>, A>
+{
+    fn on_event(
+        &mut self,
+        Executed(request, result): Executed<Request<A>>,
+        context: &mut C,
+    ) -> anyhow::Result<()> {
+        if self.executing.get(&request.client_id) == Some(&request.seq) {
+            self.executing.remove(&request.client_id);
+        }
+        let reply = Reply {
+            seq: request.seq,
+            result: Payload(result),
+        };
+        self.insert_reply(request.client_id, reply.clone());
+        context.net().send(Cast(request.client_addr, reply))
+    }
+}
+
+impl<S, A> ServerState<S, A> {
+    // reply (or ignore, if it is stale) the request if it has been executed
+    fn replied(
+        &mut self,
+        request: &Request<A>,
+        context: &mut impl ServerContext<A>,
+    ) -> anyhow::Result<bool>
+    where
+        A: Clone,
+    {
+        self.acknowledge(request);
+        match self.replies.get(&request.client_id) {
+            Some((seq, ..)) if *seq > request.seq => Ok(true),
+            Some((seq, reply, _)) if *seq == request.seq => {
+                // the evicted reply is not resent, see `reply_capacity`
+                if let Some(reply) = reply {
+                    context
+                        .net()
+                        .send(Cast(request.client_addr.clone(), reply.clone()))?
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn num_replies(&self) -> usize {
+        self.num_replies
+    }
+
+    // the bookkeeping is skipped without capacity, so unbounded replies are not distinguished by the
+    // acknowledging history, e.g. when searching the states
+    fn acknowledge(&mut self, request: &Request<A>) {
+        if self.reply_capacity.is_none() {
+            return;
+        }
+        let Some((seq, _, acked)) = self.replies.get_mut(&request.client_id) else {
+            return;
+        };
+        if *seq >= request.seq {
+            return;
+        }
+        self.clock += 1;
+        if let Some(acked) = acked.replace(self.clock) {
+            self.acked.remove(&acked);
+        }
+        self.acked.insert(self.clock, request.client_id);
+    }
+
+    fn insert_reply(&mut self, client_id: u32, reply: Reply) {
+        // the acknowledgement of the previous reply carries over, so the latest reply is evictable
+        let acked = self
+            .replies
+            .get(&client_id)
+            .and_then(|(_, _, acked)| *acked);
+        let replaced = self
+            .replies
+            .insert(client_id, (reply.seq, Some(reply), acked));
+        if !matches!(replaced, Some((_, Some(_), _))) {
+            self.num_replies += 1
+        }
+        let Some(capacity) = self.reply_capacity else {
+            return;
+        };
+        while self.num_replies > capacity {
+            // the reply that is just inserted is not evicted, the client has not received it yet
+            let Some((&clock, &evicted)) = self.acked.iter().find(|(_, id)| **id != client_id)
+            else {
+                break;
+            };
+            self.acked.remove(&clock);
+            let Some((_, reply, acked)) = self.replies.get_mut(&evicted) else {
+                unreachable!()
+            };
+            *acked = None;
+            if reply.take().is_some() {
+                self.num_replies -= 1
+            }
+        }
+    }
+}
+
 pub mod codec {
     use crate::codec::{bincode, Encode};
 
@@ -191,7 +440,7 @@ pub mod model {
 
     use crate::{
         codec::{Decode, Encode},
-        model::search::state::{Network, Schedule, TimerId},
+        model::search::state::{Network, NetworkFault, NetworkFaults, Schedule, TimerId},
         workload::{
             app::kvstore::{self, KVStore},
             CloseLoop, Workload,
@@ -217,6 +466,7 @@ pub mod model {
     #[derive(Debug, Clone, PartialEq, Eq, Hash)]
     pub enum Timer {
         ClientResend,
+        ServerFlush,
     }
 
     impl From<client::Resend> for Timer {
@@ -225,12 +475,19 @@ pub mod model {
         }
     }
 
+    impl From<server::Flush> for Timer {
+        fn from(server::Flush: server::Flush) -> Self {
+            Self::ServerFlush
+        }
+    }
+
     #[derive(Debug, Clone)]
     #[derive_where(PartialEq, Eq, Hash)]
     pub struct State<W> {
         pub clients: Vec<(ClientState<Addr>, ClientContextState<W>)>,
-        server: ServerState<kvstore::App>,
-        network: Network<Addr, Message>,
+        pub server: ServerState<kvstore::App, Addr>,
+        server_schedule: Schedule<Timer>,
+        pub network: Network<Addr, Message>,
     }
 
     #[derive(Debug, Clone)]
@@ -265,10 +522,16 @@ pub mod model {
         }
     }
 
-    impl super::ServerContext<Addr> for Network<Addr, Message> {
-        type Net = Self;
+    pub struct ServerContext<'a>(&'a mut Network<Addr, Message>, &'a mut Schedule<Timer>);
+
+    impl super::ServerContext<Addr> for ServerContext<'_> {
+        type Net = Network<Addr, Message>;
+        type Schedule = Schedule<Timer>;
         fn net(&mut self) -> &mut Self::Net {
-            self
+            self.0
+        }
+        fn schedule(&mut self) -> &mut Self::Schedule {
+            self.1
         }
     }
 
@@ -282,10 +545,28 @@ pub mod model {
     pub enum Event {
         Message(Addr, Message),
         Timer(u8, TimerId, Timer),
+        ServerTimer(TimerId, Timer),
+        NetworkFault(NetworkFault<Addr, Message>),
     }
 
     impl<W: Workload<Op = Bytes, Result = Bytes>> SendEvent<Event> for State<W> {
         fn send(&mut self, event: Event) -> anyhow::Result<()> {
+            let event = match event {
+                Event::Message(addr, message) => {
+                    self.network.deliver(addr, message.clone())?;
+                    Event::Message(addr, message)
+                }
+                Event::NetworkFault(fault) => {
+                    let Some((addr, message)) = self.network.fault(fault)? else {
+                        return Ok(());
+                    };
+                    Event::Message(addr, message)
+                }
+                event @ (Event::Timer(..) | Event::ServerTimer(..)) => {
+                    self.network.tick();
+                    event
+                }
+            };
             match event {
                 Event::Message(Addr::Client(index), _) | Event::Timer(index, ..) => {
                     let Some((client, context)) = self.clients.get_mut(index as usize) else {
@@ -303,8 +584,16 @@ pub mod model {
                         _ => anyhow::bail!("unexpected event {event:?}"),
                     }
                 }
-                Event::Message(Addr::Server, Message::Request(message)) => {
-                    self.server.on_event(Recv(message), &mut self.network)
+                Event::Message(Addr::Server, Message::Request(message)) => self.server.on_event(
+                    Recv(message),
+                    &mut ServerContext(&mut self.network, &mut self.server_schedule),
+                ),
+                Event::ServerTimer(id, Timer::ServerFlush) => {
+                    self.server_schedule.tick(id)?;
+                    self.server.on_event(
+                        server::Flush,
+                        &mut ServerContext(&mut self.network, &mut self.server_schedule),
+                    )
                 }
                 _ => anyhow::bail!("unexpected event {event:?}"),
             }?;
@@ -346,10 +635,16 @@ pub mod model {
                         .events()
                         .map(move |(id, event)| Event::Timer(index as _, id, event))
                 });
+            let server_timers = self
+                .server_schedule
+                .events()
+                .map(|(id, event)| Event::ServerTimer(id, event));
             self.network
                 .events()
                 .map(|(addr, message)| Event::Message(addr, message))
+                .chain(self.network.fault_events().map(Event::NetworkFault))
                 .chain(timers)
+                .chain(server_timers)
         }
     }
 
@@ -362,11 +657,45 @@ pub mod model {
     impl<W> State<W> {
         pub fn new() -> Self {
             Self {
-                server: ServerState::new(Decode::json(Encode::json(KVStore::new()))),
+                server: ServerState::new(Decode::json(Encode::json(KVStore::new())), None),
+                server_schedule: Schedule::new(),
                 clients: Default::default(),
                 network: Network::new(),
             }
         }
+
+        pub fn with_network_faults(faults: NetworkFaults) -> Self {
+            Self {
+                network: Network::with_faults(faults),
+                ..Self::new()
+            }
+        }
+
+        // the messages to `addr` are delayed for `rounds` timer ticks
+        pub fn with_network_delays(delays: impl IntoIterator<Item = (Addr, u32)>) -> Self {
+            Self {
+                network: Network::new().with_delays(delays),
+                ..Self::new()
+            }
+        }
+
+        pub fn with_reply_capacity(self, capacity: usize) -> Self {
+            Self {
+                server: ServerState::new(Decode::json(Encode::json(KVStore::new())), capacity),
+                ..self
+            }
+        }
+
+        pub fn with_batching(batch_interval: Duration) -> Self {
+            Self {
+                server: ServerState::with_batching(
+                    Decode::json(Encode::json(KVStore::new())),
+                    None,
+                    batch_interval,
+                ),
+                ..Self::new()
+            }
+        }
     }
 
     impl<W: Workload<Op = kvstore::Op, Result = kvstore::Result>>
@@ -383,3 +712,73 @@ pub mod model {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::array::IntoIter;
+
+    use crate::{
+        codec::{Decode, Encode},
+        event::SendEvent,
+        model::search::{state::NetworkFaults, State as _},
+        workload::{app::kvstore, combinators::UncheckedIter},
+    };
+
+    use super::{
+        model::{Addr, Event, Message, State},
+        Cast,
+    };
+
+    #[test]
+    fn evict_replies() -> anyhow::Result<()> {
+        let mut state = State::<
+            Decode<
+                kvstore::Result,
+                Encode<kvstore::Op, UncheckedIter<kvstore::Result, IntoIter<kvstore::Op, 2>>>,
+            >,
+        >::with_network_faults(NetworkFaults {
+            drop: true,
+            ..Default::default()
+        })
+        .with_reply_capacity(2);
+        for index in 0..8 {
+            state.push_client(UncheckedIter::new([
+                kvstore::Op::Put(format!("foo{index}"), String::from("bar")),
+                kvstore::Op::Get(format!("foo{index}")),
+            ]))
+        }
+        state.init()?;
+        let mut requests = Vec::new();
+        while !state
+            .clients
+            .iter()
+            .all(|(_, context)| context.upcall.workload.done)
+        {
+            let event = state
+                .events()
+                .next()
+                .ok_or(anyhow::format_err!("no event"))?;
+            if let Event::Message(Addr::Server, Message::Request(request)) = &event {
+                requests.push(request.clone())
+            }
+            state.send(event)?
+        }
+        // every client has acknowledged its first reply, so all but the capacity are evicted
+        anyhow::ensure!(state.server.num_replies() == 2);
+        // the duplicate requests of the evicted replies are neither executed nor replied
+        for request in requests {
+            state.network.send(Cast(Addr::Server, request))?
+        }
+        loop {
+            let event = state.events().next();
+            let Some(event) = event else { break };
+            anyhow::ensure!(
+                !matches!(event, Event::Message(Addr::Client(index), _) if index < 6),
+                "unexpected {event:?}"
+            );
+            state.send(event)?
+        }
+        anyhow::ensure!(state.server.num_replies() == 2);
+        Ok(())
+    }
+}
@@ -1,7 +1,17 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    future::Future,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
 use bytes::Bytes;
-use events::{Invoke, InvokeOk};
+use derive_where::derive_where;
+use events::{Arrive, Invoke, InvokeOk, Think};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::event::SendEvent;
+use crate::event::{ActiveTimer, ScheduleEvent, SendEvent};
 
 pub mod events {
     #[derive(Debug, Clone)]
@@ -9,16 +19,63 @@ pub mod events {
 
     #[derive(Debug)]
     pub struct InvokeOk<M>(pub M);
+
+    #[derive(Debug, Clone)]
+    pub struct Arrive;
+
+    #[derive(Debug, Clone)]
+    pub struct Think;
+
+    // the result of an op executed by `AsyncApp`, along with the token submitted with the op
+    #[derive(Debug)]
+    pub struct Executed<T>(pub T, pub bytes::Bytes);
 }
 
 pub mod app {
     pub mod kvstore;
+    pub mod merkle;
+    #[cfg(feature = "rocksdb")]
+    pub mod rocks;
 }
 
 pub mod combinators;
+pub mod task;
 
 pub trait App {
     fn execute(&mut self, op: &[u8]) -> anyhow::Result<Bytes>;
+
+    // the whole state of the app, for state transfer and checkpointing. restoring a snapshot into
+    // any instance results in the state at the time of taking it
+    fn snapshot(&self) -> anyhow::Result<Bytes>;
+
+    fn restore(&mut self, snapshot: &[u8]) -> anyhow::Result<()>;
+}
+
+// the stateful parts that apps are composed of, e.g. `KVStore` in `kvstore::App`, which the codec
+// layers of the apps delegate the snapshots to
+pub trait Snapshot {
+    fn snapshot(&self) -> anyhow::Result<Bytes>;
+
+    fn restore(&mut self, snapshot: &[u8]) -> anyhow::Result<()>;
+}
+
+impl<K: Ord + Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> Snapshot
+    for BTreeMap<K, V>
+{
+    fn snapshot(&self) -> anyhow::Result<Bytes> {
+        crate::codec::bincode::encode(self)
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> anyhow::Result<()> {
+        *self = crate::codec::bincode::decode(snapshot)?;
+        Ok(())
+    }
+}
+
+// for applications doing I/O (e.g. on top of persistent storage) that should not block the event loop
+// on executing. see `task::Offload`
+pub trait AsyncApp {
+    fn execute(&mut self, op: &[u8]) -> impl Future<Output = anyhow::Result<Bytes>> + Send;
 }
 
 #[derive(Debug)]
@@ -28,6 +85,134 @@ impl App for Null {
     fn execute(&mut self, _: &[u8]) -> anyhow::Result<Bytes> {
         Ok(Default::default())
     }
+
+    fn snapshot(&self) -> anyhow::Result<Bytes> {
+        Ok(Default::default())
+    }
+
+    fn restore(&mut self, _: &[u8]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncApp for Null {
+    async fn execute(&mut self, _: &[u8]) -> anyhow::Result<Bytes> {
+        Ok(Default::default())
+    }
+}
+
+// for microbenchmarking the protocols in isolation from the application cost: replies every op with
+// the op itself, or with a zeroed reply of `reply_size` bytes if it is set
+// pairs with `random_payloads` for issuing ops of a configured size
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NoopApp {
+    pub reply_size: Option<usize>,
+}
+
+impl App for NoopApp {
+    fn execute(&mut self, op: &[u8]) -> anyhow::Result<Bytes> {
+        Ok(match self.reply_size {
+            Some(reply_size) => vec![0; reply_size].into(),
+            None => Bytes::copy_from_slice(op),
+        })
+    }
+
+    // `reply_size` is configuration rather than state
+    fn snapshot(&self) -> anyhow::Result<Bytes> {
+        Ok(Default::default())
+    }
+
+    fn restore(&mut self, _: &[u8]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncApp for NoopApp {
+    async fn execute(&mut self, op: &[u8]) -> anyhow::Result<Bytes> {
+        App::execute(self, op)
+    }
+}
+
+// endless random ops of `size` bytes, e.g. for `UncheckedIter`
+pub fn random_payloads(
+    size: usize,
+    seed_rng: &mut impl Rng,
+) -> anyhow::Result<impl Iterator<Item = Bytes>> {
+    let mut rng = StdRng::from_rng(seed_rng)?;
+    Ok(std::iter::repeat_with(move || {
+        let mut payload = vec![0; size];
+        rng.fill(&mut payload[..]);
+        payload.into()
+    }))
+}
+
+// the ops without side effects, whose results stay the same until some other op executes
+pub trait ReadOnly {
+    fn is_read_only(&self) -> bool;
+}
+
+// replies the read-only ops with the results of their previous executions, and drops all the
+// results on any other op, so e.g. a cached scan never misses a write into its range
+// the cache is not part of the app's identity, so the model checking states that only differ in
+// what is cached are considered the same
+// wrap it as any other app e.g. `Decode::json(Encode::json(CachedApp::new(KVStore::new())))`
+#[derive(Debug, Clone)]
+#[derive_where(PartialEq, Eq, Hash; S)]
+pub struct CachedApp<S, O, R> {
+    pub inner: S,
+    #[derive_where(skip)]
+    cache: HashMap<O, R>,
+    #[derive_where(skip)]
+    pub hits: usize,
+}
+
+impl<S, O, R> CachedApp<S, O, R> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            cache: Default::default(),
+            hits: 0,
+        }
+    }
+}
+
+impl<S, O, R, E> SendEvent<Invoke<O>> for (&'_ mut CachedApp<S, O, R>, E)
+where
+    for<'a, 'b> (&'a mut S, &'b mut Option<InvokeOk<R>>): SendEvent<Invoke<O>>,
+    O: ReadOnly + Eq + Hash + Clone,
+    R: Clone,
+    E: SendEvent<InvokeOk<R>>,
+{
+    fn send(&mut self, Invoke(op): Invoke<O>) -> anyhow::Result<()> {
+        let (app, response) = self;
+        if !op.is_read_only() {
+            app.cache.clear()
+        } else if let Some(result) = app.cache.get(&op) {
+            app.hits += 1;
+            return response.send(InvokeOk(result.clone()));
+        }
+        let mut result = None;
+        (&mut app.inner, &mut result).send(Invoke(op.clone()))?;
+        let Some(InvokeOk(result)) = result else {
+            anyhow::bail!("missing execution result")
+        };
+        if op.is_read_only() {
+            app.cache.insert(op, result.clone());
+        }
+        response.send(InvokeOk(result))
+    }
+}
+
+// the cache is not included, and is dropped on restoring
+impl<S: Snapshot, O, R> Snapshot for CachedApp<S, O, R> {
+    fn snapshot(&self) -> anyhow::Result<Bytes> {
+        self.inner.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> anyhow::Result<()> {
+        self.cache.clear();
+        self.inner.restore(snapshot)
+    }
 }
 
 pub trait Workload {
@@ -43,26 +228,369 @@ pub trait Workload {
     ) -> anyhow::Result<()>;
 }
 
+// the rng of the workload of client `index`, so that the ops of all clients are reproduced from
+// `master_seed` alone, e.g. for comparing protocols against the same ops
+// the two are placed in disjoint bytes of the seed, so every (master seed, index) pair has its own
+// stream rather than some offset of another one's
+pub fn client_rng(master_seed: u64, index: usize) -> StdRng {
+    let mut seed = <StdRng as SeedableRng>::Seed::default();
+    seed[..8].copy_from_slice(&master_seed.to_le_bytes());
+    seed[8..16].copy_from_slice(&(index as u64).to_le_bytes());
+    StdRng::from_seed(seed)
+}
+
 #[derive(Debug, Clone)]
-pub struct CloseLoop<W, E> {
+pub struct CloseLoop<W, E, T = ()> {
     pub workload: W,
     pub sender: E,
+    // only for real runs. model checking leaves it `None`, and anyway skips `CloseLoop` (which is
+    // part of context states) when comparing and hashing
+    pub recorder: Option<Recorder>,
+    // `Thinking` with think time, see `with_think_time`
+    think: T,
+}
+
+// the pause of a client between receiving a result and issuing the next op
+#[derive(Debug, Clone, Copy)]
+pub enum ThinkTime {
+    Constant(Duration),
+    // exponentially distributed with the mean
+    Exponential(Duration),
+}
+
+impl ThinkTime {
+    fn sample(self, rng: &mut impl Rng) -> Duration {
+        match self {
+            Self::Constant(duration) => duration,
+            // same as `Arrival::Poisson`
+            Self::Exponential(mean) => mean.mul_f64(-(1. - rng.gen::<f64>()).ln()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Thinking<S, R> {
+    think_time: ThinkTime,
+    rng: StdRng,
+    schedule: S,
+    // the result that is held back from the workload until the `Think` timer goes off
+    held: Option<(R, ActiveTimer)>,
 }
 
 impl<W, E> CloseLoop<W, E> {
     pub fn new(workload: W, sender: E) -> Self {
-        Self { workload, sender }
+        Self {
+            workload,
+            sender,
+            recorder: None,
+            think: (),
+        }
+    }
+
+    pub fn with_recorder(workload: W, sender: E) -> Self {
+        Self {
+            recorder: Some(Default::default()),
+            ..Self::new(workload, sender)
+        }
+    }
+}
+
+impl<W, E, T> CloseLoop<W, E, T> {
+    // skip recording the first `n` completions, for discarding the samples before the system
+    // reaches steady state
+    pub fn with_warmup(mut self, n: usize) -> Self {
+        self.recorder.get_or_insert_with(Default::default).warmup = n;
+        self
+    }
+
+    // stop recording after `duration` since the first recorded completion. the loop keeps invoking
+    // after that, so the system stays loaded until every client is done with its window
+    pub fn for_duration(mut self, duration: Duration) -> Self {
+        self.recorder.get_or_insert_with(Default::default).duration = Some(duration);
+        self
+    }
+}
+
+impl<W: Workload, E> CloseLoop<W, E> {
+    // hold back every result for a think time before feeding it to the workload, which issues the
+    // next op. the `Think` timer is set on `schedule`, and the driver delivers it back as an event
+    pub fn with_think_time<S: ScheduleEvent<Think>>(
+        self,
+        think_time: ThinkTime,
+        rng: StdRng,
+        schedule: S,
+    ) -> CloseLoop<W, E, Thinking<S, W::Result>> {
+        CloseLoop {
+            workload: self.workload,
+            sender: self.sender,
+            recorder: self.recorder,
+            think: Thinking {
+                think_time,
+                rng,
+                schedule,
+                held: None,
+            },
+        }
     }
 }
 
-impl<W: Workload, E: SendEvent<Invoke<W::Op>>> CloseLoop<W, E> {
+impl<W: Workload, E: SendEvent<Invoke<W::Op>>, T> CloseLoop<W, E, T> {
     pub fn init(&mut self) -> anyhow::Result<()> {
-        self.workload.init(&mut self.sender)
+        self.workload
+            .init(Recording(&mut self.sender, self.recorder.as_mut()))
     }
 }
 
 impl<W: Workload, E: SendEvent<Invoke<W::Op>>> SendEvent<InvokeOk<W::Result>> for CloseLoop<W, E> {
     fn send(&mut self, result: InvokeOk<W::Result>) -> anyhow::Result<()> {
-        self.workload.on_result(result, &mut self.sender)
+        if let Some(recorder) = &mut self.recorder {
+            recorder.complete()?
+        }
+        self.workload
+            .on_result(result, Recording(&mut self.sender, self.recorder.as_mut()))
+    }
+}
+
+impl<W: Workload, E: SendEvent<Invoke<W::Op>>, S: ScheduleEvent<Think>>
+    SendEvent<InvokeOk<W::Result>> for CloseLoop<W, E, Thinking<S, W::Result>>
+{
+    fn send(&mut self, InvokeOk(result): InvokeOk<W::Result>) -> anyhow::Result<()> {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.complete()?
+        }
+        let think = &mut self.think;
+        anyhow::ensure!(think.held.is_none(), "result while thinking");
+        let timer = think
+            .schedule
+            .set(think.think_time.sample(&mut think.rng), Think)?;
+        think.held = Some((result, timer));
+        Ok(())
+    }
+}
+
+impl<W: Workload, E: SendEvent<Invoke<W::Op>>, S: ScheduleEvent<Think>> SendEvent<Think>
+    for CloseLoop<W, E, Thinking<S, W::Result>>
+{
+    fn send(&mut self, Think: Think) -> anyhow::Result<()> {
+        let Some((result, timer)) = self.think.held.take() else {
+            anyhow::bail!("missing thinking result")
+        };
+        self.think.schedule.unset(timer)?;
+        self.workload.on_result(
+            InvokeOk(result),
+            Recording(&mut self.sender, self.recorder.as_mut()),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    outstanding: VecDeque<Instant>,
+    // (invoke, complete), assuming invocations complete in FIFO order
+    pub samples: Vec<(Instant, Instant)>,
+    // number of in-flight invocations after each change
+    pub in_flight: Vec<(Instant, usize)>,
+    // the measurement window. `samples` and `in_flight` only cover the changes within it
+    warmup: usize,
+    duration: Option<Duration>,
+    num_completed: usize,
+    window_start: Option<Instant>,
+}
+
+impl Recorder {
+    fn invoke(&mut self) {
+        let now = Instant::now();
+        self.outstanding.push_back(now);
+        if self.window_start.is_some() && !self.is_done(now) {
+            self.in_flight.push((now, self.outstanding.len()))
+        }
+    }
+
+    fn complete(&mut self) -> anyhow::Result<()> {
+        let Some(start) = self.outstanding.pop_front() else {
+            anyhow::bail!("missing outstanding invocation")
+        };
+        self.num_completed += 1;
+        if self.num_completed <= self.warmup {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if self.is_done(now) {
+            return Ok(());
+        }
+        self.window_start.get_or_insert(now);
+        self.samples.push((start, now));
+        self.in_flight.push((now, self.outstanding.len()));
+        Ok(())
+    }
+
+    // whether the measurement window has ended at `now`
+    pub fn is_done(&self, now: Instant) -> bool {
+        match (self.window_start, self.duration) {
+            (Some(window_start), Some(duration)) => now - window_start >= duration,
+            _ => false,
+        }
+    }
+}
+
+struct Recording<'a, E>(E, Option<&'a mut Recorder>);
+
+impl<E: SendEvent<Invoke<M>>, M> SendEvent<Invoke<M>> for Recording<'_, E> {
+    fn send(&mut self, event: Invoke<M>) -> anyhow::Result<()> {
+        if let Some(recorder) = &mut self.1 {
+            recorder.invoke()
+        }
+        self.0.send(event)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    Fixed,
+    Poisson,
+}
+
+// issue ops at a configured rate regardless of outstanding ones, for measuring latency under a
+// given load
+// the workload is used as an op generator: every `Arrive` takes a fresh op from its `init`, and the
+// results are not fed back to it, so it only works with workloads that do not depend on results
+// e.g. `UncheckedIter`
+// the invocations are expected to complete in FIFO order i.e. each result is matched to the
+// earliest outstanding invocation when recording latency
+// the driver is responsible for delivering `Arrive` with the gaps suggested by `interval`
+#[derive(Debug)]
+pub struct OpenLoop<W, E> {
+    pub workload: W,
+    pub sender: E,
+    mean_interval: Duration,
+    arrival: Arrival,
+    rng: StdRng,
+    outstanding: VecDeque<Instant>,
+    pub latencies: Vec<Duration>,
+    pub done: bool,
+}
+
+impl<W, E> OpenLoop<W, E> {
+    pub fn new(
+        workload: W,
+        sender: E,
+        rate: f64, // arrivals per second
+        arrival: Arrival,
+        seed_rng: &mut impl Rng,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(rate > 0., "invalid rate {rate}");
+        Ok(Self {
+            workload,
+            sender,
+            mean_interval: Duration::from_secs_f64(1. / rate),
+            arrival,
+            rng: StdRng::from_rng(seed_rng)?,
+            outstanding: Default::default(),
+            latencies: Default::default(),
+            done: false,
+        })
+    }
+
+    // the gap before the next `Arrive`
+    pub fn interval(&mut self) -> Duration {
+        match self.arrival {
+            Arrival::Fixed => self.mean_interval,
+            // exponentially distributed gaps, sampled by inverse transform. `1 - u` keeps the
+            // logarithm away from zero
+            Arrival::Poisson => self
+                .mean_interval
+                .mul_f64(-(1. - self.rng.gen::<f64>()).ln()),
+        }
+    }
+}
+
+impl<W: Workload, E: SendEvent<Invoke<W::Op>>> SendEvent<Arrive> for OpenLoop<W, E> {
+    fn send(&mut self, Arrive: Arrive) -> anyhow::Result<()> {
+        if self.done {
+            return Ok(());
+        }
+        let mut intercept = None;
+        self.workload.init(&mut intercept)?;
+        let Some(invoke) = intercept else {
+            self.done = true;
+            return Ok(());
+        };
+        self.outstanding.push_back(Instant::now());
+        self.sender.send(invoke)
+    }
+}
+
+impl<W: Workload, E> SendEvent<InvokeOk<W::Result>> for OpenLoop<W, E> {
+    fn send(&mut self, InvokeOk(_): InvokeOk<W::Result>) -> anyhow::Result<()> {
+        let Some(start) = self.outstanding.pop_front() else {
+            anyhow::bail!("missing outstanding invocation")
+        };
+        self.latencies.push(start.elapsed());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warmup() -> anyhow::Result<()> {
+        let mut recorder = CloseLoop::new((), ())
+            .with_warmup(2)
+            .for_duration(Duration::ZERO)
+            .recorder
+            .unwrap();
+        for _ in 0..4 {
+            recorder.invoke()
+        }
+        for _ in 0..4 {
+            recorder.complete()?
+        }
+        // the third completion opens the window, which ends right away
+        anyhow::ensure!(recorder.samples.len() == 1);
+        anyhow::ensure!(recorder.in_flight == [(recorder.samples[0].1, 1)]);
+        anyhow::ensure!(recorder.is_done(Instant::now()));
+        Ok(())
+    }
+
+    #[test]
+    fn think_time() -> anyhow::Result<()> {
+        use crate::{model::search::state::Schedule, workload::combinators::UncheckedIter};
+
+        let ops = [Bytes::from("foo"), Bytes::from("bar")];
+        let mut close_loop = CloseLoop::new(UncheckedIter::<(), _>::new(ops.clone()), None)
+            .with_think_time(
+                ThinkTime::Constant(Duration::from_millis(100)),
+                client_rng(0, 0),
+                Schedule::new(),
+            );
+        close_loop.init()?;
+        anyhow::ensure!(matches!(close_loop.sender.take(), Some(Invoke(op)) if op == ops[0]));
+        close_loop.send(InvokeOk(()))?;
+        // the next op is not issued until the timer goes off
+        anyhow::ensure!(close_loop.sender.is_none());
+        let schedule = &close_loop.think.schedule;
+        let Some((id, Think)) = schedule.events().next() else {
+            anyhow::bail!("missing think timer")
+        };
+        anyhow::ensure!(schedule.period(id) == Some(Duration::from_millis(100)));
+        close_loop.send(Think)?;
+        anyhow::ensure!(matches!(close_loop.sender.take(), Some(Invoke(op)) if op == ops[1]));
+        anyhow::ensure!(close_loop.think.schedule.events().next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn noop() -> anyhow::Result<()> {
+        let mut payloads = random_payloads(100, &mut rand::thread_rng())?;
+        let op = payloads.next().unwrap();
+        anyhow::ensure!(op.len() == 100 && payloads.next() != Some(op.clone()));
+        anyhow::ensure!(App::execute(&mut NoopApp::default(), &op)? == op);
+        let mut app = NoopApp {
+            reply_size: Some(10),
+        };
+        anyhow::ensure!(App::execute(&mut app, &op)? == [0; 10][..]);
+        Ok(())
     }
 }
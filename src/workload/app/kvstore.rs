@@ -6,15 +6,48 @@ use serde::{Deserialize, Serialize};
 
 use crate::codec::Encode;
 use crate::event::SendEvent;
-use crate::workload::events::{Invoke, InvokeOk};
+use crate::workload::{
+    client_rng,
+    events::{Invoke, InvokeOk},
+    ReadOnly, Snapshot,
+};
 
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
-pub struct KVStore(BTreeMap<String, String>);
+// the default of the server-side cap on the number of records a single scan returns
+pub const MAX_SCAN: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KVStore {
+    store: BTreeMap<String, String>,
+    max_scan: usize,
+}
+
+impl Default for KVStore {
+    fn default() -> Self {
+        Self {
+            store: Default::default(),
+            max_scan: MAX_SCAN,
+        }
+    }
+}
 
 impl KVStore {
     pub fn new() -> Self {
         Self::default()
     }
+
+    // bound the work of a scan regardless of the requested count, so a misbehaving client cannot
+    // stall the replica with a huge one
+    pub fn with_max_scan(max_scan: usize) -> Self {
+        Self {
+            max_scan,
+            ..Self::new()
+        }
+    }
+
+    // all records in key order, without the cap of `Scan`
+    pub fn records(&self) -> impl Iterator<Item = (&String, &String)> + '_ {
+        self.store.iter()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -22,6 +55,34 @@ pub enum Op {
     Put(String, String),
     Get(String),
     Append(String, String),
+    // up to `count` records with keys not less than `key`, in key order. the count is capped by the
+    // server, see `ScanResult`
+    Scan(String, usize),
+    Delete(String),
+    // executed atomically: either all ops take effect, or the transaction aborts with none of them
+    // taking effect. it aborts on the first op that fails, which is a `Get` of a missing key or a
+    // nested transaction
+    Transaction(Vec<Op>),
+}
+
+impl ReadOnly for Op {
+    fn is_read_only(&self) -> bool {
+        match self {
+            Self::Get(_) | Self::Scan(..) => true,
+            Self::Transaction(ops) => ops.iter().all(Self::is_read_only),
+            _ => false,
+        }
+    }
+}
+
+impl Op {
+    // the key that the op may write, whose previous value is kept for rolling back a transaction
+    pub fn written_key(&self) -> Option<&String> {
+        match self {
+            Self::Put(key, _) | Self::Append(key, _) | Self::Delete(key) => Some(key),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -30,14 +91,44 @@ pub enum Result {
     GetResult(String),
     KeyNotFound,
     AppendResult(String),
+    // the records and whether they have been truncated by the server-side cap, i.e. the scan would
+    // return more records without it. the client may continue with a scan that starts after the last
+    // record
+    ScanResult(Vec<(String, String)>, bool),
+    // deleting a missing key is not an error, so the op is idempotent as the others
+    DeleteOk,
+    // the results of all ops in order
+    TransactionResult(Vec<Result>),
+    // the index of the failed op, which is the only indication of the failure. none of the ops took
+    // effect
+    TransactionAborted(usize),
+}
+
+// `max_scan` is configuration rather than state, and is kept on restoring
+impl Snapshot for KVStore {
+    fn snapshot(&self) -> anyhow::Result<bytes::Bytes> {
+        self.store.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> anyhow::Result<()> {
+        self.store.restore(snapshot)
+    }
 }
 
 pub type App = crate::codec::Decode<Op, Encode<Result, KVStore>>;
 
 impl<E: SendEvent<InvokeOk<Result>>> SendEvent<Invoke<Op>> for (&'_ mut KVStore, E) {
     fn send(&mut self, Invoke(op): Invoke<Op>) -> anyhow::Result<()> {
-        let (KVStore(store), response) = self;
-        let result = match op {
+        let (store, response) = self;
+        let result = store.execute(op);
+        response.send(InvokeOk(result))
+    }
+}
+
+impl KVStore {
+    fn execute(&mut self, op: Op) -> Result {
+        let Self { store, max_scan } = self;
+        match op {
             Op::Put(key, value) => {
                 store.insert(key, value);
                 Result::PutOk
@@ -55,8 +146,51 @@ impl<E: SendEvent<InvokeOk<Result>>> SendEvent<Invoke<Op>> for (&'_ mut KVStore,
                 store.insert(key, value.clone());
                 Result::AppendResult(value)
             }
-        };
-        response.send(InvokeOk(result))
+            Op::Scan(key, count) => {
+                let limit = count.min(*max_scan);
+                // one more record for telling whether there are more
+                let mut records = store
+                    .range(key..)
+                    .take(limit.saturating_add(1))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect::<Vec<_>>();
+                let truncated = records.len() > limit && limit < count;
+                records.truncate(limit);
+                Result::ScanResult(records, truncated)
+            }
+            Op::Delete(key) => {
+                store.remove(&key);
+                Result::DeleteOk
+            }
+            Op::Transaction(ops) => self.execute_transaction(ops),
+        }
+    }
+
+    // the writes are applied in place, and undone in reverse order on aborting
+    fn execute_transaction(&mut self, ops: Vec<Op>) -> Result {
+        let mut undo_log = Vec::new();
+        let mut results = Vec::new();
+        for (index, op) in ops.into_iter().enumerate() {
+            if let Some(key) = op.written_key() {
+                undo_log.push((key.clone(), self.store.get(key).cloned()))
+            }
+            let result = match op {
+                Op::Transaction(_) => None,
+                op => Some(self.execute(op)).filter(|result| *result != Result::KeyNotFound),
+            };
+            let Some(result) = result else {
+                for (key, value) in undo_log.into_iter().rev() {
+                    if let Some(value) = value {
+                        self.store.insert(key, value);
+                    } else {
+                        self.store.remove(&key);
+                    }
+                }
+                return Result::TransactionAborted(index);
+            };
+            results.push(result)
+        }
+        Result::TransactionResult(results)
     }
 }
 
@@ -68,6 +202,7 @@ pub struct InfinitePutGet {
     rng: StdRng,
     values: [String; 5],
     should_get: bool,
+    deletes: bool,
 }
 
 impl InfinitePutGet {
@@ -77,6 +212,28 @@ impl InfinitePutGet {
             rng: StdRng::from_rng(seed_rng)?,
             values: Default::default(),
             should_get: false,
+            deletes: false,
+        })
+    }
+
+    // see `workload::client_rng`
+    pub fn for_client(
+        namespace: impl Into<String>,
+        master_seed: u64,
+        index: usize,
+    ) -> anyhow::Result<Self> {
+        Self::new(namespace, &mut client_rng(master_seed, index))
+    }
+
+    // half of the writes delete the key instead, and the following gets of the deleted keys expect
+    // `KeyNotFound` until they are put again
+    pub fn with_deletes(
+        namespace: impl Into<String>,
+        seed_rng: &mut impl Rng,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            deletes: true,
+            ..Self::new(namespace, seed_rng)?
         })
     }
 }
@@ -95,6 +252,12 @@ impl Iterator for InfinitePutGet {
                     Result::GetResult(self.values[index].clone())
                 },
             )
+        } else if self.deletes && self.rng.gen() {
+            self.values[index] = String::default();
+            (
+                Op::Delete(format!("{}-{index}", self.namespace)),
+                Result::DeleteOk,
+            )
         } else {
             let value = (&mut self.rng)
                 .sample_iter(Alphanumeric)
@@ -111,3 +274,265 @@ impl Iterator for InfinitePutGet {
         Some((op, result))
     }
 }
+
+// transactions of `txn_size` appends to random keys, which the ops of workload F (read-modify-write)
+// become when made atomic. with `aborts`, a quarter of the transactions additionally get a missing
+// key at the end, and expect to be aborted without any of the appends taking effect
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive_where(Hash)]
+pub struct TxnWorkload {
+    namespace: String,
+    #[derive_where(skip)]
+    rng: StdRng,
+    values: [String; 5],
+    txn_size: usize,
+    aborts: bool,
+}
+
+impl TxnWorkload {
+    pub fn new(
+        namespace: impl Into<String>,
+        txn_size: usize,
+        seed_rng: &mut impl Rng,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            namespace: namespace.into(),
+            rng: StdRng::from_rng(seed_rng)?,
+            values: Default::default(),
+            txn_size,
+            aborts: false,
+        })
+    }
+
+    pub fn with_aborts(self) -> Self {
+        Self {
+            aborts: true,
+            ..self
+        }
+    }
+}
+
+impl Iterator for TxnWorkload {
+    type Item = (Op, Result);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut values = self.values.clone();
+        let mut ops = Vec::new();
+        let mut results = Vec::new();
+        for _ in 0..self.txn_size {
+            let index = self.rng.gen_range(0..5);
+            let postfix = (&mut self.rng)
+                .sample_iter(Alphanumeric)
+                .take(8)
+                .map(char::from)
+                .collect::<String>();
+            values[index] += &postfix;
+            ops.push(Op::Append(format!("{}-{index}", self.namespace), postfix));
+            results.push(Result::AppendResult(values[index].clone()))
+        }
+        if self.aborts && self.rng.gen_ratio(1, 4) {
+            ops.push(Op::Get(format!("{}-missing", self.namespace)));
+            return Some((
+                Op::Transaction(ops),
+                Result::TransactionAborted(self.txn_size),
+            ));
+        }
+        self.values = values;
+        Some((Op::Transaction(ops), Result::TransactionResult(results)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invoke(store: &mut KVStore, op: Op) -> anyhow::Result<Result> {
+        let mut result = None;
+        (store, &mut result).send(Invoke(op))?;
+        let Some(InvokeOk(result)) = result else {
+            anyhow::bail!("missing result")
+        };
+        Ok(result)
+    }
+
+    #[test]
+    fn scan() -> anyhow::Result<()> {
+        let mut store = KVStore::new();
+        for key in ["c", "a", "e", "b", "d"] {
+            invoke(&mut store, Op::Put(key.into(), key.to_uppercase()))?;
+        }
+        let records = |keys: &[&str]| {
+            Result::ScanResult(
+                keys.iter()
+                    .map(|key| (key.to_string(), key.to_uppercase()))
+                    .collect(),
+                false,
+            )
+        };
+        anyhow::ensure!(invoke(&mut store, Op::Scan("b".into(), 3))? == records(&["b", "c", "d"]));
+        // the start key is not necessarily present
+        anyhow::ensure!(
+            invoke(&mut store, Op::Scan("bb".into(), 10))? == records(&["c", "d", "e"])
+        );
+        anyhow::ensure!(invoke(&mut store, Op::Scan("f".into(), 1))? == records(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn scan_cap() -> anyhow::Result<()> {
+        let mut store = KVStore::with_max_scan(2);
+        for key in ["a", "b", "c"] {
+            invoke(&mut store, Op::Put(key.into(), key.into()))?;
+        }
+        let scan = |store: &mut _, key: &str, count| {
+            let Result::ScanResult(records, truncated) =
+                invoke(store, Op::Scan(key.into(), count))?
+            else {
+                anyhow::bail!("unexpected result")
+            };
+            anyhow::Ok((records.len(), truncated))
+        };
+        anyhow::ensure!(scan(&mut store, "a", usize::MAX)? == (2, true));
+        // not truncated by the cap if the client asks for no more than it
+        anyhow::ensure!(scan(&mut store, "a", 2)? == (2, false));
+        // or if there is no more record anyway
+        anyhow::ensure!(scan(&mut store, "b", 3)? == (2, false));
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot() -> anyhow::Result<()> {
+        use crate::{codec::Decode, workload::App as _};
+
+        let mut app = Decode::json(Encode::json(KVStore::new()));
+        let execute = |app: &mut App, op| {
+            let result = app.execute(&serde_json::to_vec(&op)?)?;
+            anyhow::Ok(serde_json::from_slice::<Result>(&result)?)
+        };
+        for (key, value) in [("foo", "bar"), ("baz", "qux")] {
+            execute(&mut app, Op::Put(key.into(), value.into()))?;
+        }
+        execute(&mut app, Op::Delete("baz".into()))?;
+        let snapshot = app.snapshot()?;
+        let mut restored = Decode::json(Encode::json(KVStore::new()));
+        execute(&mut restored, Op::Put("baz".into(), "stale".into()))?;
+        restored.restore(&snapshot)?;
+        anyhow::ensure!(restored == app);
+        for key in ["foo", "baz"] {
+            anyhow::ensure!(
+                execute(&mut restored, Op::Get(key.into()))?
+                    == execute(&mut app, Op::Get(key.into()))?
+            )
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn for_client() -> anyhow::Result<()> {
+        let ops = |master_seed, index| {
+            anyhow::Ok(
+                InfinitePutGet::for_client("KEY", master_seed, index)?
+                    .take(100)
+                    .collect::<Vec<_>>(),
+            )
+        };
+        anyhow::ensure!(ops(42, 0)? == ops(42, 0)?);
+        anyhow::ensure!(ops(42, 0)? != ops(42, 1)?);
+        anyhow::ensure!(ops(42, 0)? != ops(43, 0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn cached() -> anyhow::Result<()> {
+        use crate::{
+            codec::Decode,
+            workload::{App as _, CachedApp},
+        };
+
+        let mut app = Decode::json(Encode::json(CachedApp::new(KVStore::new())));
+        let mut execute = |op| {
+            let result = app.execute(&serde_json::to_vec(&op)?)?;
+            anyhow::Ok((serde_json::from_slice::<Result>(&result)?, app.hits))
+        };
+        let get = || Op::Get("foo".into());
+        anyhow::ensure!(execute(get())? == (Result::KeyNotFound, 0));
+        anyhow::ensure!(execute(get())? == (Result::KeyNotFound, 1));
+        // every write invalidates, so the following get misses once and hits after that
+        for (op, expected, hits) in [
+            (
+                Op::Put("foo".into(), "bar".into()),
+                Result::GetResult("bar".into()),
+                2,
+            ),
+            (
+                Op::Append("foo".into(), "baz".into()),
+                Result::GetResult("barbaz".into()),
+                3,
+            ),
+            (Op::Delete("foo".into()), Result::KeyNotFound, 4),
+        ] {
+            execute(op)?;
+            anyhow::ensure!(execute(get())? == (expected.clone(), hits - 1));
+            anyhow::ensure!(execute(get())? == (expected, hits));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn transaction() -> anyhow::Result<()> {
+        let mut store = KVStore::new();
+        invoke(&mut store, Op::Put("foo".into(), "bar".into()))?;
+        let ops = vec![
+            Op::Append("foo".into(), "baz".into()),
+            Op::Delete("foo".into()),
+            Op::Put("qux".into(), "quux".into()),
+            Op::Get("qux".into()),
+        ];
+        let result = invoke(&mut store, Op::Transaction(ops.clone()))?;
+        anyhow::ensure!(
+            result
+                == Result::TransactionResult(vec![
+                    Result::AppendResult("barbaz".into()),
+                    Result::DeleteOk,
+                    Result::PutOk,
+                    Result::GetResult("quux".into()),
+                ])
+        );
+        // none of the writes before the failed op takes effect
+        let committed = store.clone();
+        let mut ops = ops;
+        ops.insert(3, Op::Get("foo".into()));
+        let result = invoke(&mut store, Op::Transaction(ops))?;
+        anyhow::ensure!(result == Result::TransactionAborted(3));
+        anyhow::ensure!(store == committed);
+
+        let mut workload = TxnWorkload::new("KEY", 3, &mut StdRng::seed_from_u64(0))?.with_aborts();
+        for (op, expected) in workload.by_ref().take(100) {
+            let result = invoke(&mut store, op.clone())?;
+            anyhow::ensure!(
+                result == expected,
+                "{op:?} get {result:?} expect {expected:?}"
+            )
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn put_get_delete() -> anyhow::Result<()> {
+        let mut store = KVStore::new();
+        let mut workload = InfinitePutGet::with_deletes("KEY", &mut StdRng::seed_from_u64(0))?;
+        let mut num_delete = 0;
+        for (op, expected) in workload.by_ref().take(1000) {
+            if matches!(op, Op::Delete(_)) {
+                num_delete += 1
+            }
+            let result = invoke(&mut store, op.clone())?;
+            anyhow::ensure!(
+                result == expected,
+                "{op:?} get {result:?} expect {expected:?}"
+            )
+        }
+        anyhow::ensure!(num_delete > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::Encode,
+    crypto::{DigestHash as _, H256},
+    event::SendEvent,
+    workload::{
+        events::{Invoke, InvokeOk},
+        ReadOnly as _, Snapshot,
+    },
+};
+
+use super::kvstore::{self, KVStore, Op};
+
+// a key-value store that certifies its reads with a Merkle tree over the records, so a client (or an
+// auditor) that knows the root can check a read without trusting the replying replica
+// the root comes along with every proven read. with a replicated service the client already waits
+// for matching replies from enough replicas, and the root that is matched in the replies is the
+// committed one
+// the tree is rebuilt from all records on the first read after writes, which is linear to the size
+// of the store
+#[derive(Debug, Clone, Default)]
+pub struct MerkleKVStore {
+    store: KVStore,
+    // the levels from the leaves up to the root, `None` if outdated
+    tree: Option<Vec<Vec<H256>>>,
+}
+
+impl MerkleKVStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn root(&mut self) -> H256 {
+        self.tree()
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn tree(&mut self) -> &[Vec<H256>] {
+        self.tree.get_or_insert_with(|| {
+            let mut level = self
+                .store
+                .records()
+                .map(|(key, value)| leaf_digest(key, value))
+                .collect::<Vec<_>>();
+            let mut tree = Vec::new();
+            while level.len() > 1 {
+                let next_level = level
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        [left, right] => node_digest(*left, *right),
+                        // the odd one is promoted as is
+                        [node] => *node,
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                tree.push(std::mem::replace(&mut level, next_level))
+            }
+            if !level.is_empty() {
+                tree.push(level)
+            }
+            tree
+        })
+    }
+
+    // the value of `key` along with its proof
+    fn prove(&mut self, key: &str) -> Option<(String, Proof)> {
+        let (mut index, (_, value)) = self
+            .store
+            .records()
+            .enumerate()
+            .find(|(_, (other, _))| *other == key)?;
+        let value = value.clone();
+        let mut siblings = Vec::new();
+        for level in self.tree() {
+            match level.get(index ^ 1) {
+                Some(sibling) if index % 2 == 0 => siblings.push(Sibling::Right(*sibling)),
+                Some(sibling) => siblings.push(Sibling::Left(*sibling)),
+                None => {}
+            }
+            index /= 2
+        }
+        Some((value, Proof { siblings }))
+    }
+}
+
+// the leaves and the inner nodes are hashed in different domains, so an inner node cannot pass as a
+// record
+fn leaf_digest(key: &str, value: &str) -> H256 {
+    (0u8, key, value).sha256()
+}
+
+fn node_digest(left: H256, right: H256) -> H256 {
+    (1u8, left, right).sha256()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Sibling {
+    Left(H256),
+    Right(H256),
+}
+
+// the siblings on the path from the leaf of the record up to the root
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Proof {
+    pub siblings: Vec<Sibling>,
+}
+
+impl Proof {
+    pub fn verify(&self, root: H256, key: &str, value: &str) -> anyhow::Result<()> {
+        let digest = self
+            .siblings
+            .iter()
+            .fold(leaf_digest(key, value), |digest, sibling| match sibling {
+                Sibling::Left(sibling) => node_digest(*sibling, digest),
+                Sibling::Right(sibling) => node_digest(digest, *sibling),
+            });
+        anyhow::ensure!(digest == root, "record not included in root {root:?}");
+        Ok(())
+    }
+}
+
+// only the `Get`s of the existing keys are proven. the results of the other ops, including the
+// `Get`s in transactions and the missing keys, are the same as `kvstore::App`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Result {
+    Plain(kvstore::Result),
+    Proven {
+        value: String,
+        proof: Proof,
+        root: H256,
+    },
+}
+
+// the tree is derived from the records and rebuilt after restoring
+impl Snapshot for MerkleKVStore {
+    fn snapshot(&self) -> anyhow::Result<bytes::Bytes> {
+        self.store.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> anyhow::Result<()> {
+        self.tree = None;
+        self.store.restore(snapshot)
+    }
+}
+
+pub type App = crate::codec::Decode<Op, Encode<Result, MerkleKVStore>>;
+
+impl<E: SendEvent<InvokeOk<Result>>> SendEvent<Invoke<Op>> for (&'_ mut MerkleKVStore, E) {
+    fn send(&mut self, Invoke(op): Invoke<Op>) -> anyhow::Result<()> {
+        let (store, response) = self;
+        if let Op::Get(key) = &op {
+            if let Some((value, proof)) = store.prove(key) {
+                let result = Result::Proven {
+                    value,
+                    proof,
+                    root: store.root(),
+                };
+                return response.send(InvokeOk(result));
+            }
+        }
+        if !op.is_read_only() {
+            store.tree = None
+        }
+        let mut result = None;
+        (&mut store.store, &mut result).send(Invoke(op))?;
+        let Some(InvokeOk(result)) = result else {
+            anyhow::bail!("missing execution result")
+        };
+        response.send(InvokeOk(Result::Plain(result)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invoke(store: &mut MerkleKVStore, op: Op) -> anyhow::Result<Result> {
+        let mut result = None;
+        (store, &mut result).send(Invoke(op))?;
+        let Some(InvokeOk(result)) = result else {
+            anyhow::bail!("missing result")
+        };
+        Ok(result)
+    }
+
+    #[test]
+    fn proof() -> anyhow::Result<()> {
+        let mut store = MerkleKVStore::new();
+        let empty_root = store.root();
+        // an odd number of records, so some node is promoted
+        for i in 0..5 {
+            invoke(&mut store, Op::Put(format!("key{i}"), format!("value{i}")))?;
+        }
+        let root = store.root();
+        anyhow::ensure!(root != empty_root);
+        for i in 0..5 {
+            let (key, value) = (format!("key{i}"), format!("value{i}"));
+            let Result::Proven {
+                value: proven_value,
+                proof,
+                root: proven_root,
+            } = invoke(&mut store, Op::Get(key.clone()))?
+            else {
+                anyhow::bail!("unproven read of {key}")
+            };
+            anyhow::ensure!(proven_value == value && proven_root == root);
+            proof.verify(root, &key, &value)?;
+            anyhow::ensure!(proof.verify(root, &key, "tampered").is_err());
+            anyhow::ensure!(proof.verify(root, "key5", &value).is_err())
+        }
+        anyhow::ensure!(
+            invoke(&mut store, Op::Get(String::from("key5")))?
+                == Result::Plain(kvstore::Result::KeyNotFound)
+        );
+
+        invoke(
+            &mut store,
+            Op::Append(String::from("key0"), String::from("!")),
+        )?;
+        anyhow::ensure!(store.root() != root);
+        let mut restored = MerkleKVStore::new();
+        restored.restore(&store.snapshot()?)?;
+        anyhow::ensure!(restored.root() == store.root());
+        Ok(())
+    }
+}
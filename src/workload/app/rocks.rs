@@ -0,0 +1,167 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+
+use crate::{
+    codec::{Decode, Encode},
+    event::SendEvent,
+    workload::{
+        events::{Invoke, InvokeOk},
+        Snapshot,
+    },
+};
+
+use super::kvstore::{Op, Result, MAX_SCAN};
+
+// the persistent counterpart of `KVStore` for durability benchmarks, which executes the same ops so
+// the two are interchangeable on the client side
+// every write goes to the store (and its WAL) before the result is replied
+#[derive(Debug)]
+pub struct RocksStore {
+    db: DB,
+    max_scan: usize,
+}
+
+impl RocksStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: DB::open_default(path)?,
+            max_scan: MAX_SCAN,
+        })
+    }
+
+    // see `KVStore::with_max_scan`
+    pub fn with_max_scan(mut self, max_scan: usize) -> Self {
+        self.max_scan = max_scan;
+        self
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let Some(value) = self.db.get(key)? else {
+            return Ok(None);
+        };
+        Ok(Some(String::from_utf8(value)?))
+    }
+}
+
+// the snapshot is a full copy of the records, which suits the small stores of benchmarks. restoring
+// replaces all records in a single batch
+impl Snapshot for RocksStore {
+    fn snapshot(&self) -> anyhow::Result<Bytes> {
+        let records = self
+            .db
+            .iterator(IteratorMode::Start)
+            .map(|record| {
+                let (key, value) = record?;
+                anyhow::Ok((key.into_vec(), value.into_vec()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        crate::codec::bincode::encode(&records)
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> anyhow::Result<()> {
+        let records = crate::codec::bincode::decode::<Vec<(Vec<u8>, Vec<u8>)>>(snapshot)?;
+        let mut batch = WriteBatch::default();
+        for record in self.db.iterator(IteratorMode::Start) {
+            let (key, _) = record?;
+            batch.delete(key)
+        }
+        for (key, value) in records {
+            batch.put(key, value)
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+pub type App = Decode<Op, Encode<Result, RocksStore>>;
+
+impl<E: SendEvent<InvokeOk<Result>>> SendEvent<Invoke<Op>> for (&'_ mut RocksStore, E) {
+    fn send(&mut self, Invoke(op): Invoke<Op>) -> anyhow::Result<()> {
+        let (store, response) = self;
+        let result = store.execute(op)?;
+        response.send(InvokeOk(result))
+    }
+}
+
+impl RocksStore {
+    fn execute(&mut self, op: Op) -> anyhow::Result<Result> {
+        let store = self;
+        let result = match op {
+            Op::Put(key, value) => {
+                store.db.put(key, value)?;
+                Result::PutOk
+            }
+            Op::Get(key) => {
+                if let Some(value) = store.get(&key)? {
+                    Result::GetResult(value)
+                } else {
+                    Result::KeyNotFound
+                }
+            }
+            Op::Append(key, postfix) => {
+                let mut value = store.get(&key)?.unwrap_or_default();
+                value += &postfix;
+                store.db.put(key, &value)?;
+                Result::AppendResult(value)
+            }
+            Op::Scan(key, count) => {
+                let limit = count.min(store.max_scan);
+                let mut records = Vec::new();
+                for record in store
+                    .db
+                    .iterator(IteratorMode::From(key.as_bytes(), Direction::Forward))
+                    .take(limit.saturating_add(1))
+                {
+                    let (key, value) = record?;
+                    records.push((
+                        String::from_utf8(key.into())?,
+                        String::from_utf8(value.into())?,
+                    ))
+                }
+                let truncated = records.len() > limit && limit < count;
+                records.truncate(limit);
+                Result::ScanResult(records, truncated)
+            }
+            Op::Delete(key) => {
+                store.db.delete(key)?;
+                Result::DeleteOk
+            }
+            Op::Transaction(ops) => store.execute_transaction(ops)?,
+        };
+        Ok(result)
+    }
+
+    // same as `KVStore`, the writes are applied in place and undone on aborting, and the undoing
+    // is written in a single batch
+    // the writes are not atomic against a crash in the middle of a transaction, which is fine for
+    // benchmarking
+    fn execute_transaction(&mut self, ops: Vec<Op>) -> anyhow::Result<Result> {
+        let mut undo_log = Vec::new();
+        let mut results = Vec::new();
+        for (index, op) in ops.into_iter().enumerate() {
+            if let Some(key) = op.written_key() {
+                undo_log.push((key.clone(), self.get(key)?))
+            }
+            let result = match op {
+                Op::Transaction(_) => None,
+                op => Some(self.execute(op)?).filter(|result| *result != Result::KeyNotFound),
+            };
+            let Some(result) = result else {
+                let mut batch = WriteBatch::default();
+                for (key, value) in undo_log.into_iter().rev() {
+                    if let Some(value) = value {
+                        batch.put(key, value)
+                    } else {
+                        batch.delete(key)
+                    }
+                }
+                self.db.write(batch)?;
+                return Ok(Result::TransactionAborted(index));
+            };
+            results.push(result)
+        }
+        Ok(Result::TransactionResult(results))
+    }
+}
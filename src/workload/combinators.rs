@@ -1,8 +1,10 @@
 use derive_more::Deref;
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, path::Path};
 
-use crate::event::SendEvent;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{codec::bincode, event::SendEvent};
 
 use super::{
     events::{Invoke, InvokeOk},
@@ -172,3 +174,107 @@ where
         Ok(())
     }
 }
+
+// capture the ops that a (usually randomized) workload produces, so that the exact same op stream
+// can be replayed later with `Replay`, e.g. to give two protocols identical requests independent
+// of RNG seeding
+// unlike `Record` it only keeps the ops, and does not require the results to be cloneable
+#[derive(Debug, Clone, Deref)]
+pub struct Trace<O, W> {
+    #[deref]
+    inner: W,
+    pub ops: Vec<O>,
+}
+
+impl<O, W> Trace<O, W> {
+    pub fn new(workload: W) -> Self {
+        Self {
+            inner: workload,
+            ops: Default::default(),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()>
+    where
+        O: Serialize,
+    {
+        std::fs::write(path, bincode::encode(&self.ops)?)?;
+        Ok(())
+    }
+}
+
+struct Tracing<'a, O, E>(&'a mut Vec<O>, E);
+
+impl<O: Clone, E: SendEvent<Invoke<O>>> SendEvent<Invoke<O>> for Tracing<'_, O, E> {
+    fn send(&mut self, Invoke(op): Invoke<O>) -> anyhow::Result<()> {
+        self.0.push(op.clone());
+        self.1.send(Invoke(op))
+    }
+}
+
+impl<W: Workload> Workload for Trace<W::Op, W>
+where
+    W::Op: Clone,
+{
+    type Op = W::Op;
+    type Result = W::Result;
+
+    fn init(&mut self, sender: impl SendEvent<Invoke<Self::Op>>) -> anyhow::Result<()> {
+        self.inner.init(Tracing(&mut self.ops, sender))
+    }
+
+    fn on_result(
+        &mut self,
+        result: InvokeOk<Self::Result>,
+        sender: impl SendEvent<Invoke<Self::Op>>,
+    ) -> anyhow::Result<()> {
+        self.inner.on_result(result, Tracing(&mut self.ops, sender))
+    }
+}
+
+// the results are not checked, as a trace does not record them
+pub type Replay<R, O> = UncheckedIter<R, std::vec::IntoIter<O>>;
+
+impl<R, O: DeserializeOwned> UncheckedIter<R, std::vec::IntoIter<O>> {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let ops = bincode::decode::<Vec<O>>(&std::fs::read(path)?)?;
+        Ok(Self::new(ops))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use super::*;
+
+    #[test]
+    fn trace_replay() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut workload = Trace::new(UncheckedIter::<(), _>::new(
+            (0..100).map(move |_| rng.gen::<u64>()),
+        ));
+        let mut sender = None;
+        workload.init(&mut sender)?;
+        let mut ops = Vec::new();
+        while let Some(Invoke(op)) = sender.take() {
+            ops.push(op);
+            workload.on_result(InvokeOk(()), &mut sender)?
+        }
+        anyhow::ensure!(workload.ops == ops);
+
+        let path = std::env::temp_dir().join(format!("trace-{}", std::process::id()));
+        workload.save(&path)?;
+        let mut replay = Replay::<(), u64>::load(&path)?;
+        std::fs::remove_file(&path)?;
+        replay.init(&mut sender)?;
+        let mut replayed = Vec::new();
+        while let Some(Invoke(op)) = sender.take() {
+            replayed.push(op);
+            replay.on_result(InvokeOk(()), &mut sender)?
+        }
+        anyhow::ensure!(replay.done);
+        anyhow::ensure!(replayed == ops);
+        Ok(())
+    }
+}
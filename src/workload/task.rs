@@ -0,0 +1,40 @@
+use bytes::Bytes;
+use derive_where::derive_where;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::event::SendEvent;
+
+use super::{events::Executed, AsyncApp};
+
+// the handle for the state to submit ops to the `run` task, which executes them with an `AsyncApp`
+// and delivers the results as `Executed` events. the token is for the state to recall what the op
+// is for, e.g. the request to reply
+#[derive_where(Debug, Clone)]
+pub struct Offload<T>(UnboundedSender<(T, Bytes)>);
+
+impl<T> Offload<T> {
+    pub fn new(sender: UnboundedSender<(T, Bytes)>) -> Self {
+        Self(sender)
+    }
+
+    pub fn submit(&mut self, token: T, op: Bytes) -> anyhow::Result<()> {
+        self.0
+            .send((token, op))
+            .map_err(|_| anyhow::format_err!("unexpected send channel closed"))
+    }
+}
+
+// the ops are executed one at a time in the submitted order, since the app is a state machine and
+// executing concurrently would make the results depend on timing. the event loop keeps handling
+// other events in the meantime
+pub async fn run<T>(
+    mut app: impl AsyncApp,
+    receiver: &mut UnboundedReceiver<(T, Bytes)>,
+    mut sender: impl SendEvent<Executed<T>>,
+) -> anyhow::Result<()> {
+    while let Some((token, op)) = receiver.recv().await {
+        let result = app.execute(&op).await?;
+        sender.send(Executed(token, result))?
+    }
+    Ok(())
+}